@@ -0,0 +1,36 @@
+//! Build script shared by all packages which are linked into a Kernel
+//! Image.
+//!
+//! `cargo xtask` renders the linker scripts for the memory layout of
+//! the build configuration and points us to them, so that all packages
+//! can be built in a single cargo invocation with identical `RUSTFLAGS`.
+//! Plain cargo builds without xtask link without any linker script.
+
+use std::{env, path::PathBuf};
+
+fn main() {
+    println!("cargo:rerun-if-env-changed=ONYX_LINKER_SCRIPTS");
+    println!("cargo:rerun-if-env-changed=ONYX_LINKER_MAPS");
+
+    let pkg = env::var("CARGO_PKG_NAME").unwrap();
+
+    if let Some(scripts) = env::var_os("ONYX_LINKER_SCRIPTS") {
+        let script = PathBuf::from(scripts).join(format!("{pkg}.x"));
+        println!("cargo:rerun-if-changed={}", script.display());
+        println!("cargo:rustc-link-arg-bins=-T{}", script.display());
+    }
+
+    // The Kernel Loader hashes itself after relocating, so the words
+    // it relocates must hold their link-time values in the binary.
+    if pkg == "onyx-loader" {
+        println!("cargo:rustc-link-arg-bins=--apply-dynamic-relocs");
+    }
+
+    // Only set for regular builds, so that the map always belongs to
+    // the binary that ends up in the Kernel Image.
+    if let Some(maps) = env::var_os("ONYX_LINKER_MAPS") {
+        let profile = env::var("PROFILE").unwrap();
+        let map = PathBuf::from(maps).join(format!("{pkg}-{profile}.map"));
+        println!("cargo:rustc-link-arg-bins=-Map={}", map.display());
+    }
+}
@@ -8,13 +8,21 @@ PHDRS {
     dynamic PT_DYNAMIC;
 }
 
+/*
+ * This is a template which is rendered by the build system with the
+ * memory layout from the build config.
+ */
+
+/*
+ * The kernel is linked at address 0 and relocates itself to wherever it
+ * is loaded, so the region only bounds its size by the available RAM.
+ */
 MEMORY {
-    /* Definition for qemu-system-riscv64 virt machines. */
-    ram (rwx): ORIGIN = 0x80000000, LENGTH = 128M
+    ram (rwx): ORIGIN = 0, LENGTH = ${RAM_SIZE}
 }
 
 /* The page size used by the kernel. */
-PAGE_SIZE = 4K;
+PAGE_SIZE = ${PAGE_SIZE};
 
 SECTIONS {
     PROVIDE(__start__ = 0);
@@ -24,55 +32,55 @@ SECTIONS {
     .r0 : {
         KEEP(*(.r0 .r0.*))
         . = ALIGN(8);
-    } :text
+    } > ram :text
 
     .text : {
         *(.text .text.*)
         . = ALIGN(8);
-    } :text
+    } > ram :text
 
     .plt : {
         *(.plt .plt.*)
         . = ALIGN(8);
-    } :text
+    } > ram :text
 
     . = ALIGN(PAGE_SIZE);
     __text_end__ = .;
 
     __rodata_start__ = .;
 
-    .rodata : {
+    .rodata ALIGN(PAGE_SIZE) : {
         *(.rodata .rodata.*)
         . = ALIGN(8);
-    } :rodata
+    } > ram :rodata
 
     .data.rel.ro : {
         *(.data.rel.ro .data.rel.ro.*)
         . = ALIGN(8);
-    } :rodata
+    } > ram :rodata
 
-    .hash     : { *(.hash)             } :rodata
-    .gnu.hash : { *(.gnu.hash)         } :rodata
-    .dynsym   : { *(.dynsym .dynsym.*) } :rodata
-    .dynstr   : { *(.dynstr .dynstr.*) } :rodata
-    .rela.dyn : { *(.rela.*)           } :rodata
+    .hash     : { *(.hash)             } > ram :rodata
+    .gnu.hash : { *(.gnu.hash)         } > ram :rodata
+    .dynsym   : { *(.dynsym .dynsym.*) } > ram :rodata
+    .dynstr   : { *(.dynstr .dynstr.*) } > ram :rodata
+    .rela.dyn : { *(.rela.*)           } > ram :rodata
 
     .dynamic : {
         HIDDEN(__dynamic_start__ = .);
         *(.dynamic)
-    } :rodata :dynamic
+    } > ram :rodata :dynamic
 
     __got_start__ = .;
 
     .got : {
         *(.got)
         *(.igot)
-    } :rodata
+    } > ram :rodata
 
     .got.plt : {
         *(.got.plt)
         *(.igot.plt)
-    } :rodata
+    } > ram :rodata
 
     __got_end__ = .;
 
@@ -81,23 +89,28 @@ SECTIONS {
 
     __data_start__ = .;
 
-    .data ALIGN(8) : {
+    .data ALIGN(PAGE_SIZE) : {
         *(.data .data.*)
         *(.sdata .sdata.*)
-    } :data
+    } > ram :data
 
     . = ALIGN(PAGE_SIZE);
     __data_end__ = .;
 
     __bss_start__ = .;
 
-    .rela.dyn : { *(.rela.*) } :data
-
-    .bss ADDR(.rela.dyn) (NOLOAD) : {
+    .bss ALIGN(PAGE_SIZE) (NOLOAD) : {
         *(.bss .bss.*)
+        *(.sbss .sbss.*)
         *(COMMON)
         *(.dynbss)
-    } :data
+
+        /* Reserve stack memory for early boot. */
+        . = ALIGN(16);
+        __stack_bottom__ = .;
+        . += ${STACK_SIZE};
+        __stack_top__ = .;
+    } > ram :data
 
     . = ALIGN(PAGE_SIZE);
     __bss_end__ = .;
@@ -1,11 +1,24 @@
 OUTPUT_ARCH(riscv64imac)
 ENTRY(__onyx_loader_start)
 
+/*
+ * This is a template which is rendered by the build system with the
+ * memory layout from the build config.
+ */
+
 PHDRS {
     loader  PT_LOAD FLAGS(7);
     dynamic PT_DYNAMIC;
 }
 
+/*
+ * The loader is linked at address 0 and relocates itself to wherever it
+ * is loaded, so the region only bounds its size by the available RAM.
+ */
+MEMORY {
+    ram (rwx): ORIGIN = 0, LENGTH = ${RAM_SIZE}
+}
+
 SECTIONS {
     PROVIDE(__start__ = 0);
 
@@ -14,34 +27,38 @@ SECTIONS {
     .r0 : {
         KEEP(*(.r0 .r0.*))
         . = ALIGN(8);
-    } :loader
+    } > ram :loader
 
     .text : {
         *(.text .text.*)
         . = ALIGN(8);
-    } :loader
+    } > ram :loader
 
     .plt : {
         *(.plt .plt.*)
         . = ALIGN(8);
-    } :loader
+    } > ram :loader
 
     __rodata_start__ = .;
 
     .rodata : {
         *(.rodata .rodata.*)
-    } :loader
-
-    .hash     : { *(.hash)             } :loader
-    .gnu.hash : { *(.gnu.hash)         } :loader
-    .dynsym   : { *(.dynsym .dynsym.*) } :loader
-    .dynstr   : { *(.dynstr .dynstr.*) } :loader
-    .rela.dyn : { *(.rela.*)           } :loader
+    } > ram :loader
+
+    .hash     : { *(.hash)             } > ram :loader
+    .gnu.hash : { *(.gnu.hash)         } > ram :loader
+    .dynsym   : { *(.dynsym .dynsym.*) } > ram :loader
+    .dynstr   : { *(.dynstr .dynstr.*) } > ram :loader
+    .rela.dyn : {
+        HIDDEN(__rela_start__ = .);
+        *(.rela.*)
+        HIDDEN(__rela_end__ = .);
+    } > ram :loader
 
     .dynamic : {
         HIDDEN(__dynamic_start__ = .);
         *(.dynamic)
-    } :loader :dynamic
+    } > ram :loader :dynamic
 
     __rodata_end__ = .;
 
@@ -49,20 +66,21 @@ SECTIONS {
 
     .data ALIGN(8) : {
         *(.data .data.*)
+        *(.sdata .sdata.*)
         SORT(CONSTRUCTORS)
-    } :loader
+    } > ram :loader
 
     __got_start__ = .;
 
     .got : {
         *(.got)
         *(.igot)
-    } :loader
+    } > ram :loader
 
     .got.plt : {
         *(.got.plt)
         *(.igot.plt)
-    } :loader
+    } > ram :loader
 
     __got_end__ = .;
 
@@ -71,16 +89,17 @@ SECTIONS {
     .bss ALIGN(8) : {
         HIDDEN(__bss_start__ = .);
         *(.bss .bss.*)
+        *(.sbss .sbss.*)
         *(COMMON)
         *(.dynbss)
 
-        /* Reserve 4KiB of stack memory. */
-        . = ALIGN(8);
+        /* Reserve stack memory for image verification. */
+        . = ALIGN(16);
         __stack_bottom__ = .;
-        . += 0x1000;
+        . += ${STACK_SIZE};
         __stack_top__ = .;
         HIDDEN(__bss_end__ = .);
-    } :loader
+    } > ram :loader
 
     PROVIDE(__end__ = ABSOLUTE(.));
 
@@ -5,17 +5,55 @@ use xshell::{cmd, Shell};
 
 use crate::{cargo, config::Config, rustc};
 
-/// Builds a given cargo package in the source tree and returns the
-/// path to the produced ELF binary.
-pub fn build(pkg: &str, config: &Config, release: bool, verbose: bool) -> Result<PathBuf> {
-    let output = cargo::subcommand("build", pkg, config, release, verbose)?;
-    let artifacts = cargo::parse_print_output(&output)?;
-
-    // Try to extract the produced ELF binary for successful builds.
-    artifacts
+/// Builds a given cargo package in the source tree with additional
+/// `features` and returns the path to the produced ELF binary.
+pub fn build(
+    pkg: &str,
+    config: &Config,
+    features: &[&str],
+    release: bool,
+    verbose: bool,
+) -> Result<PathBuf> {
+    let mut elfs = build_all(&[pkg], config, features, release, verbose)?;
+    Ok(elfs.remove(0))
+}
+
+/// Builds the given cargo packages in the source tree at once with
+/// additional `features` in `package/feature` form, and returns the
+/// paths to the produced ELF binaries in the order of `packages`.
+pub fn build_all(
+    packages: &[&str],
+    config: &Config,
+    features: &[&str],
+    release: bool,
+    verbose: bool,
+) -> Result<Vec<PathBuf>> {
+    let executables = cargo::subcommand(&["build"], packages, config, features, release, verbose)?;
+
+    // Try to extract the produced ELF binary of every package.
+    packages
+        .iter()
+        .map(|pkg| {
+            executables
+                .iter()
+                .find(|exe| exe.pkg == *pkg)
+                .map(|exe| exe.path.clone())
+                .ok_or_else(|| anyhow!("failed to extract build artifact for package `{}`", pkg))
+        })
+        .collect()
+}
+
+/// Builds the in-kernel test harness of a given cargo package in the
+/// source tree and returns the path to the produced ELF binary.
+pub fn build_tests(pkg: &str, config: &Config, release: bool, verbose: bool) -> Result<PathBuf> {
+    let executables =
+        cargo::subcommand(&["test", "--no-run"], &[pkg], config, &[], release, verbose)?;
+
+    executables
         .into_iter()
-        .next()
-        .ok_or_else(|| anyhow!("failed to extract build artifact for package `{}`", pkg))
+        .find(|exe| exe.pkg == pkg)
+        .map(|exe| exe.path)
+        .ok_or_else(|| anyhow!("failed to extract test harness for package `{}`", pkg))
 }
 
 /// Converts a given ELF file into a raw binary using objcopy and
@@ -0,0 +1,66 @@
+use std::{
+    env,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use anyhow::Context;
+
+use onyx_image::BuildId;
+use xshell::{cmd, Shell};
+
+use crate::rustc;
+
+/// Collects the [`BuildId`] for a build of the current source tree.
+///
+/// Builds outside of a git checkout leave the commit hash empty. The
+/// build time is only recorded when `timestamps` are enabled.
+pub fn collect(sh: &Shell, release: bool, timestamps: bool) -> anyhow::Result<BuildId> {
+    let _cwd = sh.push_dir(rustc::project_root());
+    let mut build_id = BuildId::default();
+
+    let commit = cmd!(sh, "git rev-parse HEAD")
+        .quiet()
+        .ignore_stderr()
+        .read();
+    if let Ok(commit) = commit {
+        let commit = commit.trim().as_bytes();
+        let len = commit.len().min(build_id.commit.len());
+        build_id.commit[..len].copy_from_slice(&commit[..len]);
+
+        let status = cmd!(sh, "git status --porcelain").quiet().read()?;
+        if !status.trim().is_empty() {
+            build_id.flags |= BuildId::FLAG_DIRTY;
+        }
+    }
+
+    let profile = if release {
+        build_id.flags |= BuildId::FLAG_RELEASE;
+        "release"
+    } else {
+        "debug"
+    };
+    build_id.profile[..profile.len()].copy_from_slice(profile.as_bytes());
+
+    build_id.timestamp = timestamp(timestamps)?;
+
+    Ok(build_id)
+}
+
+/// Gets the timestamp to embed into build artifacts.
+///
+/// This is zero unless `enabled`, so that images are reproducible by
+/// default. `SOURCE_DATE_EPOCH` takes precedence over the current time
+/// for reproducible builds with timestamps.
+pub fn timestamp(enabled: bool) -> anyhow::Result<u64> {
+    if !enabled {
+        return Ok(0);
+    }
+
+    match env::var("SOURCE_DATE_EPOCH") {
+        Ok(epoch) => epoch
+            .trim()
+            .parse()
+            .with_context(|| format!("invalid SOURCE_DATE_EPOCH `{epoch}`")),
+        Err(_) => Ok(SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs()),
+    }
+}
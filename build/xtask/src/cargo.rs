@@ -1,112 +1,423 @@
 use std::{
-    io::{self, BufReader, Write},
-    path::PathBuf,
-    process::{Command, Stdio},
+    env, fmt, fs,
+    io::{self, BufRead, BufReader, Write},
+    path::{Path, PathBuf},
+    process::{Command, ExitStatus, Stdio},
+    sync::OnceLock,
 };
 
-use cargo_metadata::Message;
+use anyhow::anyhow;
+use cargo_metadata::{
+    camino::Utf8PathBuf,
+    diagnostic::{Diagnostic, DiagnosticLevel},
+    semver::Version,
+    Message, MetadataCommand, Package,
+};
+use clap::ValueEnum;
+use xshell::Shell;
+
+use crate::{config::Config, image, linker, rustc};
+
+/// The format in which messages from cargo are reported.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, ValueEnum)]
+pub enum MessageFormat {
+    /// Rendered diagnostics and a summary of the build progress.
+    #[default]
+    Human,
+    /// cargo's JSON messages, passed through unchanged on stdout.
+    Json,
+}
+
+static MESSAGE_FORMAT: OnceLock<MessageFormat> = OnceLock::new();
+
+/// Selects the format of messages from all following cargo invocations.
+pub fn set_message_format(format: MessageFormat) {
+    MESSAGE_FORMAT.set(format).ok();
+}
+
+/// Gets the format selected through [`set_message_format`].
+pub fn message_format() -> MessageFormat {
+    MESSAGE_FORMAT.get().copied().unwrap_or_default()
+}
+
+/// A failed cargo invocation, summarizing the errors reported by the
+/// compiler.
+#[derive(Debug)]
+pub struct CargoError {
+    subcommand: String,
+    packages: Vec<String>,
+    status: ExitStatus,
+    /// The rendered headers and locations of all compiler errors.
+    errors: Vec<String>,
+    warnings: usize,
+}
+
+impl CargoError {
+    /// Gets the exit code cargo terminated with, if any.
+    pub fn code(&self) -> Option<i32> {
+        self.status.code()
+    }
+}
+
+impl fmt::Display for CargoError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "`cargo {}` failed for `{}`",
+            self.subcommand,
+            self.packages.join("`, `")
+        )?;
+        if !self.errors.is_empty() {
+            write!(f, " with {}", plural(self.errors.len(), "error"))?;
+            if self.warnings > 0 {
+                write!(f, " and {}", plural(self.warnings, "warning"))?;
+            }
+        }
+        write!(f, " ({})", self.status)?;
+
+        for error in &self.errors {
+            write!(f, "\n{error}")?;
+        }
+        Ok(())
+    }
+}
 
-use crate::{config::Config, rustc};
+impl std::error::Error for CargoError {}
+
+fn plural(count: usize, noun: &str) -> String {
+    match count {
+        1 => format!("1 {noun}"),
+        _ => format!("{count} {noun}s"),
+    }
+}
 
-/// Runs a `cargo` subcommand based on the given build configuration
-/// and obtains its stdout.
+/// Runs a `cargo` subcommand for one or more packages based on the
+/// given build configuration and returns the executables it produced.
+///
+/// `subcommand` is the name of the subcommand followed by any of its
+/// specific arguments. `extra_features` are enabled in addition to the
+/// board and are given in `package/feature` form.
+///
+/// All packages are built in a single cargo invocation, sharing the
+/// artifacts of dependencies and the standard library. Per-package
+/// linker arguments are applied by the build script in `build/link.rs`.
+///
+/// cargo's messages are reported as they arrive in the selected
+/// [`MessageFormat`]. When cargo fails, a [`CargoError`] carries its
+/// exit status and a summary of the compiler errors.
 pub fn subcommand(
-    subcommand: &str,
-    pkg: &str,
+    subcommand: &[&str],
+    packages: &[&str],
     config: &Config,
+    extra_features: &[&str],
     release: bool,
     verbose: bool,
-) -> anyhow::Result<Vec<u8>> {
+) -> anyhow::Result<Vec<Executable>> {
     // Prepare cargo flags to pass to the command.
     let release_arg = if release { &["--release"][..] } else { &[] };
     let verbose_arg = if verbose { &["--verbose"][..] } else { &[] };
+    let package_args = packages.iter().flat_map(|pkg| ["-p", pkg]);
 
-    // Build the path to the linker script to utilize.
-    let mut linker_script = PathBuf::new();
-    linker_script.push("build");
-    linker_script.push("linker-scripts");
-    linker_script.push(if pkg == "onyx" {
-        &config.kernel.linker_script
-    } else {
-        &config.loader.linker_script
-    });
+    // Enable the board feature of every package with board-specific
+    // code alongside any requested ones.
+    let workspace = workspace_packages()?;
+    let mut features: Vec<_> = packages
+        .iter()
+        .filter(|pkg| {
+            workspace
+                .iter()
+                .any(|p| p.name == **pkg && p.dependencies.iter().any(|d| d.name == "onyx-board"))
+        })
+        .map(|pkg| format!("{pkg}/{}", config.board))
+        .collect();
+    features.extend(extra_features.iter().map(|f| f.to_string()));
+    let features = features.join(",");
+
+    // Generate the linker scripts for the memory layout of the board.
+    for pkg in packages {
+        linker::render(pkg, config)?;
+    }
 
-    // Run the cargo command with all relevant build options set.
-    // XXX: Can't use xshell because they don't give stdout on command error.
-    let output = Command::new("cargo")
-        .arg(subcommand)
+    // These must be the same for all packages, so that they can share
+    // the artifacts of their dependencies.
+    let mut rustflags = "-C relocation-model=pic -C link-arg=--pie".to_string();
+    rustflags.push_str(&remap_path_prefixes()?);
+
+    // The public key for verifying image signatures is embedded into
+    // the Kernel Loader at compile time.
+    let public_key = match &config.image.signing_key {
+        Some(key) => {
+            let key = image::read_signing_key(rustc::project_root().join(key))?;
+            key.verifying_key()
+                .as_bytes()
+                .iter()
+                .map(|b| format!("{b:02x}"))
+                .collect()
+        }
+        None => String::new(),
+    };
+
+    let format = message_format();
+    let format_arg = match format {
+        MessageFormat::Human => "--message-format=json-diagnostic-rendered-ansi",
+        MessageFormat::Json => "--message-format=json",
+    };
+
+    // Run the cargo command with all relevant build options set and
+    // process its messages while it is running.
+    let mut cargo = Command::new("cargo");
+    cargo
+        .args(subcommand)
         .args(release_arg)
         .args(verbose_arg)
-        .args(["-p", pkg])
+        .args(package_args)
         .arg("--target")
         .arg(&config.target)
-        .args(["--features", &config.board])
+        .args(["--features", &features])
         .args(["-Z", "build-std=core,alloc,compiler_builtins"])
         .args(["-Z", "build-std-features=compiler-builtins-mem"])
-        .arg("--message-format=json-diagnostic-rendered-ansi")
-        .env(
-            "RUSTFLAGS",
-            format!(
-                "-C relocation-model=pic -C link-arg=--pie -C link-arg=-T{}",
-                linker_script.display()
-            ),
-        )
+        .arg(format_arg)
+        .env("RUSTFLAGS", rustflags)
+        .env("ONYX_PUBLIC_KEY", public_key)
+        .env("ONYX_MEMORY_BASE", config.memory.base.to_string())
+        .env("ONYX_MEMORY_SIZE", config.memory.size.to_string())
+        .env("ONYX_LINKER_SCRIPTS", linker::scripts_dir())
         .current_dir(rustc::project_root())
-        .stdout(Stdio::piped())
-        .spawn()?
-        .wait_with_output()?;
+        .stdout(Stdio::piped());
 
-    // Return the stdout buffer we extracted.
-    Ok(output.stdout)
+    // Only regular builds produce a linker map, so that the map always
+    // belongs to the binary that ends up in the Kernel Image.
+    if subcommand[0] == "build" {
+        fs::create_dir_all(linker::maps_dir())?;
+        cargo.env("ONYX_LINKER_MAPS", linker::maps_dir());
+    } else {
+        cargo.env_remove("ONYX_LINKER_MAPS");
+    }
+    let mut cargo = cargo.spawn()?;
+
+    let mut output = Output::default();
+    let stdout = BufReader::new(cargo.stdout.take().unwrap());
+    for line in stdout.lines() {
+        output.process(&line?, format)?;
+    }
+
+    let status = cargo.wait()?;
+    if !status.success() {
+        return Err(CargoError {
+            subcommand: subcommand[0].to_string(),
+            packages: packages.iter().map(|p| p.to_string()).collect(),
+            status,
+            errors: output.errors,
+            warnings: output.warnings,
+        }
+        .into());
+    }
+
+    // Attribute every executable to its package by its manifest.
+    output
+        .artifacts
+        .into_iter()
+        .map(|(manifest, path)| {
+            let pkg = workspace
+                .iter()
+                .find(|p| p.manifest_path == manifest)
+                .ok_or_else(|| anyhow!("cargo built {} for an unknown package", path.display()))?;
+            Ok(Executable {
+                pkg: pkg.name.clone(),
+                path,
+            })
+        })
+        .collect()
 }
 
-/// Parses `cargo` output and returns a list of build artifacts,
-/// while printing every message to stdout.
-///
-/// This should be used with [`subcommand`] so that diagnostics
-/// are not lost while driving cargo through this build script.
-pub fn parse_print_output(output: &[u8]) -> anyhow::Result<Vec<PathBuf>> {
-    let mut artifacts = Vec::new();
+/// An executable produced by a cargo invocation.
+pub struct Executable {
+    /// The name of the package the executable belongs to.
+    pub pkg: String,
+    pub path: PathBuf,
+}
 
-    let stdout = io::stdout();
-    let mut stdout = stdout.lock();
+/// The results collected from the messages of a cargo invocation.
+#[derive(Default)]
+struct Output {
+    /// The executables produced, along with the manifests of their
+    /// packages.
+    artifacts: Vec<(Utf8PathBuf, PathBuf)>,
+    errors: Vec<String>,
+    warnings: usize,
+}
 
-    let reader = BufReader::new(output);
-    for message in Message::parse_stream(reader) {
-        match message.unwrap() {
-            Message::CompilerMessage(msg) => {
-                if let Some(msg) = &msg.message.rendered {
-                    stdout.write_all(msg.as_bytes())?;
-                }
-            }
-            Message::CompilerArtifact(artifact) => {
-                let message = format!("Compiled {}\n", &artifact.package_id.repr);
-                stdout.write_all(message.as_bytes())?;
+impl Output {
+    /// Records a line of cargo output and reports it in the given
+    /// format.
+    ///
+    /// In [`MessageFormat::Json`], only cargo's JSON messages go to
+    /// stdout so that consumers can parse it line by line.
+    fn process(&mut self, line: &str, format: MessageFormat) -> anyhow::Result<()> {
+        let message = serde_json::from_str(line).unwrap_or_else(|_| Message::TextLine(line.into()));
 
-                if let Some(exe) = artifact.executable {
-                    artifacts.push(PathBuf::from(exe));
-                }
-            }
-            Message::BuildScriptExecuted(build) => {
-                let message = format!("Build script executed ({:?})\n", build.out_dir);
-                stdout.write_all(message.as_bytes())?;
-            }
-            Message::BuildFinished(res) if res.success => {
-                stdout.write_all(b"Build completed successfully!\n")?;
-            }
-            Message::BuildFinished(_) => {
-                stdout.write_all(b"Errors occured during build!\n")?;
+        if let Message::CompilerMessage(msg) = &message {
+            self.record(&msg.message);
+        }
+        if let Message::CompilerArtifact(artifact) = &message {
+            if let Some(exe) = &artifact.executable {
+                let manifest = artifact.manifest_path.clone();
+                self.artifacts.push((manifest, exe.into()));
             }
-            Message::TextLine(s) => {
-                // Unknown message content.
-                stdout.write_all(s.as_bytes())?;
-                stdout.write_all(b"\n")?;
+        }
+
+        match (format, &message) {
+            (MessageFormat::Json, Message::TextLine(line)) => eprintln!("{line}"),
+            (MessageFormat::Json, _) => println!("{line}"),
+            (MessageFormat::Human, message) => print_message(message)?,
+        }
+
+        Ok(())
+    }
+
+    fn record(&mut self, diagnostic: &Diagnostic) {
+        match diagnostic.level {
+            // The compiler's closing "aborting due to" error only
+            // repeats the error count.
+            DiagnosticLevel::Error | DiagnosticLevel::Ice
+                if !diagnostic.message.starts_with("aborting due to") =>
+            {
+                let header = match &diagnostic.code {
+                    Some(code) => format!("error[{}]: {}", code.code, diagnostic.message),
+                    None => format!("error: {}", diagnostic.message),
+                };
+                let location = diagnostic
+                    .spans
+                    .iter()
+                    .find(|s| s.is_primary)
+                    .map(|s| {
+                        format!(
+                            "\n   --> {}:{}:{}",
+                            s.file_name, s.line_start, s.column_start
+                        )
+                    })
+                    .unwrap_or_default();
+                self.errors.push(format!("  {header}{location}"));
             }
+            DiagnosticLevel::Warning => self.warnings += 1,
+            _ => {}
+        }
+    }
+}
+
+/// Prints a cargo message in human-readable form to stdout.
+fn print_message(message: &Message) -> io::Result<()> {
+    let stdout = io::stdout();
+    let mut stdout = stdout.lock();
 
-            // Unhandled message types.
-            _ => (),
+    match message {
+        Message::CompilerMessage(msg) => {
+            if let Some(msg) = &msg.message.rendered {
+                stdout.write_all(msg.as_bytes())?;
+            }
+        }
+        Message::CompilerArtifact(artifact) => {
+            let message = format!("Compiled {}\n", &artifact.package_id.repr);
+            stdout.write_all(message.as_bytes())?;
+        }
+        Message::BuildScriptExecuted(build) => {
+            let message = format!("Build script executed ({:?})\n", build.out_dir);
+            stdout.write_all(message.as_bytes())?;
+        }
+        Message::BuildFinished(res) if res.success => {
+            stdout.write_all(b"Build completed successfully!\n")?;
+        }
+        Message::BuildFinished(_) => {
+            stdout.write_all(b"Errors occured during build!\n")?;
+        }
+        Message::TextLine(s) => {
+            // Unknown message content.
+            stdout.write_all(s.as_bytes())?;
+            stdout.write_all(b"\n")?;
         }
+
+        // Unhandled message types.
+        _ => (),
+    }
+
+    Ok(())
+}
+
+/// Gets the path prefixes of the build machine to remap in panic
+/// messages and debug info, so that builds from different checkouts
+/// are bit-for-bit identical.
+///
+/// Prefixes are ordered such that the last matching one applies.
+pub fn path_remaps() -> anyhow::Result<Vec<(PathBuf, String)>> {
+    let mut remaps = vec![(rustc::project_root(), "/onyx".to_string())];
+
+    // With `build-std`, the standard library is compiled from the rust-src
+    // component. Match the paths of the prebuilt one for familiarity.
+    let sysroot = rustc::sysroot(&Shell::new()?)?;
+    let rust_src = match rustc_version::version_meta()?.commit_hash {
+        Some(hash) => format!("/rustc/{hash}"),
+        None => "/rustc".to_string(),
+    };
+    remaps.push((sysroot.join("lib/rustlib/src/rust"), rust_src));
+
+    let cargo_home = env::var_os("CARGO_HOME")
+        .map(PathBuf::from)
+        .or_else(|| env::var_os("HOME").map(|home| Path::new(&home).join(".cargo")));
+    if let Some(cargo_home) = cargo_home {
+        remaps.push((cargo_home, "/cargo".to_string()));
+    }
+
+    // Out-of-tree target directories come last to take precedence.
+    if let Some(target_dir) = env::var_os("CARGO_TARGET_DIR") {
+        remaps.push((target_dir.into(), "/onyx/target".to_string()));
+    }
+
+    Ok(remaps)
+}
+
+fn remap_path_prefixes() -> anyhow::Result<String> {
+    Ok(path_remaps()?
+        .into_iter()
+        .map(|(from, to)| format!(" --remap-path-prefix={}={to}", from.display()))
+        .collect())
+}
+
+/// Gets the version of a cargo package in the workspace.
+pub fn package_version(pkg: &str) -> anyhow::Result<Version> {
+    workspace_package(pkg).map(|p| p.version)
+}
+
+/// Gets the names of all features of a cargo package in the workspace,
+/// except for `default`.
+pub fn package_features(pkg: &str) -> anyhow::Result<Vec<String>> {
+    let package = workspace_package(pkg)?;
+    Ok(package
+        .features
+        .into_keys()
+        .filter(|f| f != "default")
+        .collect())
+}
+
+fn workspace_package(pkg: &str) -> anyhow::Result<Package> {
+    workspace_packages()?
+        .iter()
+        .find(|p| p.name == pkg)
+        .cloned()
+        .ok_or_else(|| anyhow!("package `{pkg}` not found in workspace"))
+}
+
+static WORKSPACE_PACKAGES: OnceLock<Vec<Package>> = OnceLock::new();
+
+/// Gets the packages of the workspace, which are only queried from
+/// `cargo metadata` once per invocation.
+fn workspace_packages() -> anyhow::Result<&'static [Package]> {
+    if let Some(packages) = WORKSPACE_PACKAGES.get() {
+        return Ok(packages);
     }
 
-    Ok(artifacts)
+    let metadata = MetadataCommand::new()
+        .manifest_path(rustc::project_root().join("Cargo.toml"))
+        .no_deps()
+        .exec()?;
+    Ok(WORKSPACE_PACKAGES.get_or_init(|| metadata.packages))
 }
@@ -1,15 +1,28 @@
-use std::path::{Path, PathBuf};
+use std::{
+    fmt::Write,
+    fs,
+    path::{Path, PathBuf},
+};
 
+use anyhow::{anyhow, bail, Context};
 use binrw::Endian;
-use serde::{de::Deserializer, Deserialize};
+use serde::{de::Deserializer, ser::Serializer, Deserialize, Serialize};
+use toml::{Table, Value};
+
+use crate::{
+    cargo,
+    format::Format,
+    image::{Compression, KernelMeta, PAGE_SIZE},
+    rustc,
+};
 
 /// The build configuration for an Onyx distribution.
 ///
 /// This defines build targets, the individual pieces of software
 /// to build, and some customization options related to the inner
 /// workings of the Kernel and the resulting Kernel Image.
-#[derive(Debug, Deserialize)]
-#[serde(rename_all = "kebab-case")]
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
 pub struct Config {
     /// The path to the target description file in JSON format.
     ///
@@ -19,7 +32,11 @@ pub struct Config {
     /// The endianness used by the target architecture.
     ///
     /// Defaults to little endian.
-    #[serde(deserialize_with = "deserialize_endian", default = "little_endian")]
+    #[serde(
+        deserialize_with = "deserialize_endian",
+        serialize_with = "serialize_endian",
+        default = "little_endian"
+    )]
     pub endian: Endian,
     /// The board the kernel is building for, if any.
     ///
@@ -27,12 +44,16 @@ pub struct Config {
     /// to conditionally compile board-specific code.
     ///
     /// This is useful for specialized targets and keeps Onyx
-    /// modular for porting.
+    /// modular for porting. The supported boards are defined by
+    /// the `onyx-board` crate, currently `generic`, `qemu-virt`
+    /// and `qemu-sifive-u`.
     ///
     /// Defaults to the `"generic"` board which does not pull in
     /// any board-specific peripheral code.
     #[serde(default = "generic_board")]
     pub board: String,
+    /// The physical memory layout of the board.
+    pub memory: Memory,
     /// Image-related configuration.
     pub image: Image,
     /// Kernel-specific build configuration.
@@ -44,52 +65,442 @@ pub struct Config {
 }
 
 impl Config {
-    /// Reads a configuration file in TOML format from the given path.
-    pub fn read<P: AsRef<Path>>(path: P) -> anyhow::Result<Self> {
-        let config = std::fs::read_to_string(path)?;
-        toml::from_str(&config).map_err(Into::into)
+    /// Gets the path of the configuration file with the given name.
+    ///
+    /// Names are resolved relative to `build/config`, with the `.toml`
+    /// extension being optional.
+    pub fn path<P: AsRef<Path>>(name: P) -> PathBuf {
+        let mut path = rustc::project_root();
+        path.push("build");
+        path.push("config");
+        path.push(name);
+        path.set_extension("toml");
+        path
+    }
+
+    /// Reads a configuration file in TOML format from the given path
+    /// and validates it.
+    ///
+    /// A file may name another configuration in its `extends` key to
+    /// inherit all values it does not set itself. Tables are merged
+    /// key by key, whereas all other values are replaced entirely.
+    ///
+    /// `overrides` are applied last, in the form of `key=value` with
+    /// a dotted key and a TOML value. Values which are not valid TOML
+    /// are taken as strings, so `image.compression=lz4` works.
+    ///
+    /// Unknown keys are rejected, with a hint at the replacement of
+    /// keys which were renamed.
+    ///
+    /// All problems found by [`Config::validate`] are reported at once,
+    /// each along with the location of the offending key.
+    pub fn read<P: AsRef<Path>>(path: P, overrides: &[String]) -> anyhow::Result<Self> {
+        let path = path.as_ref();
+        let layers = read_layers(path)?;
+
+        // Merge the layers from the base configuration upwards.
+        let mut table = Table::new();
+        for layer in layers.iter().rev() {
+            merge(&mut table, layer.table.clone());
+        }
+        let mut overridden = Vec::with_capacity(overrides.len());
+        for entry in overrides {
+            overridden.push(apply_override(&mut table, entry)?);
+        }
+
+        let location = |key: &str| {
+            if overridden.iter().any(|k| k == key) {
+                format!("--set {key}")
+            } else {
+                locate_in(&layers, key)
+            }
+        };
+
+        // Renamed keys would otherwise be rejected as unknown without
+        // a hint at their replacement.
+        for (old, new) in RENAMED_KEYS {
+            if lookup(&table, old).is_some() {
+                bail!(
+                    "invalid build config {}: `{old}` was replaced by `{new}`",
+                    location(old)
+                );
+            }
+        }
+
+        // Deserializing the merged table directly would lose track of
+        // where its values are set, so it takes a detour through TOML
+        // source to learn the key of a value which fails to deserialize.
+        let source = toml::to_string(&table)?;
+        let config: Self = toml::from_str(&source).map_err(|e| {
+            // Errors spanning the whole source concern the root table.
+            let span = e.span().filter(|span| span.len() < source.len());
+            let key = span.and_then(|span| key_at(&source, span.start));
+            // Missing fields are reported on their table, which for the
+            // root table means its first line.
+            let key = key.filter(|key| {
+                !e.message().starts_with("missing field")
+                    || matches!(table.get(key), Some(Value::Table(_)))
+            });
+            let location = match key {
+                Some(key) => location(&key),
+                None => path.display().to_string(),
+            };
+            anyhow!(
+                "invalid build config {location}: {}",
+                e.message().trim_end()
+            )
+        })?;
+
+        let problems = config.validate()?;
+        if !problems.is_empty() {
+            let mut report = format!("invalid build config {}:", path.display());
+            for problem in &problems {
+                write!(
+                    report,
+                    "\n  {}: `{}` {}",
+                    location(problem.key),
+                    problem.key,
+                    problem.message
+                )?;
+            }
+            bail!(report);
+        }
+
+        Ok(config)
+    }
+
+    /// Checks the values of the configuration for problems which would
+    /// otherwise only surface as confusing cargo or QEMU failures.
+    pub fn validate(&self) -> anyhow::Result<Vec<Problem>> {
+        let mut problems = Vec::new();
+        let mut problem = |key, message: String| problems.push(Problem { key, message });
+        let root = rustc::project_root();
+
+        // Targets are either built into rustc or JSON specifications.
+        if self.target.extension().map_or(false, |ext| ext == "json") {
+            if !root.join(&self.target).is_file() {
+                problem(
+                    "target",
+                    format!(
+                        "names a target specification {} which does not exist",
+                        self.target.display()
+                    ),
+                );
+            }
+        } else if let Some(targets) = rustc::target_list() {
+            if !targets.iter().any(|t| Path::new(t) == self.target) {
+                problem(
+                    "target",
+                    format!("names an unknown target `{}`", self.target.display()),
+                );
+            }
+        }
+
+        // Boards are selected through the features of `onyx-board`.
+        let boards = cargo::package_features("onyx-board")?;
+        if !boards.contains(&self.board) {
+            problem(
+                "board",
+                format!(
+                    "names an unknown board `{}`; expected one of {}",
+                    self.board,
+                    boards.join(", ")
+                ),
+            );
+        }
+
+        let page_size = self.memory.page_size;
+        if !page_size.is_power_of_two() || page_size < PAGE_SIZE {
+            problem(
+                "memory.page-size",
+                format!("is {page_size:#x}, but must be a power of two of at least {PAGE_SIZE:#x}"),
+            );
+        }
+        // Avoid follow-up errors by checking alignment against our own
+        // page size when the configured one is invalid.
+        let alignment = if page_size.is_power_of_two() && page_size >= PAGE_SIZE {
+            page_size
+        } else {
+            PAGE_SIZE
+        };
+        let aligned = |value: usize| value % alignment == 0;
+        if !aligned(self.memory.base) {
+            problem(
+                "memory.base",
+                format!(
+                    "is {:#x}, but must be aligned to the page size",
+                    self.memory.base
+                ),
+            );
+        }
+        if self.memory.size == 0 || !aligned(self.memory.size) {
+            problem(
+                "memory.size",
+                format!(
+                    "is {:#x}, but must be a non-zero multiple of the page size",
+                    self.memory.size
+                ),
+            );
+        }
+
+        let ram = self.memory.base..self.memory.base.saturating_add(self.memory.size);
+        if !aligned(self.qemu.address) || !ram.contains(&self.qemu.address) {
+            problem(
+                "qemu.address",
+                format!(
+                    "is {:#x}, but must be page-aligned and within RAM at {:#x}..{:#x}",
+                    self.qemu.address, ram.start, ram.end
+                ),
+            );
+        }
+        if let Some(address) = self.image.load_address.filter(|&a| !aligned(a)) {
+            problem(
+                "image.load-address",
+                format!("is {address:#x}, but must be aligned to the page size"),
+            );
+        }
+
+        for (key, script) in [
+            ("kernel.linker-script", &self.kernel.linker_script),
+            ("loader.linker-script", &self.loader.linker_script),
+        ] {
+            let path = root.join("build").join("linker-scripts").join(script);
+            if !path.is_file() {
+                problem(
+                    key,
+                    format!("names {} which does not exist", path.display()),
+                );
+            }
+        }
+        for (key, size) in [
+            ("kernel.stack-size", self.kernel.stack_size),
+            ("loader.stack-size", self.loader.stack_size),
+        ] {
+            if size == 0 || size % 16 != 0 {
+                problem(
+                    key,
+                    format!("is {size:#x}, but must be a non-zero multiple of 16"),
+                );
+            }
+        }
+
+        let paths = self.image.kips.iter().map(|kip| ("image.kips", kip));
+        let paths = paths
+            .chain(
+                self.image
+                    .signing_key
+                    .iter()
+                    .map(|key| ("image.signing-key", key)),
+            )
+            .chain(self.image.dtb.iter().map(|dtb| ("image.dtb", dtb)));
+        for (key, path) in paths {
+            if !root.join(path).is_file() {
+                problem(
+                    key,
+                    format!("names {} which does not exist", path.display()),
+                );
+            }
+        }
+
+        // Features of the image need a KernelMeta revision which can
+        // reference them.
+        let revision = self.image.revision;
+        if KernelMeta::header_size_of(revision).is_none() {
+            problem(
+                "image.revision",
+                format!("is {revision}, but must be in 1..={}", KernelMeta::REVISION),
+            );
+        }
+        let features = [
+            ("image.checksum", self.image.checksum, 2),
+            ("image.signing-key", self.image.signing_key.is_some(), 2),
+            ("image.dtb", self.image.dtb.is_some(), 4),
+            ("kernel.cmdline", self.kernel.cmdline.is_some(), 5),
+        ];
+        for (key, enabled, required) in features {
+            if enabled && revision < required {
+                problem(
+                    key,
+                    format!("requires KernelMeta revision {required}, but `image.revision` is {revision}"),
+                );
+            }
+        }
+        if self
+            .kernel
+            .cmdline
+            .as_deref()
+            .map_or(false, |c| c.contains('\0'))
+        {
+            problem(
+                "kernel.cmdline",
+                "must not contain NUL characters".to_string(),
+            );
+        }
+
+        Ok(problems)
     }
 }
 
+/// A problem with a value of a [`Config`].
+#[derive(Debug)]
+pub struct Problem {
+    /// The dotted path to the offending key, as written in TOML.
+    pub key: &'static str,
+    /// A description of the problem.
+    pub message: String,
+}
+
+/// The physical memory layout of the board, used for generating the
+/// linker scripts.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+pub struct Memory {
+    /// The physical base address of RAM.
+    ///
+    /// Boards with a statically known memory map describe RAM here.
+    pub base: usize,
+    /// The size of RAM in bytes.
+    ///
+    /// QEMU is configured with this amount of memory, and boards with
+    /// a statically known memory map describe this much RAM.
+    pub size: usize,
+    /// The page size used by the kernel and for aligning the
+    /// components of the Kernel Image.
+    ///
+    /// Must be a power of two of at least 4KiB.
+    ///
+    /// Defaults to 4KiB.
+    #[serde(default = "default_page_size")]
+    pub page_size: usize,
+}
+
 /// Build configuration for the final Kernel Image blob.
-#[derive(Debug, Deserialize)]
-#[serde(rename_all = "kebab-case")]
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
 pub struct Image {
-    /// Whether the resulting image should be compressed.
+    /// The compression format for the resulting image.
+    ///
+    /// One of `none`, `gzip`, `lz4` or `zstd`. Compressed images
+    /// are prefixed by a decompression stub, so they remain
+    /// directly bootable.
+    ///
+    /// Defaults to `none`.
+    #[serde(default)]
+    pub compression: Compression,
+    /// Paths to KIP1 binaries to pack as Kernel Initial Processes.
+    ///
+    /// Paths are expected to be absolute or relative to the
+    /// project root.
+    ///
+    /// Defaults to no initial processes.
+    #[serde(default)]
+    pub kips: Vec<PathBuf>,
+    /// Whether to append a trailer with a SHA-256 digest of the
+    /// image, which the Kernel Loader verifies before booting.
+    ///
+    /// Defaults to `false`.
+    #[serde(default)]
+    pub checksum: bool,
+    /// Path to an Ed25519 key for signing the image digest.
+    ///
+    /// The file must contain the raw 32-byte secret key. Setting
+    /// this implies `checksum` and embeds the public key into the
+    /// Kernel Loader, which then refuses to boot unsigned images.
+    ///
+    /// Paths are expected to be absolute or relative to the
+    /// project root.
+    pub signing_key: Option<PathBuf>,
+    /// Additional formats to wrap the image in for bootloaders
+    /// other than QEMU's generic loader device.
+    ///
+    /// Any of `elf`, `uimage` and `fit`. The raw image is always
+    /// produced alongside.
+    ///
+    /// Defaults to no additional formats.
+    #[serde(default)]
+    pub formats: Vec<Format>,
+    /// The `KernelMeta` format revision to emit.
+    ///
+    /// Older revisions remain bootable by older Kernel Loaders, but
+    /// cannot use features added since. Revision 1 does not support
+    /// `checksum` and `signing-key`, revisions before 4 do not
+    /// support `dtb`, and revisions before 5 do not support a
+    /// kernel `cmdline`.
+    ///
+    /// Defaults to the latest revision.
+    #[serde(default = "latest_revision")]
+    pub revision: u32,
+    /// Path to a device tree to embed into the image.
+    ///
+    /// Either a compiled `.dtb` blob or a `.dts` source, which is
+    /// compiled with the `dtc` found in `PATH`. The Kernel Loader
+    /// boots with this tree only when firmware passes none itself.
+    ///
+    /// Paths are expected to be absolute or relative to the
+    /// project root.
+    pub dtb: Option<PathBuf>,
+    /// The physical address the wrapped image is loaded to.
+    ///
+    /// Defaults to the QEMU load address.
+    pub load_address: Option<usize>,
+    /// Whether to record the build time in the build ID and in the
+    /// headers of wrapped images.
     ///
-    /// The image will be compressed with gzip if this is
-    /// set to `true`.
+    /// Timestamps break bit-for-bit reproducible builds unless
+    /// `SOURCE_DATE_EPOCH` is set, which takes precedence over the
+    /// current time.
     ///
     /// Defaults to `false`.
-    #[serde(default = "dont_compress")]
-    pub compress: bool,
+    #[serde(default)]
+    pub timestamps: bool,
 }
 
 /// Build configuration for the `onyx` kernel application.
-#[derive(Debug, Deserialize)]
-#[serde(rename_all = "kebab-case")]
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
 pub struct Kernel {
-    /// Path to the linker script to use for building the kernel.
+    /// Path to the linker script template to use for building the
+    /// kernel.
     ///
     /// Paths are expected to be absolute or relative to the
-    /// project root.
+    /// `build/linker-scripts` directory.
     pub linker_script: PathBuf,
+    /// The size of the early boot stack in bytes.
+    ///
+    /// Defaults to 16KiB.
+    #[serde(default = "default_kernel_stack_size")]
+    pub stack_size: usize,
+    /// The command line to embed into the image, such as
+    /// `"loglevel=debug smp=2 kaslr=off"`.
+    ///
+    /// A non-empty `/chosen/bootargs` property in the device tree
+    /// takes precedence over this at boot.
+    ///
+    /// Defaults to no command line.
+    pub cmdline: Option<String>,
 }
 
 /// Build configuration for the `onyx-loader` kernel loader application.
-#[derive(Debug, Deserialize)]
-#[serde(rename_all = "kebab-case")]
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
 pub struct Loader {
-    /// Path to the linker script to use for building the loader.
+    /// Path to the linker script template to use for building the
+    /// loader.
     ///
     /// Paths are expected to be absolute or relative to the
-    /// project root.
+    /// `build/linker-scripts` directory.
     pub linker_script: PathBuf,
+    /// The size of the stack in bytes, which must accommodate image
+    /// verification.
+    ///
+    /// Defaults to 64KiB.
+    #[serde(default = "default_loader_stack_size")]
+    pub stack_size: usize,
 }
 
 /// QEMU configuration for testing Onyx builds through emulation.
-#[derive(Debug, Deserialize)]
-#[serde(rename_all = "kebab-case")]
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
 pub struct Qemu {
     /// The name of the QEMU executable to use.
     pub name: String,
@@ -99,6 +510,176 @@ pub struct Qemu {
     pub extra_args: Vec<String>,
 }
 
+/// Keys which were renamed, along with their replacements.
+const RENAMED_KEYS: [(&str, &str); 1] = [("image.compress", "image.compression")];
+
+/// A single configuration file in a chain of `extends` keys.
+struct Layer {
+    path: PathBuf,
+    source: String,
+    table: Table,
+}
+
+/// Reads the configuration file at `path` along with all files it
+/// transitively extends, starting with `path` itself.
+fn read_layers(path: &Path) -> anyhow::Result<Vec<Layer>> {
+    let mut layers: Vec<Layer> = Vec::new();
+    let mut next = Some(path.to_path_buf());
+
+    while let Some(path) = next.take() {
+        if layers.iter().any(|l| l.path == path) {
+            bail!("build config {} extends itself", path.display());
+        }
+
+        let source = fs::read_to_string(&path)
+            .with_context(|| format!("failed to read build config {}", path.display()))?;
+        let mut table: Table = toml::from_str(&source)
+            .with_context(|| format!("invalid build config {}", path.display()))?;
+
+        next = match table.remove("extends") {
+            Some(Value::String(parent)) => Some(Config::path(parent)),
+            Some(_) => bail!("`extends` in {} must be a config name", path.display()),
+            None => None,
+        };
+        layers.push(Layer {
+            path,
+            source,
+            table,
+        });
+    }
+
+    Ok(layers)
+}
+
+/// Merges `overlay` into `base`, recursing into tables present in both.
+fn merge(base: &mut Table, overlay: Table) {
+    for (key, value) in overlay {
+        match (base.get_mut(&key), value) {
+            (Some(Value::Table(base)), Value::Table(overlay)) => merge(base, overlay),
+            (_, value) => {
+                base.insert(key, value);
+            }
+        }
+    }
+}
+
+/// Applies an override of the form `key=value` to `table` and returns
+/// the overridden key.
+fn apply_override(table: &mut Table, entry: &str) -> anyhow::Result<String> {
+    let (key, value) = entry
+        .split_once('=')
+        .ok_or_else(|| anyhow!("override `{entry}` must be of the form `key=value`"))?;
+    let key = key.trim();
+
+    // Parse the value as TOML if possible and as a string otherwise.
+    let value = toml::from_str::<Table>(&format!("value = {value}"))
+        .ok()
+        .and_then(|mut t| t.remove("value"))
+        .unwrap_or_else(|| Value::String(value.to_string()));
+
+    let mut parts: Vec<_> = key.split('.').collect();
+    let name = parts.pop().filter(|n| !n.is_empty());
+    let name = name.ok_or_else(|| anyhow!("override `{entry}` has an empty key"))?;
+
+    let mut table = table;
+    for part in parts {
+        table = match table
+            .entry(part)
+            .or_insert_with(|| Value::Table(Table::new()))
+        {
+            Value::Table(t) => t,
+            _ => bail!("override `{entry}` descends into `{part}`, which is not a table"),
+        };
+    }
+    table.insert(name.to_string(), value);
+
+    Ok(key.to_string())
+}
+
+/// Looks up the value of a dotted `key` in `table`.
+fn lookup<'a>(table: &'a Table, key: &str) -> Option<&'a Value> {
+    let (table, name) = match key.rsplit_once('.') {
+        Some((path, name)) => (
+            path.split('.')
+                .try_fold(table, |t, part| t.get(part)?.as_table())?,
+            name,
+        ),
+        None => (table, key),
+    };
+    table.get(name)
+}
+
+/// Describes where a dotted `key` is set in a chain of configuration
+/// layers, for use in diagnostics.
+///
+/// Falls back to the table header of the key in the most derived file
+/// for values that were defaulted.
+fn locate_in(layers: &[Layer], key: &str) -> String {
+    let found = layers
+        .iter()
+        .find_map(|l| locate(&l.source, key, false).map(|line| (l, line)))
+        .or_else(|| locate(&layers[0].source, key, true).map(|line| (&layers[0], line)));
+
+    match found {
+        Some((layer, line)) => format!("{}:{line}", layer.path.display()),
+        None => layers[0].path.display().to_string(),
+    }
+}
+
+/// Finds the line of a dotted `key` in TOML `source`, or the line of
+/// its table header when `header` is set.
+///
+/// Keys naming a table are found by their header in either case.
+fn locate(source: &str, key: &str, header: bool) -> Option<usize> {
+    let (table, name) = key.rsplit_once('.').unwrap_or(("", key));
+
+    let mut current = "";
+    for (line, text) in source.lines().enumerate() {
+        let text = text.trim();
+        if let Some(t) = text.strip_prefix('[').and_then(|t| t.strip_suffix(']')) {
+            current = t.trim();
+            if (header && current == table) || current == key {
+                return Some(line + 1);
+            }
+        } else if let Some((k, _)) = text.split_once('=') {
+            if !header && current == table && k.trim() == name {
+                return Some(line + 1);
+            }
+        }
+    }
+
+    None
+}
+
+/// Gets the dotted key set on the line at byte `offset` of TOML
+/// `source`, or the name of the table when the line is its header.
+///
+/// Only understands the plain layout emitted by [`toml::to_string`].
+fn key_at(source: &str, offset: usize) -> Option<String> {
+    let mut table = "";
+    let mut start = 0;
+    for text in source.split_inclusive('\n') {
+        let end = start + text.len();
+        let text = text.trim();
+        let header = text.strip_prefix('[').and_then(|t| t.strip_suffix(']'));
+        if let Some(t) = header {
+            table = t.trim();
+        }
+        if (start..end).contains(&offset) {
+            return match text.split_once('=') {
+                Some((name, _)) if header.is_none() && table.is_empty() => {
+                    Some(name.trim().to_string())
+                }
+                Some((name, _)) if header.is_none() => Some(format!("{table}.{}", name.trim())),
+                _ => (!table.is_empty()).then(|| table.to_string()),
+            };
+        }
+        start = end;
+    }
+
+    None
+}
+
 fn deserialize_endian<'de, D: Deserializer<'de>>(de: D) -> Result<Endian, D::Error> {
     let endian: &str = Deserialize::deserialize(de)?;
     match endian {
@@ -110,14 +691,367 @@ fn deserialize_endian<'de, D: Deserializer<'de>>(de: D) -> Result<Endian, D::Err
     }
 }
 
+fn default_page_size() -> usize {
+    0x1000
+}
+
+fn default_kernel_stack_size() -> usize {
+    0x4000
+}
+
+fn default_loader_stack_size() -> usize {
+    0x1_0000
+}
+
+fn serialize_endian<S: Serializer>(endian: &Endian, ser: S) -> Result<S::Ok, S::Error> {
+    ser.serialize_str(match endian {
+        Endian::Little => "little",
+        Endian::Big => "big",
+    })
+}
+
 fn generic_board() -> String {
     "generic".to_string()
 }
 
-fn dont_compress() -> bool {
-    false
+fn latest_revision() -> u32 {
+    KernelMeta::REVISION
 }
 
 fn little_endian() -> Endian {
     Endian::Little
 }
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+
+    use super::*;
+
+    /// Writes a configuration file for a test to a temporary directory
+    /// and returns its path.
+    fn write(name: &str, source: &str) -> PathBuf {
+        let dir = env::temp_dir().join(format!("xtask-config-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+
+        let path = dir.join(name).with_extension("toml");
+        fs::write(&path, source).unwrap();
+        path
+    }
+
+    fn qemu() -> Config {
+        Config::read(Config::path("riscv64_qemu"), &[]).unwrap()
+    }
+
+    fn problems(config: &Config) -> Vec<&'static str> {
+        let problems = config.validate().unwrap();
+        problems.into_iter().map(|p| p.key).collect()
+    }
+
+    #[test]
+    fn accepts_shipped_configs() {
+        for name in ["riscv64_qemu", "riscv64_sifive_u"] {
+            let config = Config::read(Config::path(name), &[]).unwrap();
+            assert!(problems(&config).is_empty(), "{name}");
+        }
+    }
+
+    #[test]
+    fn rejects_unknown_targets_and_boards() {
+        let mut config = qemu();
+        config.target = "riscv64-onyx-none".into();
+        config.board = "qemu-unknown".to_string();
+        assert_eq!(problems(&config), ["target", "board"]);
+
+        config.target = "build/targets/missing.json".into();
+        config.board = "generic".to_string();
+        assert_eq!(problems(&config), ["target"]);
+    }
+
+    #[test]
+    fn rejects_misaligned_memory() {
+        let mut config = qemu();
+        config.memory.page_size = 0x1800;
+        assert_eq!(problems(&config), ["memory.page-size"]);
+
+        config.memory.page_size = 0x10000;
+        config.memory.base += 0x1000;
+        config.memory.size = 0;
+        config.image.load_address = Some(0x8020_1000);
+        assert_eq!(
+            problems(&config),
+            [
+                "memory.base",
+                "memory.size",
+                "qemu.address",
+                "image.load-address"
+            ]
+        );
+    }
+
+    #[test]
+    fn rejects_qemu_address_outside_ram() {
+        let mut config = qemu();
+        config.qemu.address = config.memory.base + config.memory.size;
+        assert_eq!(problems(&config), ["qemu.address"]);
+    }
+
+    #[test]
+    fn rejects_missing_files() {
+        let mut config = qemu();
+        config.kernel.linker_script = "missing.x".into();
+        config.loader.linker_script = "missing.x".into();
+        config.image.kips = vec!["missing.kip".into()];
+        config.image.signing_key = Some("missing.key".into());
+        config.image.dtb = Some("missing.dtb".into());
+        assert_eq!(
+            problems(&config),
+            [
+                "kernel.linker-script",
+                "loader.linker-script",
+                "image.kips",
+                "image.signing-key",
+                "image.dtb"
+            ]
+        );
+    }
+
+    #[test]
+    fn rejects_bad_stack_sizes() {
+        let mut config = qemu();
+        config.kernel.stack_size = 0;
+        config.loader.stack_size = 0x1008;
+        assert_eq!(
+            problems(&config),
+            ["kernel.stack-size", "loader.stack-size"]
+        );
+    }
+
+    #[test]
+    fn rejects_features_of_newer_revisions() {
+        let mut config = qemu();
+        config.image.revision = KernelMeta::REVISION + 1;
+        assert_eq!(problems(&config), ["image.revision"]);
+
+        config.image.revision = 1;
+        config.image.checksum = true;
+        config.kernel.cmdline = Some("loglevel=debug".to_string());
+        assert_eq!(problems(&config), ["image.checksum", "kernel.cmdline"]);
+    }
+
+    #[test]
+    fn rejects_nul_in_cmdline() {
+        let mut config = qemu();
+        config.kernel.cmdline = Some("loglevel=debug\0smp=2".to_string());
+        assert_eq!(problems(&config), ["kernel.cmdline"]);
+    }
+
+    #[test]
+    fn locates_keys_and_headers() {
+        let source = "target = \"x\"\n\n[memory]\nbase = 0\n  size = 1\n\n[qemu]\n";
+        assert_eq!(locate(source, "target", false), Some(1));
+        assert_eq!(locate(source, "memory.base", false), Some(4));
+        assert_eq!(locate(source, "memory.size", false), Some(5));
+        assert_eq!(locate(source, "memory", false), Some(3));
+        assert_eq!(locate(source, "memory.page-size", false), None);
+        assert_eq!(locate(source, "memory.page-size", true), Some(3));
+        assert_eq!(locate(source, "qemu.address", true), Some(7));
+        assert_eq!(locate(source, "image.dtb", true), None);
+    }
+
+    #[test]
+    fn locates_keys_in_layers() {
+        let layer = |path: &str, source: &str| Layer {
+            path: path.into(),
+            source: source.to_string(),
+            table: Table::new(),
+        };
+        let layers = [
+            layer("derived.toml", "[memory]\nsize = 1\n"),
+            layer("base.toml", "[memory]\nbase = 0\nsize = 2\n\n[qemu]\n"),
+        ];
+
+        assert_eq!(locate_in(&layers, "memory.size"), "derived.toml:2");
+        assert_eq!(locate_in(&layers, "memory.base"), "base.toml:2");
+        assert_eq!(locate_in(&layers, "memory.page-size"), "derived.toml:1");
+        assert_eq!(locate_in(&layers, "qemu.address"), "derived.toml");
+    }
+
+    #[test]
+    fn reports_problems_with_their_location() {
+        let path = write(
+            "problems",
+            "extends = \"riscv64_qemu\"\n\n[memory]\nbase = 0x80000800\n\n[kernel]\nstack-size = 8\n",
+        );
+        let error = Config::read(&path, &["qemu.address=1".to_string()]).unwrap_err();
+        let error = error.to_string();
+
+        let base = Config::path("riscv64_qemu");
+        for expected in [
+            format!("{}:4: `memory.base`", path.display()),
+            format!("{}:7: `kernel.stack-size`", path.display()),
+            "--set qemu.address: `qemu.address`".to_string(),
+        ] {
+            assert!(error.contains(&expected), "{expected} not in {error}");
+        }
+        assert!(!error.contains(&base.display().to_string()), "{error}");
+    }
+
+    #[test]
+    fn reports_type_errors_with_their_location() {
+        let path = write(
+            "types",
+            "extends = \"riscv64_qemu\"\n\n[memory]\nsize = \"lots\"\n",
+        );
+        let error = Config::read(&path, &[]).unwrap_err().to_string();
+        assert!(error.starts_with(&format!("invalid build config {}:4:", path.display())));
+
+        let base = Config::path("riscv64_qemu");
+        let error = Config::read(base, &["qemu.name=[1]".to_string()]).unwrap_err();
+        assert!(error
+            .to_string()
+            .starts_with("invalid build config --set qemu.name:"));
+
+        // Missing fields are located at their table.
+        let path = write("missing", "target = \"x\"\n\n[memory]\nbase = 0\n");
+        let error = Config::read(&path, &[]).unwrap_err().to_string();
+        assert_eq!(
+            error,
+            format!(
+                "invalid build config {}:3: missing field `size`",
+                path.display()
+            )
+        );
+
+        let path = write("root", "target = \"x\"\n\n[memory]\nbase = 0\nsize = 1\n");
+        let error = Config::read(&path, &[]).unwrap_err().to_string();
+        assert_eq!(
+            error,
+            format!(
+                "invalid build config {}: missing field `image`",
+                path.display()
+            )
+        );
+    }
+
+    #[test]
+    fn rejects_unknown_and_renamed_keys() {
+        let path = write(
+            "renamed",
+            "extends = \"riscv64_qemu\"\n\n[image]\ncompress = true\n",
+        );
+        let error = Config::read(&path, &[]).unwrap_err().to_string();
+        assert_eq!(
+            error,
+            format!(
+                "invalid build config {}:4: `image.compress` was replaced by `image.compression`",
+                path.display()
+            )
+        );
+
+        let base = Config::path("riscv64_qemu");
+        let error = Config::read(base, &["image.compress=true".to_string()]).unwrap_err();
+        assert!(error.to_string().starts_with(
+            "invalid build config --set image.compress: `image.compress` was replaced"
+        ));
+
+        let path = write(
+            "unknown",
+            "extends = \"riscv64_qemu\"\n\n[kernel]\nstack = 1\n",
+        );
+        let error = Config::read(&path, &[]).unwrap_err().to_string();
+        assert!(
+            error.starts_with(&format!(
+                "invalid build config {}:4: unknown field `stack`",
+                path.display()
+            )),
+            "{error}"
+        );
+    }
+
+    fn table(source: &str) -> Table {
+        toml::from_str(source).unwrap()
+    }
+
+    #[test]
+    fn merges_tables_and_replaces_other_values() {
+        let mut base = table(
+            "target = \"a\"\n[memory]\nbase = 0\nsize = 1\n[qemu]\nextra-args = [\"-s\", \"-S\"]\n",
+        );
+        merge(
+            &mut base,
+            table("target = \"b\"\n[memory]\nsize = 2\n[qemu]\nextra-args = [\"-d\"]\n"),
+        );
+
+        assert_eq!(
+            base,
+            table("target = \"b\"\n[memory]\nbase = 0\nsize = 2\n[qemu]\nextra-args = [\"-d\"]\n")
+        );
+    }
+
+    #[test]
+    fn applies_overrides() {
+        let mut config = table("[memory]\nsize = 1\n");
+        let overrides = [
+            "memory.size = 0x2000",
+            "image.compression=lz4",
+            "image.formats=[\"elf\", \"fit\"]",
+            "kernel.cmdline=loglevel=debug",
+        ];
+        for entry in overrides {
+            apply_override(&mut config, entry).unwrap();
+        }
+
+        assert_eq!(
+            config,
+            table(
+                "[memory]\nsize = 0x2000\n\
+                 [image]\ncompression = \"lz4\"\nformats = [\"elf\", \"fit\"]\n\
+                 [kernel]\ncmdline = \"loglevel=debug\"\n"
+            )
+        );
+    }
+
+    #[test]
+    fn rejects_malformed_overrides() {
+        let mut config = table("board = \"generic\"\n");
+        for entry in ["board", "memory.=1", "board.name=x"] {
+            assert!(apply_override(&mut config, entry).is_err(), "{entry}");
+        }
+        assert_eq!(config, table("board = \"generic\"\n"));
+    }
+
+    #[test]
+    fn reads_layers_from_the_most_derived() {
+        let base = write("layers_base", "board = \"generic\"\n");
+        let base = base.with_extension("");
+        let path = write(
+            "layers",
+            &format!("extends = \"{}\"\nboard = \"qemu-virt\"\n", base.display()),
+        );
+
+        let layers = read_layers(&path).unwrap();
+        let paths: Vec<_> = layers.iter().map(|l| &l.path).collect();
+        assert_eq!(paths, [&path, &base.with_extension("toml")]);
+        assert_eq!(layers[0].table, table("board = \"qemu-virt\"\n"));
+    }
+
+    #[test]
+    fn rejects_extends_cycles() {
+        let dir = write("cycle_a", "").with_file_name("");
+        let a = write(
+            "cycle_a",
+            &format!("extends = \"{}\"\n", dir.join("cycle_b").display()),
+        );
+        write(
+            "cycle_b",
+            &format!("extends = \"{}\"\n", dir.join("cycle_a").display()),
+        );
+
+        let error = read_layers(&a).err().unwrap().to_string();
+        assert_eq!(
+            error,
+            format!("build config {} extends itself", a.display())
+        );
+    }
+}
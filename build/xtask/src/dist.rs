@@ -0,0 +1,195 @@
+use std::{fs, path::Path};
+
+use anyhow::bail;
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use xshell::{cmd, Shell};
+
+use crate::{
+    cargo,
+    config::Config,
+    image::{self, BuildId, Compression, KernelImage},
+    linker, rustc,
+};
+
+/// Describes a Kernel Image and the files shipped alongside it.
+#[derive(Serialize)]
+struct Manifest {
+    /// The file name of the image.
+    image: String,
+    /// The size of the image file.
+    size: usize,
+    /// The SHA-256 digest of the image file, in hex.
+    sha256: String,
+    compression: Compression,
+    /// The address QEMU loads the image to.
+    load_address: usize,
+    /// The offset from the load address to the raw image in memory.
+    ///
+    /// Non-zero for compressed images, which are extracted behind
+    /// their payload.
+    image_offset: usize,
+    version: String,
+    build_id: Option<BuildId>,
+    /// All components of the raw image, in order of their offsets.
+    components: Vec<Component>,
+}
+
+#[derive(Serialize)]
+struct Component {
+    name: &'static str,
+    /// The offset of the component in the raw image.
+    offset: u64,
+    size: u64,
+    /// The files describing the component's binary, if it has one.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    files: Option<Files>,
+}
+
+#[derive(Serialize)]
+struct Files {
+    elf: String,
+    map: String,
+    symbols: String,
+}
+
+/// Ships the debug artifacts of a dist build next to the image.
+///
+/// For the kernel and the Kernel Loader, this copies the unstripped
+/// ELF, the linker map and an `nm`-style symbol listing, named after
+/// their package. The ELFs are suffixed with `.debug.elf` to tell them
+/// apart from an image wrapped in the ELF [`Format`]. An
+/// `onyx.manifest.json` describes the layout of the image and refers
+/// to all of them.
+///
+/// [`Format`]: crate::format::Format
+pub fn ship(
+    sh: &Shell,
+    image_path: &Path,
+    kernel_elf: &Path,
+    loader_elf: &Path,
+    config: &Config,
+    release: bool,
+) -> anyhow::Result<()> {
+    let dist = image_path.parent().unwrap();
+
+    let kernel = ship_binary(sh, dist, "onyx", kernel_elf, release)?;
+    let loader = ship_binary(sh, dist, "onyx-loader", loader_elf, release)?;
+
+    let file = fs::read(image_path)?;
+    let (data, compression) = image::read_image(image_path, config.endian)?;
+    let image = KernelImage::parse(&data, config.endian)?;
+
+    let mut files = [Some(kernel), Some(loader)].into_iter();
+    let components = image
+        .components()
+        .into_iter()
+        .map(|(name, range)| Component {
+            name,
+            offset: range.start,
+            size: range.end - range.start,
+            files: files.next().flatten(),
+        })
+        .collect();
+
+    let manifest = Manifest {
+        image: file_name(image_path),
+        size: file.len(),
+        sha256: Sha256::digest(&file)
+            .iter()
+            .map(|b| format!("{b:02x}"))
+            .collect(),
+        compression,
+        load_address: config.qemu.address,
+        image_offset: image::extracted_offset(&file, config.endian)?,
+        version: cargo::package_version("onyx")?.to_string(),
+        build_id: image.build_id().cloned(),
+        components,
+    };
+
+    let path = dist.join("onyx.manifest.json");
+    fs::write(path, serde_json::to_string_pretty(&manifest)? + "\n")?;
+
+    Ok(())
+}
+
+/// Compares the Kernel Image at `image` and all its wrapped formats
+/// against those of a second build at `rebuilt`.
+///
+/// On a mismatch, the first difference between the raw images is
+/// attributed to the component it falls into.
+pub fn check_reproducible(image: &Path, rebuilt: &Path, config: &Config) -> anyhow::Result<()> {
+    let mut files = vec![(image.to_path_buf(), rebuilt.to_path_buf())];
+    for format in &config.image.formats {
+        files.push((
+            image.with_extension(format.extension()),
+            rebuilt.with_extension(format.extension()),
+        ));
+    }
+
+    let mut mismatches = Vec::new();
+    for (first, second) in &files {
+        if fs::read(first)? != fs::read(second)? {
+            mismatches.push(file_name(first));
+        }
+    }
+    if mismatches.is_empty() {
+        status!("Build is reproducible: {}", file_name(image));
+        return Ok(());
+    }
+
+    let (first, _) = image::read_image(image, config.endian)?;
+    let (second, _) = image::read_image(rebuilt, config.endian)?;
+    let hint = match first.iter().zip(&second).position(|(a, b)| a != b) {
+        Some(offset) => {
+            let component = KernelImage::parse(&first, config.endian)?
+                .components()
+                .into_iter()
+                .find(|(_, range)| range.contains(&(offset as u64)))
+                .map_or("padding", |(name, _)| name);
+            format!("; the raw images first differ at {offset:#x} ({component})")
+        }
+        None if first.len() != second.len() => format!(
+            "; the raw images differ in size ({:#x} and {:#x} bytes)",
+            first.len(),
+            second.len()
+        ),
+        None => String::new(),
+    };
+
+    bail!("{} differ between two builds{hint}", mismatches.join(", "));
+}
+
+/// Copies the ELF and the linker map of a package to `dist` and writes
+/// its symbol listing.
+fn ship_binary(
+    sh: &Shell,
+    dist: &Path,
+    pkg: &str,
+    elf: &Path,
+    release: bool,
+) -> anyhow::Result<Files> {
+    let files = Files {
+        elf: debug_elf_name(pkg),
+        map: format!("{pkg}.map"),
+        symbols: format!("{pkg}.sym"),
+    };
+
+    fs::copy(elf, dist.join(&files.elf))?;
+    fs::copy(linker::map_path(pkg, release), dist.join(&files.map))?;
+
+    let nm = rustc::llvm_binutil(sh, "nm")?;
+    let symbols = cmd!(sh, "{nm} --numeric-sort --demangle {elf}").read()?;
+    fs::write(dist.join(&files.symbols), symbols + "\n")?;
+
+    Ok(files)
+}
+
+/// Gets the file name of the unstripped ELF of a package in a dist.
+pub fn debug_elf_name(pkg: &str) -> String {
+    format!("{pkg}.debug.elf")
+}
+
+fn file_name(path: &Path) -> String {
+    path.file_name().unwrap().to_string_lossy().into_owned()
+}
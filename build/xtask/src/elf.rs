@@ -0,0 +1,121 @@
+use std::{collections::HashMap, fs, path::Path};
+
+use anyhow::{anyhow, bail, Context};
+use object::{Object, ObjectSection, ObjectSegment, ObjectSymbol, SectionKind};
+
+use crate::image::{KernelMeta, KERNEL_MAGIC};
+
+/// The symbol marking the start of the kernel binary in `start.s`.
+pub const START_SYMBOL: &str = "__onyx_start";
+
+/// A kernel ELF file, used for looking up symbols.
+pub struct KernelElf {
+    elf: Vec<u8>,
+    /// The addresses of all symbols by their names.
+    symbols: HashMap<String, u64>,
+}
+
+impl KernelElf {
+    /// Reads the kernel ELF file at the given path.
+    pub fn read<P: AsRef<Path>>(path: P) -> anyhow::Result<Self> {
+        let path = path.as_ref();
+        let elf = fs::read(path).with_context(|| format!("failed to read {}", path.display()))?;
+
+        // The first symbol wins when several share a name.
+        let file = object::File::parse(&*elf)
+            .with_context(|| format!("{} is not a valid ELF file", path.display()))?;
+        let mut symbols = HashMap::new();
+        for symbol in file.symbols() {
+            if let Ok(name) = symbol.name() {
+                symbols
+                    .entry(name.to_string())
+                    .or_insert_with(|| symbol.address());
+            }
+        }
+
+        Ok(Self { elf, symbols })
+    }
+
+    /// Looks up the address of a symbol by its name.
+    pub fn symbol(&self, name: &str) -> anyhow::Result<u64> {
+        self.symbols
+            .get(name)
+            .copied()
+            .ok_or_else(|| anyhow!("symbol `{name}` not found in kernel ELF"))
+    }
+
+    /// Sums up the sizes of all sections of the given kinds.
+    pub fn section_size(&self, kinds: &[SectionKind]) -> anyhow::Result<u64> {
+        let file = object::File::parse(&*self.elf)?;
+        Ok(file
+            .sections()
+            .filter(|s| kinds.contains(&s.kind()))
+            .map(|s| s.size())
+            .sum())
+    }
+
+    /// Gets the size of the binary in memory, from its lowest loaded
+    /// address up to the end of its last segment.
+    ///
+    /// Unlike the raw binary, this includes sections such as .bss
+    /// which occupy no space in the file.
+    pub fn memory_size(&self) -> anyhow::Result<u64> {
+        let file = object::File::parse(&*self.elf)?;
+        let start = file.segments().map(|s| s.address()).min();
+        let end = file.segments().map(|s| s.address() + s.size()).max();
+        match (start, end) {
+            (Some(start), Some(end)) => Ok(end - start),
+            _ => bail!("ELF has no loadable segments"),
+        }
+    }
+
+    /// Locates the [`KernelMeta`] in the raw binary `kernel` by the
+    /// symbols of its fields and returns its offset.
+    ///
+    /// This cross-checks the labels in `r0/start.s` against the
+    /// layout of [`KernelMeta`] and fails with a precise error when
+    /// the two drifted apart.
+    pub fn kernel_meta_offset(&self, kernel: &[u8]) -> anyhow::Result<usize> {
+        let file = object::File::parse(&*self.elf)?;
+        let start = self.symbol(START_SYMBOL)?;
+
+        // objcopy emits the raw binary from the lowest loaded address,
+        // which must be the start of the kernel for offsets to match.
+        let base = file
+            .segments()
+            .filter(|s| s.file_range().1 != 0)
+            .map(|s| s.address())
+            .min()
+            .ok_or_else(|| anyhow!("kernel ELF has no loadable segments"))?;
+        if base != start {
+            bail!("kernel ELF is loaded at {base:#x}, but `{START_SYMBOL}` is at {start:#x}");
+        }
+
+        let (magic_symbol, _) = KernelMeta::SYMBOLS[0];
+        let magic = self.symbol(magic_symbol)?;
+        for (name, expected) in KernelMeta::SYMBOLS {
+            let actual = self.symbol(name)? - magic;
+            if actual != expected as u64 {
+                bail!(
+                    "`{name}` is at offset {actual:#x} into the metadata in `start.s`, \
+                     but `KernelMeta` expects it at {expected:#x}; \
+                     the assembly and the build system drifted apart"
+                );
+            }
+        }
+
+        // Confirm the symbols describe the raw binary we were given.
+        let offset = (magic - start) as usize;
+        let end = offset + KernelMeta::SIZE;
+        match kernel.get(offset..end) {
+            Some(meta) if meta.starts_with(&KERNEL_MAGIC) => Ok(offset),
+            Some(_) => bail!(
+                "raw kernel binary lacks the `ONYX` magic at offset {offset:#x} given by `{magic_symbol}`"
+            ),
+            None => bail!(
+                "raw kernel binary of size {:#x} is too small to hold metadata at {offset:#x}",
+                kernel.len()
+            ),
+        }
+    }
+}
@@ -0,0 +1,164 @@
+use std::{collections::HashMap, fs, path::Path};
+
+use anyhow::{bail, Context};
+pub use onyx_image::FDT_MAGIC;
+use xshell::{cmd, Shell};
+
+const FDT_BEGIN_NODE: u32 = 0x1;
+const FDT_END_NODE: u32 = 0x2;
+const FDT_PROP: u32 = 0x3;
+const FDT_END: u32 = 0x9;
+
+/// The size of the FDT header in bytes.
+const HEADER_SIZE: usize = 0x28;
+
+/// Reads the device tree blob at `path`.
+///
+/// Device tree sources with a `.dts` extension are compiled to a blob
+/// through the `dtc` found in `PATH` first.
+pub fn read_dtb(sh: &Shell, path: &Path) -> anyhow::Result<Vec<u8>> {
+    let dtb = if path.extension().map_or(false, |ext| ext == "dts") {
+        cmd!(sh, "dtc -I dts -O dtb {path}")
+            .output()
+            .with_context(|| format!("failed to compile {} with `dtc`", path.display()))?
+            .stdout
+    } else {
+        fs::read(path).with_context(|| format!("failed to read {}", path.display()))?
+    };
+
+    if dtb.get(..4) != Some(&FDT_MAGIC.to_be_bytes()[..]) {
+        bail!("{} is not a valid device tree blob", path.display());
+    }
+
+    Ok(dtb)
+}
+
+/// A minimal writer for Flattened Device Tree blobs.
+///
+/// Nodes and properties are emitted in the order they are added,
+/// and [`FdtWriter::finish`] assembles them into a version 17 blob.
+/// All values are encoded in big endian as the format demands.
+#[derive(Debug, Default)]
+pub struct FdtWriter {
+    structure: Vec<u8>,
+    strings: Vec<u8>,
+    string_offsets: HashMap<String, u32>,
+    depth: usize,
+}
+
+impl FdtWriter {
+    /// Creates a new writer for an empty device tree.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Opens a new node with the given name.
+    ///
+    /// The root node must be opened with an empty name.
+    pub fn begin_node(&mut self, name: &str) {
+        self.push_u32(FDT_BEGIN_NODE);
+        self.structure.extend_from_slice(name.as_bytes());
+        self.structure.push(0);
+        self.align();
+
+        self.depth += 1;
+    }
+
+    /// Closes the most recently opened node.
+    pub fn end_node(&mut self) {
+        assert!(self.depth > 0, "no open node to close");
+
+        self.push_u32(FDT_END_NODE);
+        self.depth -= 1;
+    }
+
+    /// Adds a property with a raw value to the current node.
+    pub fn property(&mut self, name: &str, value: &[u8]) {
+        assert!(self.depth > 0, "properties must be placed inside a node");
+
+        let name_offset = self.string_offset(name);
+        self.push_u32(FDT_PROP);
+        self.push_u32(value.len() as u32);
+        self.push_u32(name_offset);
+        self.structure.extend_from_slice(value);
+        self.align();
+    }
+
+    /// Adds a property with a NUL-terminated string value.
+    pub fn property_string(&mut self, name: &str, value: &str) {
+        let mut bytes = value.as_bytes().to_vec();
+        bytes.push(0);
+        self.property(name, &bytes);
+    }
+
+    /// Adds a property with a single 32-bit cell value.
+    pub fn property_u32(&mut self, name: &str, value: u32) {
+        self.property(name, &value.to_be_bytes());
+    }
+
+    /// Adds a property with a 64-bit value spanning two cells.
+    pub fn property_u64(&mut self, name: &str, value: u64) {
+        self.property(name, &value.to_be_bytes());
+    }
+
+    /// Assembles the final device tree blob.
+    ///
+    /// # Panics
+    ///
+    /// Panics when not all nodes have been closed.
+    pub fn finish(mut self) -> Vec<u8> {
+        assert_eq!(self.depth, 0, "unclosed nodes in device tree");
+        self.push_u32(FDT_END);
+
+        // The memory reservation map consists of a single terminator.
+        let off_mem_rsvmap = HEADER_SIZE;
+        let off_dt_struct = off_mem_rsvmap + 0x10;
+        let off_dt_strings = off_dt_struct + self.structure.len();
+        let total_size = off_dt_strings + self.strings.len();
+
+        let header = [
+            FDT_MAGIC,
+            total_size as u32,
+            off_dt_struct as u32,
+            off_dt_strings as u32,
+            off_mem_rsvmap as u32,
+            17, // version
+            16, // last_comp_version
+            0,  // boot_cpuid_phys
+            self.strings.len() as u32,
+            self.structure.len() as u32,
+        ];
+
+        let mut blob = Vec::with_capacity(total_size);
+        header
+            .iter()
+            .for_each(|v| blob.extend_from_slice(&v.to_be_bytes()));
+        blob.extend_from_slice(&[0; 0x10]);
+        blob.extend_from_slice(&self.structure);
+        blob.extend_from_slice(&self.strings);
+
+        blob
+    }
+
+    fn string_offset(&mut self, name: &str) -> u32 {
+        if let Some(&offset) = self.string_offsets.get(name) {
+            return offset;
+        }
+
+        let offset = self.strings.len() as u32;
+        self.strings.extend_from_slice(name.as_bytes());
+        self.strings.push(0);
+        self.string_offsets.insert(name.to_string(), offset);
+
+        offset
+    }
+
+    fn push_u32(&mut self, value: u32) {
+        self.structure.extend_from_slice(&value.to_be_bytes());
+    }
+
+    fn align(&mut self) {
+        let len = self.structure.len();
+        self.structure.resize((len + 3) & !3, 0);
+    }
+}
@@ -0,0 +1,441 @@
+use std::{
+    fs,
+    io::Cursor,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{anyhow, bail};
+use binrw::{binrw, BinWrite, Endian};
+use flate2::Crc;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::{build_id, config::Config, fdt::FdtWriter, image::PAGE_SIZE};
+
+/// The name that is embedded into uImage and FIT headers.
+const IMAGE_NAME: &str = "Onyx Kernel Image";
+
+/// Output formats that wrap a finished Kernel Image for consumption
+/// by bootloaders other than QEMU's generic loader device.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Format {
+    /// An ELF executable with a single loadable segment.
+    ///
+    /// Allows booting through QEMU's `-kernel` option and loading
+    /// the image into GDB.
+    Elf,
+    /// A U-Boot legacy uImage.
+    UImage,
+    /// A U-Boot Flattened Image Tree.
+    Fit,
+}
+
+impl Format {
+    /// Gets the file extension for images in this format.
+    pub fn extension(self) -> &'static str {
+        match self {
+            Self::Elf => "elf",
+            Self::UImage => "uimg",
+            Self::Fit => "itb",
+        }
+    }
+
+    /// Wraps the Kernel Image at `image` in this format and writes
+    /// the result next to it, returning the path of the new file.
+    pub fn write(self, image: &Path, config: &Config) -> anyhow::Result<PathBuf> {
+        let data = fs::read(image)?;
+        let load_address = config.image.load_address.unwrap_or(config.qemu.address) as u64;
+
+        // Targets may also be given as paths to JSON specifications.
+        let target = config
+            .target
+            .file_stem()
+            .and_then(|t| t.to_str())
+            .unwrap_or_default();
+
+        // uImage and FIT headers store the time as 32 bits.
+        let timestamp = build_id::timestamp(config.image.timestamps)? as u32;
+
+        let wrapped = match self {
+            Self::Elf => wrap_elf(&data, load_address, target, config.endian)?,
+            Self::UImage => wrap_uimage(&data, load_address, target, timestamp)?,
+            Self::Fit => wrap_fit(&data, load_address, target, timestamp),
+        };
+
+        let path = image.with_extension(self.extension());
+        fs::write(&path, wrapped)?;
+
+        Ok(path)
+    }
+}
+
+/// Wraps a Kernel Image into an ELF executable which loads it at
+/// `load_address` and enters it at its start.
+pub fn wrap_elf(
+    image: &[u8],
+    load_address: u64,
+    target: &str,
+    endian: Endian,
+) -> anyhow::Result<Vec<u8>> {
+    let (machine, flags) = elf_machine(target)?;
+
+    let mut ident = [0; 16];
+    ident[..4].copy_from_slice(b"\x7FELF");
+    ident[4] = 2; // ELFCLASS64
+    ident[5] = match endian {
+        Endian::Little => 1, // ELFDATA2LSB
+        Endian::Big => 2,    // ELFDATA2MSB
+    };
+    ident[6] = 1; // EV_CURRENT
+
+    let header = ElfHeader {
+        ident,
+        ty: 2, // ET_EXEC
+        machine,
+        version: 1,
+        entry: load_address,
+        phoff: ElfHeader::SIZE as u64,
+        shoff: 0,
+        flags,
+        ehsize: ElfHeader::SIZE as u16,
+        phentsize: ElfProgramHeader::SIZE as u16,
+        phnum: 1,
+        shentsize: 0,
+        shnum: 0,
+        shstrndx: 0,
+    };
+    let segment = ElfProgramHeader {
+        ty: 1,    // PT_LOAD
+        flags: 7, // PF_R | PF_W | PF_X
+        offset: PAGE_SIZE as u64,
+        vaddr: load_address,
+        paddr: load_address,
+        filesz: image.len() as u64,
+        memsz: image.len() as u64,
+        align: PAGE_SIZE as u64,
+    };
+
+    let mut elf = Cursor::new(Vec::with_capacity(PAGE_SIZE + image.len()));
+    header.write_options(&mut elf, endian, ())?;
+    segment.write_options(&mut elf, endian, ())?;
+
+    // Place the segment on a page boundary in the file.
+    let mut elf = elf.into_inner();
+    elf.resize(PAGE_SIZE, 0);
+    elf.extend_from_slice(image);
+
+    Ok(elf)
+}
+
+/// Wraps a Kernel Image into a U-Boot legacy uImage which loads it
+/// at `load_address` and enters it at its start.
+pub fn wrap_uimage(
+    image: &[u8],
+    load_address: u64,
+    target: &str,
+    timestamp: u32,
+) -> anyhow::Result<Vec<u8>> {
+    let load_address = u32::try_from(load_address)
+        .map_err(|_| anyhow!("uImage load address {load_address:#x} exceeds 32 bits"))?;
+    let size = u32::try_from(image.len())
+        .map_err(|_| anyhow!("Kernel Image is too large for a uImage"))?;
+
+    let mut name = [0; 32];
+    name[..IMAGE_NAME.len()].copy_from_slice(IMAGE_NAME.as_bytes());
+
+    let mut header = UImageHeader {
+        header_crc: 0,
+        time: timestamp,
+        size,
+        load: load_address,
+        entry: load_address,
+        data_crc: crc32(image),
+        os: 5, // IH_OS_LINUX
+        arch: uimage_arch(target)?,
+        ty: 2,          // IH_TYPE_KERNEL
+        compression: 0, // IH_COMP_NONE
+        name,
+    };
+
+    // The header checksum is calculated with the field itself zeroed.
+    let mut encoded = Cursor::new(Vec::with_capacity(UImageHeader::SIZE + image.len()));
+    header.write_options(&mut encoded, Endian::Big, ())?;
+    header.header_crc = crc32(encoded.get_ref());
+
+    encoded.set_position(0);
+    header.write_options(&mut encoded, Endian::Big, ())?;
+
+    let mut uimage = encoded.into_inner();
+    uimage.extend_from_slice(image);
+
+    Ok(uimage)
+}
+
+/// Wraps a Kernel Image into a U-Boot Flattened Image Tree with a
+/// single kernel and a default configuration that boots it.
+pub fn wrap_fit(image: &[u8], load_address: u64, target: &str, timestamp: u32) -> Vec<u8> {
+    let mut fit = FdtWriter::new();
+
+    fit.begin_node("");
+    fit.property_string("description", IMAGE_NAME);
+    fit.property_u32("timestamp", timestamp);
+    fit.property_u32("#address-cells", 2);
+
+    fit.begin_node("images");
+    fit.begin_node("kernel");
+    fit.property_string("description", IMAGE_NAME);
+    fit.property("data", image);
+    fit.property_string("type", "kernel");
+    fit.property_string("arch", fit_arch(target));
+    fit.property_string("os", "linux");
+    fit.property_string("compression", "none");
+    fit.property_u64("load", load_address);
+    fit.property_u64("entry", load_address);
+    fit.begin_node("hash-1");
+    fit.property("value", &Sha256::digest(image));
+    fit.property_string("algo", "sha256");
+    fit.end_node();
+    fit.end_node();
+    fit.end_node();
+
+    fit.begin_node("configurations");
+    fit.property_string("default", "conf-1");
+    fit.begin_node("conf-1");
+    fit.property_string("description", IMAGE_NAME);
+    fit.property_string("kernel", "kernel");
+    fit.end_node();
+    fit.end_node();
+
+    fit.end_node();
+    fit.finish()
+}
+
+fn elf_machine(target: &str) -> anyhow::Result<(u16, u32)> {
+    let arch = target.split('-').next().unwrap_or_default();
+    match arch.strip_prefix("riscv64") {
+        // EM_RISCV, with EF_RISCV_RVC for the compressed extension.
+        Some(extensions) => Ok((243, extensions.contains('c') as u32)),
+        None => bail!("no ELF machine known for target `{target}`"),
+    }
+}
+
+fn uimage_arch(target: &str) -> anyhow::Result<u8> {
+    match fit_arch(target) {
+        "riscv" => Ok(26), // IH_ARCH_RISCV
+        _ => bail!("no uImage architecture known for target `{target}`"),
+    }
+}
+
+fn fit_arch(target: &str) -> &'static str {
+    if target.starts_with("riscv") {
+        "riscv"
+    } else {
+        "unknown"
+    }
+}
+
+fn crc32(data: &[u8]) -> u32 {
+    let mut crc = Crc::new();
+    crc.update(data);
+    crc.sum()
+}
+
+/// The ELF64 file header.
+#[binrw]
+struct ElfHeader {
+    ident: [u8; 16],
+    ty: u16,
+    machine: u16,
+    version: u32,
+    entry: u64,
+    phoff: u64,
+    shoff: u64,
+    flags: u32,
+    ehsize: u16,
+    phentsize: u16,
+    phnum: u16,
+    shentsize: u16,
+    shnum: u16,
+    shstrndx: u16,
+}
+
+impl ElfHeader {
+    const SIZE: usize = 0x40;
+}
+
+/// An ELF64 program header describing a segment.
+#[binrw]
+struct ElfProgramHeader {
+    ty: u32,
+    flags: u32,
+    offset: u64,
+    vaddr: u64,
+    paddr: u64,
+    filesz: u64,
+    memsz: u64,
+    align: u64,
+}
+
+impl ElfProgramHeader {
+    const SIZE: usize = 0x38;
+}
+
+/// The header of a U-Boot legacy uImage, always in big endian.
+#[binrw]
+#[brw(magic = 0x27051956u32)]
+struct UImageHeader {
+    header_crc: u32,
+    time: u32,
+    size: u32,
+    load: u32,
+    entry: u32,
+    data_crc: u32,
+    os: u8,
+    arch: u8,
+    ty: u8,
+    compression: u8,
+    name: [u8; 32],
+}
+
+impl UImageHeader {
+    const SIZE: usize = 0x40;
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use binrw::BinRead;
+    use object::{Architecture, Object, ObjectSegment};
+
+    use super::*;
+    use crate::fdt::FDT_MAGIC;
+
+    const TARGET: &str = "riscv64imac-unknown-none-elf";
+    const LOAD_ADDRESS: u64 = 0x8020_0000;
+
+    fn image() -> Vec<u8> {
+        (0..0x1234).map(|i| i as u8).collect()
+    }
+
+    /// Reads all properties of a device tree blob, keyed by their full
+    /// path such as `/images/kernel/load`.
+    fn fdt_properties(blob: &[u8]) -> HashMap<String, Vec<u8>> {
+        let be32 = |offset: usize| u32::from_be_bytes(blob[offset..offset + 4].try_into().unwrap());
+        assert_eq!(be32(0), FDT_MAGIC);
+        assert_eq!(be32(4) as usize, blob.len());
+        let strings = be32(12) as usize;
+
+        let mut properties = HashMap::new();
+        let mut path = Vec::new();
+        let mut offset = be32(8) as usize;
+        loop {
+            let token = be32(offset);
+            offset += 4;
+            match token {
+                // FDT_BEGIN_NODE
+                0x1 => {
+                    let len = blob[offset..].iter().position(|&b| b == 0).unwrap();
+                    path.push(String::from_utf8(blob[offset..offset + len].to_vec()).unwrap());
+                    offset = align_up(offset + len + 1);
+                }
+                // FDT_END_NODE
+                0x2 => {
+                    path.pop().unwrap();
+                }
+                // FDT_PROP
+                0x3 => {
+                    let (len, name) = (be32(offset) as usize, strings + be32(offset + 4) as usize);
+                    let name_len = blob[name..].iter().position(|&b| b == 0).unwrap();
+                    let name = String::from_utf8(blob[name..name + name_len].to_vec()).unwrap();
+
+                    let value = blob[offset + 8..offset + 8 + len].to_vec();
+                    properties.insert(format!("{}/{name}", path.join("/")), value);
+                    offset = align_up(offset + 8 + len);
+                }
+                // FDT_END
+                0x9 => break,
+                token => panic!("unexpected FDT token {token:#x}"),
+            }
+        }
+        assert!(path.is_empty());
+
+        properties
+    }
+
+    fn align_up(offset: usize) -> usize {
+        (offset + 3) & !3
+    }
+
+    #[test]
+    fn elf_loads_image_at_load_address() {
+        for endian in [Endian::Little, Endian::Big] {
+            let image = image();
+            let elf = wrap_elf(&image, LOAD_ADDRESS, TARGET, endian).unwrap();
+            let file = object::File::parse(&*elf).unwrap();
+
+            assert_eq!(file.architecture(), Architecture::Riscv64);
+            assert_eq!(file.is_little_endian(), endian == Endian::Little);
+            assert_eq!(file.entry(), LOAD_ADDRESS);
+
+            let segments: Vec<_> = file.segments().collect();
+            assert_eq!(segments.len(), 1);
+            assert_eq!(segments[0].address(), LOAD_ADDRESS);
+            assert_eq!(segments[0].size(), image.len() as u64);
+            assert_eq!(segments[0].file_range().0 % PAGE_SIZE as u64, 0);
+            assert_eq!(segments[0].data().unwrap(), image);
+        }
+    }
+
+    #[test]
+    fn uimage_carries_valid_checksums() {
+        let image = image();
+        let uimage = wrap_uimage(&image, LOAD_ADDRESS, TARGET, 1234).unwrap();
+        let header = UImageHeader::read_be(&mut Cursor::new(&uimage)).unwrap();
+
+        assert_eq!(header.time, 1234);
+        assert_eq!(header.size as usize, image.len());
+        assert_eq!(
+            (header.load as u64, header.entry as u64),
+            (LOAD_ADDRESS, LOAD_ADDRESS)
+        );
+        assert_eq!(header.arch, 26);
+        assert_eq!(&header.name[..IMAGE_NAME.len()], IMAGE_NAME.as_bytes());
+
+        let (encoded, data) = uimage.split_at(UImageHeader::SIZE);
+        assert_eq!(data, image);
+        assert_eq!(header.data_crc, crc32(data));
+
+        let mut zeroed = encoded.to_vec();
+        zeroed[4..8].fill(0);
+        assert_eq!(header.header_crc, crc32(&zeroed));
+    }
+
+    #[test]
+    fn uimage_rejects_addresses_beyond_32_bits() {
+        assert!(wrap_uimage(&image(), 1 << 32, TARGET, 0).is_err());
+    }
+
+    #[test]
+    fn fit_describes_a_bootable_kernel() {
+        let image = image();
+        let fit = wrap_fit(&image, LOAD_ADDRESS, TARGET, 1234);
+        let properties = fdt_properties(&fit);
+        let property = |path: &str| properties[path].as_slice();
+
+        assert_eq!(property("/timestamp"), 1234u32.to_be_bytes());
+        assert_eq!(property("/images/kernel/data"), image);
+        assert_eq!(property("/images/kernel/type"), b"kernel\0");
+        assert_eq!(property("/images/kernel/arch"), b"riscv\0");
+        assert_eq!(property("/images/kernel/load"), LOAD_ADDRESS.to_be_bytes());
+        assert_eq!(property("/images/kernel/entry"), LOAD_ADDRESS.to_be_bytes());
+        assert_eq!(property("/images/kernel/hash-1/algo"), b"sha256\0");
+        assert_eq!(
+            property("/images/kernel/hash-1/value"),
+            &Sha256::digest(&image)[..]
+        );
+        assert_eq!(property("/configurations/default"), b"conf-1\0");
+        assert_eq!(property("/configurations/conf-1/kernel"), b"kernel\0");
+    }
+}
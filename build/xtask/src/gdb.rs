@@ -0,0 +1,127 @@
+use std::{
+    env,
+    fmt::Write,
+    fs,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{anyhow, bail};
+use xshell::Shell;
+
+use crate::{
+    cargo,
+    config::Config,
+    elf::{self, KernelElf},
+    image::{self, KernelImage},
+};
+
+/// The symbol marking the start of the Kernel Loader in its `start.s`.
+const LOADER_START_SYMBOL: &str = "__onyx_loader_start";
+
+/// The debuggers to try for `xtask gdb`, in order of preference.
+///
+/// `rust-gdb` wraps another GDB given by `RUST_GDB`, which needs to
+/// support the target architecture.
+const DEBUGGERS: &[&str] = &["gdb-multiarch", "gdb"];
+
+/// Writes a GDB script to `path` which attaches to QEMU and loads the
+/// symbols of the kernel and the Kernel Loader at the addresses they
+/// execute from when `image` is loaded as configured.
+///
+/// Both are position-independent and run from wherever the image was
+/// loaded, or extracted to by the decompression stub. The kernel base
+/// is not randomized yet, so this is known ahead of time.
+pub fn write_script(
+    path: &Path,
+    image: &Path,
+    kernel_elf: &Path,
+    loader_elf: &Path,
+    config: &Config,
+) -> anyhow::Result<()> {
+    let (kernel_base, loader_base) = load_addresses(image, config)?;
+    let kernel_offset =
+        kernel_base.wrapping_sub(KernelElf::read(kernel_elf)?.symbol(elf::START_SYMBOL)?);
+    let loader_offset =
+        loader_base.wrapping_sub(KernelElf::read(loader_elf)?.symbol(LOADER_START_SYMBOL)?);
+
+    let mut script = String::new();
+    writeln!(script, "# Generated by `cargo xtask run --gdb`.")?;
+    writeln!(script, "set confirm off")?;
+    // Undo the path remapping of the build, with the most specific
+    // prefix first as GDB applies the first matching rule.
+    for (from, to) in cargo::path_remaps()?.into_iter().rev() {
+        writeln!(script, "set substitute-path {to} {}", from.display())?;
+    }
+    writeln!(
+        script,
+        "add-symbol-file {} -o {kernel_offset:#x}",
+        kernel_elf.display()
+    )?;
+    writeln!(
+        script,
+        "add-symbol-file {} -o {loader_offset:#x}",
+        loader_elf.display()
+    )?;
+    writeln!(script, "target remote localhost:1234")?;
+
+    fs::write(path, script)?;
+    Ok(())
+}
+
+/// Launches GDB with the script written by [`write_script`].
+///
+/// Without an explicit `debugger`, `rust-gdb` is preferred for its
+/// pretty printers when installed.
+pub fn attach(sh: &Shell, script: &Path, debugger: Option<&str>) -> anyhow::Result<()> {
+    if !script.is_file() {
+        bail!(
+            "{} does not exist; start QEMU with `cargo xtask run --gdb` first",
+            script.display()
+        );
+    }
+
+    let (program, backend) = match debugger {
+        Some(debugger) => (debugger, None),
+        None => {
+            let backend = DEBUGGERS
+                .iter()
+                .copied()
+                .find(|d| find_program(d).is_some())
+                .ok_or_else(|| anyhow!("no GDB found, install gdb-multiarch"))?;
+
+            if find_program("rust-gdb").is_some() {
+                ("rust-gdb", Some(backend))
+            } else {
+                (backend, None)
+            }
+        }
+    };
+
+    let mut cmd = sh.cmd(program).arg("-q").arg("-x").arg(script);
+    if let Some(backend) = backend {
+        cmd = cmd.env("RUST_GDB", backend);
+    }
+    cmd.run()?;
+
+    Ok(())
+}
+
+/// Computes the base addresses of the kernel and the Kernel Loader in
+/// memory when `image` is loaded at the configured QEMU address.
+fn load_addresses(image: &Path, config: &Config) -> anyhow::Result<(u64, u64)> {
+    let offset = image::extracted_offset(&fs::read(image)?, config.endian)?;
+    let kernel_base = (config.qemu.address + offset) as u64;
+
+    let (data, _) = image::read_image(image, config.endian)?;
+    let image = KernelImage::parse(&data, config.endian)?;
+    let (_, meta) = image.kernel_meta();
+
+    Ok((kernel_base, kernel_base + meta.loader_base))
+}
+
+fn find_program(name: &str) -> Option<PathBuf> {
+    let paths = env::var_os("PATH")?;
+    env::split_paths(&paths)
+        .map(|dir| dir.join(name))
+        .find(|path| path.is_file())
+}
@@ -1,18 +1,214 @@
 use std::{
     fs,
-    io::{Cursor, Seek, SeekFrom, Write},
+    io::{Cursor, Read, Seek, SeekFrom, Write},
+    ops::Range,
     path::Path,
 };
 
-use anyhow::bail;
-use binrw::{binrw, BinRead, BinWrite, Endian};
-use flate2::{write::GzEncoder, Compression};
+use anyhow::{anyhow, bail};
+use binrw::{BinRead, BinWrite, Endian};
+use ed25519_dalek::{Signer, SigningKey};
+use flate2::{read::GzDecoder, GzBuilder};
 use memchr::memmem;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
 
-const PAGE_SIZE: usize = 0x1000;
+use onyx_core::kip::{self, ByteOrder, Ini1};
+pub use onyx_image::{
+    BuildId, ImageTrailer, Ini1Header, KernelLayout, KernelMeta, Kip1Header, Kip1Segment,
+    StubHeader, KERNEL_MAGIC, STUB_MAGIC,
+};
+
+use crate::{elf::KernelElf, fdt::FDT_MAGIC, verify};
+
+/// The smallest page size that components of an image are aligned to.
+pub const PAGE_SIZE: usize = 0x1000;
+
+/// The magic bytes at the start of every gzip stream.
+const GZIP_MAGIC: [u8; 2] = [0x1F, 0x8B];
+
+/// The gzip header value for an unknown operating system.
+const GZIP_OS_UNKNOWN: u8 = 255;
+
+/// The compression formats supported for Kernel Images.
+///
+/// Compressed images are prefixed by the `onyx-stub` binary, which
+/// extracts the image in memory and transfers control to it. This
+/// keeps them directly executable from their start.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Compression {
+    /// The image is stored uncompressed.
+    #[default]
+    None,
+    /// The image is compressed with gzip.
+    Gzip,
+    /// The image is compressed with the LZ4 block format.
+    Lz4,
+    /// The image is compressed with zstd.
+    Zstd,
+}
+
+impl Compression {
+    /// Gets the format identifier encoded into the [`StubHeader`].
+    pub fn format(self) -> u32 {
+        match self {
+            Self::None => StubHeader::FORMAT_NONE,
+            Self::Gzip => StubHeader::FORMAT_GZIP,
+            Self::Lz4 => StubHeader::FORMAT_LZ4,
+            Self::Zstd => StubHeader::FORMAT_ZSTD,
+        }
+    }
+
+    /// Gets the compression format for a [`StubHeader`] identifier.
+    pub fn from_format(format: u32) -> anyhow::Result<Self> {
+        match format {
+            StubHeader::FORMAT_NONE => Ok(Self::None),
+            StubHeader::FORMAT_GZIP => Ok(Self::Gzip),
+            StubHeader::FORMAT_LZ4 => Ok(Self::Lz4),
+            StubHeader::FORMAT_ZSTD => Ok(Self::Zstd),
+            _ => bail!("unknown compression format {format}"),
+        }
+    }
+
+    /// Gets the `onyx-stub` cargo feature that enables the decoder
+    /// for this format, if any is needed.
+    pub fn stub_feature(self) -> Option<&'static str> {
+        match self {
+            Self::None => None,
+            Self::Gzip => Some("gzip"),
+            Self::Lz4 => Some("lz4"),
+            Self::Zstd => Some("zstd"),
+        }
+    }
+
+    /// Compresses the given data in this format.
+    pub fn compress(self, data: &[u8]) -> anyhow::Result<Vec<u8>> {
+        match self {
+            Self::None => Ok(data.to_vec()),
+            Self::Gzip => {
+                // Pin the header fields which may otherwise record the
+                // time or the host OS, for reproducible images.
+                let mut encoder = GzBuilder::new()
+                    .mtime(0)
+                    .operating_system(GZIP_OS_UNKNOWN)
+                    .write(Vec::new(), flate2::Compression::default());
+                encoder.write_all(data)?;
+                Ok(encoder.finish()?)
+            }
+            Self::Lz4 => Ok(lz4_flex::block::compress(data)),
+            Self::Zstd => Ok(zstd::bulk::compress(data, zstd::DEFAULT_COMPRESSION_LEVEL)?),
+        }
+    }
+
+    /// Decompresses data in this format to its original `size`.
+    pub fn decompress(self, data: &[u8], size: usize) -> anyhow::Result<Vec<u8>> {
+        let decompressed = match self {
+            Self::None => data.to_vec(),
+            Self::Gzip => {
+                let mut decompressed = Vec::with_capacity(size);
+                GzDecoder::new(data).read_to_end(&mut decompressed)?;
+                decompressed
+            }
+            Self::Lz4 => lz4_flex::block::decompress(data, size)?,
+            Self::Zstd => zstd::bulk::decompress(data, size)?,
+        };
+
+        if decompressed.len() != size {
+            bail!(
+                "decompressed image has size {:#x}, expected {size:#x}",
+                decompressed.len()
+            );
+        }
+        Ok(decompressed)
+    }
+}
+
+/// Reads a Kernel Image from the given path.
+///
+/// Compressed images will be transparently decompressed, so the
+/// returned buffer always holds the raw image as it is laid out
+/// in memory. The compression format of the file is returned
+/// alongside it.
+pub fn read_image<P: AsRef<Path>>(
+    path: P,
+    endian: Endian,
+) -> anyhow::Result<(Vec<u8>, Compression)> {
+    let image = fs::read(path)?;
+
+    // Plain gzip streams are produced by older versions of xtask.
+    if image.starts_with(&GZIP_MAGIC) {
+        let mut decompressed = Vec::new();
+        GzDecoder::new(&image[..]).read_to_end(&mut decompressed)?;
+        return Ok((decompressed, Compression::Gzip));
+    }
+
+    let Some(offset) = find_stub_header(&image) else {
+        return Ok((image, Compression::None));
+    };
 
-/// The metadata magic of the Onyx kernel binary.
-pub const KERNEL_MAGIC: [u8; 4] = *b"ONYX";
+    let header = StubHeader::read_options(&mut Cursor::new(&image[offset..]), endian, ())?;
+    let payload = image
+        .get(header.payload_offset as usize..)
+        .and_then(|p| p.get(..header.payload_size as usize))
+        .ok_or_else(|| anyhow!("compressed payload exceeds image bounds"))?;
+
+    let compression = Compression::from_format(header.format)?;
+    Ok((
+        compression.decompress(payload, header.image_size as usize)?,
+        compression,
+    ))
+}
+
+/// Gets the offset from the start of an image file at which the raw
+/// image is laid out in memory.
+///
+/// This is non-zero for compressed images, which the stub extracts to
+/// the page behind their payload.
+pub fn extracted_offset(image: &[u8], endian: Endian) -> anyhow::Result<usize> {
+    let Some(offset) = find_stub_header(image) else {
+        return Ok(0);
+    };
+
+    let header = StubHeader::read_options(&mut Cursor::new(&image[offset..]), endian, ())?;
+    Ok(header.image_offset as usize)
+}
+
+/// Finds the offset of the [`StubHeader`] in an `onyx-stub` binary,
+/// if the given data starts with one.
+pub fn find_stub_header(stub: &[u8]) -> Option<usize> {
+    // The header directly follows the initial jump instruction.
+    let window = &stub[..stub.len().min(0x10)];
+    memmem::find(window, &STUB_MAGIC)
+}
+
+/// Reads an Ed25519 signing key for Kernel Images from the given path.
+///
+/// The file is expected to hold the raw 32-byte secret key.
+pub fn read_signing_key<P: AsRef<Path>>(path: P) -> anyhow::Result<SigningKey> {
+    let path = path.as_ref();
+
+    let key = fs::read(path)?;
+    let key = key.try_into().map_err(|key: Vec<u8>| {
+        anyhow!(
+            "expected 32-byte signing key in {}, got {} bytes",
+            path.display(),
+            key.len()
+        )
+    })?;
+
+    Ok(SigningKey::from_bytes(&key))
+}
+
+/// Finds the offset of the [`KernelMeta`] structure in a kernel binary.
+///
+/// The plausibility of the offset is checked by [`verify::check_kernel`].
+pub fn find_kernel_meta(kernel: &[u8]) -> anyhow::Result<usize> {
+    let finder = memmem::Finder::new(&KERNEL_MAGIC);
+    finder
+        .find(kernel)
+        .ok_or_else(|| anyhow!("Malformed kernel binary!"))
+}
 
 /// Representation of an Onyx Kernel Image.
 ///
@@ -24,14 +220,28 @@ pub const KERNEL_MAGIC: [u8; 4] = *b"ONYX";
 /// from its start after loading it into memory.
 pub struct KernelImage {
     endian: Endian,
-    compress: bool,
+    page_size: usize,
+    compression: Compression,
+    stub: Option<(usize, StubHeader, Vec<u8>)>,
 
     kernel: Vec<u8>,
     kernel_meta: (usize, KernelMeta),
 
     loader: Vec<u8>,
+    loader_memory_size: usize,
+
+    kips: Vec<Vec<u8>>,
+
+    dtb: Option<Vec<u8>>,
+    cmdline: Option<String>,
+
+    checksum: bool,
+    signing_key: Option<SigningKey>,
+    trailer: Option<ImageTrailer>,
 
     version: u32,
+    revision: u32,
+    build_id: Option<BuildId>,
 }
 
 impl KernelImage {
@@ -39,64 +249,350 @@ impl KernelImage {
     pub fn new() -> Self {
         Self {
             endian: Endian::Little,
-            compress: false,
+            page_size: PAGE_SIZE,
+            compression: Compression::None,
+            stub: None,
 
             kernel: Vec::new(),
             kernel_meta: (0, KernelMeta::default()),
 
             loader: Vec::new(),
+            loader_memory_size: 0,
+
+            kips: Vec::new(),
+
+            dtb: None,
+            cmdline: None,
+
+            checksum: false,
+            signing_key: None,
+            trailer: None,
 
             version: 0,
+            revision: KernelMeta::REVISION,
+            build_id: None,
         }
     }
 
+    /// Parses a finished, uncompressed Kernel Image back into its
+    /// individual components.
+    ///
+    /// The result will produce the same image when encoded again.
+    /// Signed images additionally need the signing key configured
+    /// through [`KernelImage::with_signing_key`] to achieve this.
+    pub fn parse(image: &[u8], endian: Endian) -> anyhow::Result<Self> {
+        let meta_offset = find_kernel_meta(image)?;
+        let meta = KernelMeta::read_options(&mut Cursor::new(&image[meta_offset..]), endian, ())?;
+        let has_loader_end = meta.loader_end().is_some();
+
+        let kernel = image
+            .get(..meta.kernel_size as usize)
+            .ok_or_else(|| anyhow!("kernel size exceeds image bounds"))?;
+        let loader = image
+            .get(meta.loader_base as usize..)
+            .and_then(|l| l.get(..meta.loader_size as usize))
+            .ok_or_else(|| anyhow!("Kernel Loader exceeds image bounds"))?;
+
+        let kips = Ini1::new(image, meta.kip1_base as usize, byte_order(endian))
+            .and_then(|kips| kips.map(|kip| Ok(kip?.data.to_vec())).collect())
+            .map_err(|e| anyhow!("malformed KIP1 list: {e}"))?;
+
+        let trailer = match meta.trailer_base() {
+            None => None,
+            Some(base) => {
+                let mut cursor = Cursor::new(image);
+                cursor.set_position(base);
+                Some(ImageTrailer::read_options(&mut cursor, endian, ())?)
+            }
+        };
+
+        let dtb = match meta.dtb() {
+            None => None,
+            Some((base, size)) => Some(
+                image
+                    .get(base as usize..)
+                    .and_then(|d| d.get(..size as usize))
+                    .ok_or_else(|| anyhow!("device tree exceeds image bounds"))?
+                    .to_vec(),
+            ),
+        };
+
+        let cmdline = match meta.cmdline() {
+            None => None,
+            Some((base, size)) => {
+                let cmdline = image
+                    .get(base as usize..)
+                    .and_then(|c| c.get(..size as usize))
+                    .ok_or_else(|| anyhow!("kernel command line exceeds image bounds"))?;
+                Some(String::from_utf8(cmdline.to_vec())?)
+            }
+        };
+
+        let build_id = match meta.build_id_base() {
+            None => None,
+            Some(base) => {
+                let mut cursor = Cursor::new(image);
+                cursor.set_position(base);
+                Some(BuildId::read_options(&mut cursor, endian, ())?)
+            }
+        };
+
+        // Revisions before 6 do not record the end of the Kernel Loader
+        // in memory, but everything that follows was placed behind it.
+        let loader_end = meta.loader_end().unwrap_or_else(|| {
+            let following = [
+                meta.kip1_base,
+                meta.dtb().map_or(0, |(base, _)| base),
+                meta.cmdline().map_or(0, |(base, _)| base),
+                meta.build_id_base().unwrap_or(0),
+                meta.trailer_base().unwrap_or(0),
+            ];
+            following
+                .into_iter()
+                .filter(|&base| base > meta.loader_base)
+                .chain([image.len().saturating_sub(PAGE_SIZE) as u64])
+                .min()
+                .unwrap()
+        });
+        let loader_memory_size = loader_end
+            .checked_sub(meta.loader_base)
+            .filter(|&size| size >= meta.loader_size)
+            .ok_or_else(|| anyhow!("Kernel Loader ends at {loader_end:#x} before its binary"))?;
+
+        let mut parsed = Self {
+            endian,
+            page_size: PAGE_SIZE,
+            compression: Compression::None,
+            stub: None,
+
+            kernel: kernel.to_vec(),
+            // The low byte is derived from the build ID when encoding.
+            version: meta.version & !0xFF,
+            revision: meta.revision,
+            build_id,
+            kernel_meta: (meta_offset, meta),
+
+            loader: loader.to_vec(),
+            loader_memory_size: loader_memory_size as usize,
+
+            kips,
+
+            dtb,
+            cmdline,
+
+            checksum: trailer.is_some(),
+            signing_key: None,
+            trailer,
+        };
+
+        // Images end in a page of padding behind the aligned end of
+        // their components, which reveals the page size they were
+        // encoded with. Without the end of the Kernel Loader in memory,
+        // that end is unknown, but such images always used `PAGE_SIZE`.
+        if has_loader_end {
+            let end = parsed
+                .components()
+                .into_iter()
+                .map(|(_, range)| range.end)
+                .chain([loader_end])
+                .max()
+                .unwrap() as usize;
+            parsed.page_size = (PAGE_SIZE.trailing_zeros()..usize::BITS)
+                .map(|shift| 1 << shift)
+                .take_while(|&page_size| page_size <= image.len())
+                .find(|&page_size| align_up(end, page_size) + page_size == image.len())
+                .unwrap_or(PAGE_SIZE);
+        }
+
+        Ok(parsed)
+    }
+
     /// Configures the endianness to use for encoding data.
     pub fn with_endian(mut self, endian: Endian) -> Self {
         self.endian = endian;
         self
     }
 
+    /// Configures the page size to align the components of the image
+    /// to, which must be a power of two of at least [`PAGE_SIZE`].
+    pub fn with_page_size(mut self, page_size: usize) -> Self {
+        self.page_size = page_size;
+        self
+    }
+
     /// Configures a version for the Onyx release.
     pub fn with_version(mut self, major: u8, minor: u8, patch: u8) -> Self {
         self.version = ((major as u32) << 24) | ((minor as u32) << 16) | ((patch as u32) << 8);
         self
     }
 
-    /// Configures whether the kernel image should be compressed.
-    pub fn with_compression(mut self, enable: bool) -> Self {
-        self.compress = enable;
+    /// Configures the [`BuildId`] to embed into the image.
+    ///
+    /// Its flags are mirrored into the low byte of the version.
+    pub fn with_build_id(mut self, build_id: BuildId) -> Self {
+        self.build_id = Some(build_id);
+        self
+    }
+
+    /// Configures the command line to embed for the kernel.
+    ///
+    /// Firmware may override this through `/chosen/bootargs`.
+    pub fn with_cmdline(mut self, cmdline: String) -> Self {
+        self.cmdline = Some(cmdline);
+        self
+    }
+
+    /// Configures the [`KernelMeta`] format revision to emit.
+    ///
+    /// Older revisions keep images bootable by older Kernel Loaders,
+    /// but cannot carry the fields added since.
+    pub fn with_revision(mut self, revision: u32) -> Self {
+        self.revision = revision;
+        self
+    }
+
+    /// Configures the compression format for the kernel image.
+    ///
+    /// Compressed images require an `onyx-stub` binary to be packed
+    /// through [`KernelImage::pack_stub`].
+    pub fn with_compression(mut self, compression: Compression) -> Self {
+        self.compression = compression;
+        self
+    }
+
+    /// Configures whether an [`ImageTrailer`] with a SHA-256 digest of
+    /// the image should be appended.
+    pub fn with_checksum(mut self, enable: bool) -> Self {
+        self.checksum = enable;
         self
     }
 
+    /// Configures an Ed25519 key to sign the image digest with.
+    ///
+    /// This implies [`KernelImage::with_checksum`].
+    pub fn with_signing_key(mut self, key: SigningKey) -> Self {
+        self.checksum = true;
+        self.signing_key = Some(key);
+        self
+    }
+
+    /// Gets the page size the components of the image are aligned to.
+    pub fn page_size(&self) -> usize {
+        self.page_size
+    }
+
+    /// Gets the [`ImageTrailer`] of a parsed image, if it has one.
+    pub fn trailer(&self) -> Option<&ImageTrailer> {
+        self.trailer.as_ref()
+    }
+
+    /// Gets the [`BuildId`] of this image, if it has one.
+    pub fn build_id(&self) -> Option<&BuildId> {
+        self.build_id.as_ref()
+    }
+
+    /// Gets the raw `onyx` binary in this image.
+    pub fn kernel(&self) -> &[u8] {
+        &self.kernel
+    }
+
+    /// Gets the [`KernelMeta`] of this image and its offset in the
+    /// kernel binary.
+    pub fn kernel_meta(&self) -> (usize, &KernelMeta) {
+        (self.kernel_meta.0, &self.kernel_meta.1)
+    }
+
+    /// Gets the raw `onyx-loader` binary in this image.
+    pub fn loader(&self) -> &[u8] {
+        &self.loader
+    }
+
+    /// Gets the KIP1 binaries in this image.
+    pub fn kips(&self) -> &[Vec<u8>] {
+        &self.kips
+    }
+
+    /// Gets the embedded device tree blob, if there is one.
+    pub fn dtb(&self) -> Option<&[u8]> {
+        self.dtb.as_deref()
+    }
+
+    /// Gets the embedded kernel command line, if there is one.
+    pub fn cmdline(&self) -> Option<&str> {
+        self.cmdline.as_deref()
+    }
+
+    /// Gets the names and byte ranges of all components in a parsed
+    /// image, starting with the kernel.
+    pub fn components(&self) -> Vec<(&'static str, Range<u64>)> {
+        let (_, meta) = self.kernel_meta();
+        let mut components = vec![
+            ("kernel", 0..self.kernel.len() as u64),
+            (
+                "Kernel Loader",
+                meta.loader_base..meta.loader_base + self.loader.len() as u64,
+            ),
+        ];
+        if !self.kips.is_empty() {
+            let size = Ini1Header::SIZE + self.kips.iter().map(Vec::len).sum::<usize>();
+            components.push(("KIP1 list", meta.kip1_base..meta.kip1_base + size as u64));
+        }
+        if let Some((base, size)) = meta.dtb() {
+            components.push(("device tree", base..base + size));
+        }
+        if let Some((base, size)) = meta.cmdline() {
+            // Account for the NUL terminator.
+            components.push(("command line", base..base + size + 1));
+        }
+        if let Some(base) = meta.build_id_base() {
+            components.push(("build ID", base..base + BuildId::SIZE as u64));
+        }
+        if self.trailer.is_some() {
+            let base = meta.trailer_base;
+            components.push(("image trailer", base..base + ImageTrailer::SIZE as u64));
+        }
+
+        components
+    }
+
     /// Packs an `onyx` binary into the kernel image.
     ///
+    /// The [`KernelMeta`] is located in the raw binary `kernel` through
+    /// the symbols of the `elf` it was produced from.
+    ///
     /// This must always be provided before calling [`KernelImage::finish`].
-    pub fn pack_kernel<P: AsRef<Path>>(mut self, kernel: P) -> anyhow::Result<Self> {
+    pub fn pack_kernel<P: AsRef<Path>, Q: AsRef<Path>>(
+        self,
+        elf: P,
+        kernel: Q,
+    ) -> anyhow::Result<Self> {
         let kernel = fs::read(kernel)?;
+        let meta_offset = KernelElf::read(elf)?.kernel_meta_offset(&kernel)?;
 
-        // We try to find the metadata offset for the kernel first.
-        // However ,it must not be at 0 because the image needs to
-        // start with executable code. At the same time, it is fair
-        // to assume it's a logic bug when metadata are *too* far in.
-        let finder = memmem::Finder::new(&KERNEL_MAGIC);
-        let meta_offset = match finder.find(&kernel) {
-            Some(off) if off == 0 || off > 0x10 => {
-                bail!("suspicious metadata offset found; please confirm");
-            }
-            Some(off) => off,
-            None => bail!("Malformed kernel binary!"),
-        };
+        self.pack_kernel_bytes(kernel, meta_offset)
+    }
 
-        // Now deserialize the kernel meta blob.
-        let mut cursor = Cursor::new(&kernel[meta_offset..]);
+    /// Packs an in-memory `onyx` binary into the kernel image, with
+    /// its [`KernelMeta`] at `meta_offset`.
+    ///
+    /// See [`KernelImage::pack_kernel`] for details.
+    pub fn pack_kernel_bytes(
+        mut self,
+        kernel: Vec<u8>,
+        meta_offset: usize,
+    ) -> anyhow::Result<Self> {
+        // Deserialize the kernel meta blob.
+        let meta = kernel
+            .get(meta_offset..)
+            .ok_or_else(|| anyhow!("Malformed kernel binary!"))?;
+        let mut cursor = Cursor::new(meta);
         let meta = KernelMeta::read_options(&mut cursor, self.endian, ())?;
 
-        // Confirm some assertions about the memory layout of the kernel.
-        assert!(kernel.len() <= meta.layout.kernel_end as usize);
-        assert!(meta.layout.text_start <= meta.layout.text_end);
-        assert!(meta.layout.rodata_start <= meta.layout.rodata_end);
-        assert!(meta.layout.data_start <= meta.layout.data_end);
-        assert!(meta.layout.bss_start <= meta.layout.bss_end);
+        // Confirm the invariants of the memory layout of the kernel.
+        verify::ensure_valid(
+            "kernel layout",
+            verify::check_kernel(meta_offset, kernel.len(), &meta),
+        )?;
 
         // Store the kernel along with its meta.
         self.kernel = kernel;
@@ -107,116 +603,363 @@ impl KernelImage {
 
     /// Packs an `onyx-loader` binary into the image.
     ///
+    /// The size of the loader in memory, including its .bss section
+    /// and stack, is taken from the `elf` the raw binary `loader` was
+    /// produced from.
+    ///
     /// This must always be provided before calling [`KernelImage::finish`].
-    pub fn pack_loader<P: AsRef<Path>>(mut self, loader: P) -> anyhow::Result<Self> {
+    pub fn pack_loader<P: AsRef<Path>, Q: AsRef<Path>>(
+        self,
+        elf: P,
+        loader: Q,
+    ) -> anyhow::Result<Self> {
         let loader = fs::read(loader)?;
+        let memory_size = KernelElf::read(elf)?.memory_size()?;
+
+        self.pack_loader_bytes(loader, memory_size as usize)
+    }
+
+    /// Packs an in-memory `onyx-loader` binary into the image, which
+    /// occupies `memory_size` bytes when running.
+    ///
+    /// See [`KernelImage::pack_loader`] for details.
+    pub fn pack_loader_bytes(
+        mut self,
+        loader: Vec<u8>,
+        memory_size: usize,
+    ) -> anyhow::Result<Self> {
+        if memory_size < loader.len() {
+            bail!(
+                "Kernel Loader of size {:#x} exceeds its size in memory of {memory_size:#x}",
+                loader.len()
+            );
+        }
+
         self.loader = loader;
+        self.loader_memory_size = memory_size;
+        Ok(self)
+    }
+
+    /// Packs an `onyx-stub` binary to prefix compressed images with.
+    pub fn pack_stub<P: AsRef<Path>>(self, stub: P) -> anyhow::Result<Self> {
+        self.pack_stub_bytes(fs::read(stub)?)
+    }
+
+    /// Packs an in-memory `onyx-stub` binary to prefix compressed
+    /// images with.
+    pub fn pack_stub_bytes(mut self, stub: Vec<u8>) -> anyhow::Result<Self> {
+        let offset =
+            find_stub_header(&stub).ok_or_else(|| anyhow!("Malformed decompression stub!"))?;
+        let header = StubHeader::read_options(&mut Cursor::new(&stub[offset..]), self.endian, ())?;
+        if stub.len() as u64 > header.end {
+            bail!(
+                "decompression stub exceeds its own end at {:#x}",
+                header.end
+            );
+        }
 
+        self.stub = Some((offset, header, stub));
         Ok(self)
     }
 
-    /// Finishes the image construction and writes the resulting blob to
-    /// the given output path.
-    pub fn finish<P: AsRef<Path>>(mut self, out: P) -> anyhow::Result<()> {
+    /// Packs a KIP1 binary into the list of Kernel Initial Processes.
+    ///
+    /// Processes will be launched in the order they are packed.
+    pub fn pack_kip<P: AsRef<Path>>(self, kip: P) -> anyhow::Result<Self> {
+        self.pack_kip_bytes(fs::read(kip)?)
+    }
+
+    /// Packs an in-memory KIP1 binary into the list of Kernel Initial
+    /// Processes.
+    pub fn pack_kip_bytes(mut self, kip: Vec<u8>) -> anyhow::Result<Self> {
+        let header = kip::read_kip1(&kip, byte_order(self.endian))
+            .map_err(|e| anyhow!("malformed KIP1 binary: {e}"))?;
+        if header.file_size() != kip.len() {
+            bail!(
+                "KIP1 binary for `{}` has size {:#x}, but its header describes {:#x}",
+                header.name(),
+                kip.len(),
+                header.file_size()
+            );
+        }
+
+        self.kips.push(kip);
+        Ok(self)
+    }
+
+    /// Packs an in-memory device tree blob for boards whose firmware
+    /// does not pass one to the kernel.
+    pub fn pack_dtb_bytes(mut self, dtb: Vec<u8>) -> anyhow::Result<Self> {
+        if dtb.get(..4) != Some(&FDT_MAGIC.to_be_bytes()[..]) {
+            bail!("Malformed device tree blob!");
+        }
+
+        self.dtb = Some(dtb);
+        Ok(self)
+    }
+
+    /// Encodes the image into its uncompressed binary representation.
+    pub fn encode(&self) -> anyhow::Result<Vec<u8>> {
         if self.kernel_meta.0 == 0 || self.loader.is_empty() {
             bail!("cannot build kernel image without Kernel or Loader");
         }
+        if !self.page_size.is_power_of_two() || self.page_size < PAGE_SIZE {
+            bail!(
+                "page size {:#x} is not a power of two of at least {PAGE_SIZE:#x}",
+                self.page_size
+            );
+        }
+        let page_size = self.page_size;
 
-        // Calculate the start and end offsets of the Kernel Loader.
-        let loader_start = align_up(self.kernel.len(), PAGE_SIZE);
-        let loader_end = loader_start + self.loader.len();
+        // Calculate the start and end offsets of the Kernel Loader. It
+        // runs before the kernel clears its .bss section, but both must
+        // not overlap in memory.
+        let kernel_end = self
+            .kernel
+            .len()
+            .max(self.kernel_meta.1.layout.kernel_end as usize);
+        let loader_start = align_up(kernel_end, page_size);
+        let loader_end = loader_start + self.loader_memory_size;
+
+        // Calculate the start and end offsets of the KIP1 list, if any.
+        // This and everything after it is placed behind the memory of
+        // the Kernel Loader, so that it survives the loader clearing
+        // its .bss section.
+        let kip1_start = align_up(loader_end, page_size);
+        let kip1_end = if self.kips.is_empty() {
+            kip1_start
+        } else {
+            kip1_start + Ini1Header::SIZE + self.kips.iter().map(Vec::len).sum::<usize>()
+        };
+
+        // Calculate the start and end offsets of the device tree, if any.
+        let dtb_start = align_up(kip1_end, page_size);
+        let dtb_end = match &self.dtb {
+            Some(dtb) => dtb_start + dtb.len(),
+            None => kip1_end,
+        };
+
+        // Calculate the start and end offsets of the command line, if
+        // any. The end includes its NUL terminator.
+        let cmdline_start = align_up(dtb_end, page_size);
+        let cmdline_end = match &self.cmdline {
+            Some(cmdline) => cmdline_start + cmdline.len() + 1,
+            None => dtb_end,
+        };
+
+        // Calculate the start and end offsets of the build ID, if any.
+        let build_id_start = align_up(cmdline_end, page_size);
+        let build_id_end = if self.build_id.is_some() {
+            build_id_start + BuildId::SIZE
+        } else {
+            cmdline_end
+        };
+
+        // The trailer, if any, is placed at an aligned offset after
+        // all other components.
+        let trailer_start = align_up(build_id_end, page_size);
+        let image_end = if self.checksum {
+            trailer_start + ImageTrailer::SIZE
+        } else {
+            trailer_start
+        };
+
+        // Confirm that the requested revision fits into the space the
+        // kernel reserved for its metadata.
+        let header_size = KernelMeta::header_size_of(self.revision)
+            .ok_or_else(|| anyhow!("unknown KernelMeta revision {}", self.revision))?;
+        if header_size > self.kernel_meta.1.header_size as usize {
+            bail!(
+                "KernelMeta revision {} does not fit into the {:#x} bytes reserved by the kernel",
+                self.revision,
+                self.kernel_meta.1.header_size
+            );
+        }
+        if self.checksum && self.revision < 2 {
+            bail!(
+                "KernelMeta revision {} cannot reference an image trailer",
+                self.revision
+            );
+        }
+        if self.build_id.is_some() && self.revision < 3 {
+            bail!(
+                "KernelMeta revision {} cannot reference a build ID",
+                self.revision
+            );
+        }
+
+        if self.dtb.is_some() && self.revision < 4 {
+            bail!(
+                "KernelMeta revision {} cannot reference a device tree",
+                self.revision
+            );
+        }
+
+        if self.cmdline.is_some() && self.revision < 5 {
+            bail!(
+                "KernelMeta revision {} cannot reference a kernel command line",
+                self.revision
+            );
+        }
+        if self.cmdline.as_ref().map_or(false, |c| c.contains('\0')) {
+            bail!("kernel command line must not contain NUL bytes");
+        }
 
         // Update our header accordingly.
-        self.kernel_meta.1.loader_base = loader_start as u64;
-        self.kernel_meta.1.version = self.version;
+        let mut meta = self.kernel_meta.1.clone();
+        meta.revision = self.revision;
+        meta.header_size = header_size as u32;
+        meta.kip1_base = if self.kips.is_empty() {
+            0
+        } else {
+            kip1_start as u64
+        };
+        meta.loader_base = loader_start as u64;
+        meta.loader_size = self.loader.len() as u64;
+        meta.loader_end = loader_end as u64;
+        meta.kernel_size = self.kernel.len() as u64;
+        meta.trailer_base = if self.checksum {
+            trailer_start as u64
+        } else {
+            0
+        };
+        meta.build_id_base = if self.build_id.is_some() {
+            build_id_start as u64
+        } else {
+            0
+        };
+        (meta.dtb_base, meta.dtb_size) = match &self.dtb {
+            Some(dtb) => (dtb_start as u64, dtb.len() as u64),
+            None => (0, 0),
+        };
+        (meta.cmdline_base, meta.cmdline_size) = match &self.cmdline {
+            Some(cmdline) => (cmdline_start as u64, cmdline.len() as u64),
+            None => (0, 0),
+        };
+        meta.version = self.version | self.build_id.as_ref().map_or(0, |id| id.flags as u8 as u32);
 
         // Now build the resulting image blob.
         let mut image = Cursor::new(Vec::new());
         {
-            // Write the initial bits of kernel code.
-            image.write_all(&self.kernel[..self.kernel_meta.0])?;
-
-            // Re-serialize the kernel metadata.
-            self.kernel_meta
-                .1
-                .write_options(&mut image, self.endian, ())?;
-
-            // Write the rest of the kernel code.
-            image.write_all(&self.kernel[(self.kernel_meta.0 + self.kernel_meta.1.size())..])?;
+            // Write the kernel code and re-serialize its metadata in
+            // place. Bytes beyond the emitted revision are preserved.
+            image.write_all(&self.kernel)?;
+            image.seek(SeekFrom::Start(self.kernel_meta.0 as u64))?;
+            meta.write_options(&mut image, self.endian, ())?;
 
             // Write the Kernel Loader code.
             image.seek(SeekFrom::Start(loader_start as u64))?;
             image.write_all(&self.loader)?;
 
-            // Append trailing padding at an aligned image end.
-            image.seek(SeekFrom::Start(align_up(loader_end, PAGE_SIZE) as u64))?;
-            image.write_all(&[0; PAGE_SIZE])?;
-        }
+            // Write the KIP1 list, prefixed by its INI1 header.
+            if !self.kips.is_empty() {
+                let ini1 = Ini1Header {
+                    size: (kip1_end - kip1_start) as u32,
+                    process_count: self.kips.len() as u32,
+                    ..Default::default()
+                };
 
-        // Write the image to the output file.
-        let mut output = fs::OpenOptions::new().write(true).create(true).open(out)?;
-        if self.compress {
-            let mut encoder = GzEncoder::new(&mut output, Compression::default());
-            encoder.write_all(image.get_ref())?;
-            encoder.finish()?;
-        } else {
-            output.write_all(image.get_ref())?;
+                image.seek(SeekFrom::Start(kip1_start as u64))?;
+                ini1.write_options(&mut image, self.endian, ())?;
+                for kip in &self.kips {
+                    image.write_all(kip)?;
+                }
+            }
+
+            // Write the device tree blob.
+            if let Some(dtb) = &self.dtb {
+                image.seek(SeekFrom::Start(dtb_start as u64))?;
+                image.write_all(dtb)?;
+            }
+
+            // Write the NUL-terminated command line.
+            if let Some(cmdline) = &self.cmdline {
+                image.seek(SeekFrom::Start(cmdline_start as u64))?;
+                image.write_all(cmdline.as_bytes())?;
+                image.write_all(&[0])?;
+            }
+
+            // Write the build ID into its own section.
+            if let Some(build_id) = &self.build_id {
+                image.seek(SeekFrom::Start(build_id_start as u64))?;
+                build_id.write_options(&mut image, self.endian, ())?;
+            }
+
+            // Append the trailer with the digest of everything before it.
+            if self.checksum {
+                image.get_mut().resize(trailer_start, 0);
+                let digest: [u8; 32] = Sha256::digest(image.get_ref()).into();
+
+                let trailer = match &self.signing_key {
+                    Some(key) => ImageTrailer {
+                        flags: ImageTrailer::FLAG_SIGNED,
+                        digest,
+                        signature: key.sign(&digest).to_bytes(),
+                        ..Default::default()
+                    },
+                    None => ImageTrailer {
+                        digest,
+                        ..Default::default()
+                    },
+                };
+
+                image.seek(SeekFrom::Start(trailer_start as u64))?;
+                trailer.write_options(&mut image, self.endian, ())?;
+            }
+
+            // Append a page of trailing padding at an aligned image end.
+            image
+                .get_mut()
+                .resize(align_up(image_end, page_size) + page_size, 0);
         }
 
-        Ok(())
+        Ok(image.into_inner())
     }
-}
 
-/// Encoded kernel metadata.
-#[derive(Debug, Default)]
-#[binrw]
-#[brw(magic = b"ONYX")]
-pub struct KernelMeta {
-    /// The offset to the serialized KIP1 blob which holds all
-    /// Kernel Initial Processes.
-    pub kip1_base: u64,
-    /// The base address of the Kernel Loader binary.
-    pub loader_base: u64,
-    /// The current kernel version.
-    pub version: u32,
-    /// The memory layout of the kernel binary.
-    pub layout: KernelLayout,
-}
+    /// Finishes the image construction and writes the resulting blob to
+    /// the given output path.
+    pub fn finish<P: AsRef<Path>>(self, out: P) -> anyhow::Result<()> {
+        let image = self.encode()?;
+        if self.compression == Compression::None {
+            fs::write(out, image)?;
+            return Ok(());
+        }
+
+        let Some((offset, mut header, stub)) = self.stub else {
+            bail!("cannot compress kernel image without a decompression stub");
+        };
+        let payload = self.compression.compress(&image)?;
+
+        // Place the payload behind the stub's memory footprint, so
+        // that it survives the stub clearing its .bss section.
+        header.format = self.compression.format();
+        header.payload_offset = align_up(header.end as usize, self.page_size) as u64;
+        header.payload_size = payload.len() as u64;
+        header.image_size = image.len() as u64;
 
-impl KernelMeta {
-    #[inline]
-    fn size(&self) -> usize {
-        use std::mem::size_of;
+        // The stub extracts the image to the next page boundary.
+        let payload_end = (header.payload_offset + header.payload_size) as usize;
+        header.image_offset = align_up(payload_end, self.page_size) as u64;
 
-        // (kip1_base + loader_base) + (magic + version) + KernelLayout
-        (size_of::<u64>() * 2) + (size_of::<u32>() * 2) + (size_of::<u32>() * 10)
+        let mut output = Cursor::new(stub);
+        output.seek(SeekFrom::Start(offset as u64))?;
+        header.write_options(&mut output, self.endian, ())?;
+
+        let mut output = output.into_inner();
+        output.resize(header.payload_offset as usize, 0);
+        output.extend_from_slice(&payload);
+
+        fs::write(out, output)?;
+        Ok(())
     }
 }
 
-/// The memory layout of the kernel binary.
-#[derive(Debug, Default)]
-#[binrw]
-pub struct KernelLayout {
-    /// Start of the kernel .text section.
-    pub text_start: u32,
-    /// End of the kernel .text section.
-    pub text_end: u32,
-    /// Start of the kernel .rodata section.
-    pub rodata_start: u32,
-    /// End of the kernel .rodata section.
-    pub rodata_end: u32,
-    /// Start of the kernel .data section.
-    pub data_start: u32,
-    /// End of the kernel .data section.
-    pub data_end: u32,
-    /// Start of the kernel .bss section.
-    pub bss_start: u32,
-    /// End of the kernel .bss section.
-    pub bss_end: u32,
-    /// End of the kernel blob.
-    pub kernel_end: u32,
-    /// Start of the _DYNAMIC section.
-    pub dynamic_start: u32,
+/// Gets the [`ByteOrder`] matching an [`Endian`].
+pub fn byte_order(endian: Endian) -> ByteOrder {
+    match endian {
+        Endian::Little => ByteOrder::Little,
+        Endian::Big => ByteOrder::Big,
+    }
 }
 
 #[inline(always)]
@@ -224,3 +967,260 @@ const fn align_up(value: usize, align: usize) -> usize {
     debug_assert!(align.is_power_of_two());
     (value + align - 1) & !(align - 1)
 }
+
+#[cfg(test)]
+mod tests {
+    use proptest::{collection::vec, option, prelude::*};
+
+    use super::*;
+
+    fn endian() -> impl Strategy<Value = Endian> {
+        prop_oneof![Just(Endian::Little), Just(Endian::Big)]
+    }
+
+    fn layout(kernel_len: usize) -> impl Strategy<Value = KernelLayout> {
+        (vec(0..0x100u32, 8), 0..0x10u32, any::<u32>()).prop_map(
+            move |(mut pages, extra_pages, dynamic_start)| {
+                pages.sort_unstable();
+                let bounds: Vec<_> = pages.into_iter().map(|p| p * PAGE_SIZE as u32).collect();
+
+                let kernel_end = bounds[7].max(align_up(kernel_len, PAGE_SIZE) as u32)
+                    + extra_pages * PAGE_SIZE as u32;
+                KernelLayout {
+                    text_start: bounds[0],
+                    text_end: bounds[1],
+                    rodata_start: bounds[2],
+                    rodata_end: bounds[3],
+                    data_start: bounds[4],
+                    data_end: bounds[5],
+                    bss_start: bounds[6],
+                    bss_end: bounds[7],
+                    kernel_end,
+                    dynamic_start,
+                }
+            },
+        )
+    }
+
+    /// Generates a kernel binary with embedded metadata, as it would
+    /// be produced from `start.s`.
+    fn kernel(endian: Endian) -> impl Strategy<Value = Vec<u8>> {
+        (
+            // The magic must be the first occurrence of `ONYX`.
+            vec(
+                any::<u8>().prop_filter("no magic", |&b| b != b'O'),
+                1..=0x10,
+            ),
+            vec(any::<u8>(), 0..0x2000),
+        )
+            .prop_flat_map(move |(code, body)| {
+                let len = code.len() + KernelMeta::SIZE + body.len();
+                (Just(code), Just(body), layout(len))
+            })
+            .prop_map(move |(code, body, layout)| {
+                let meta = KernelMeta {
+                    version: u32::MAX,
+                    layout,
+                    ..Default::default()
+                };
+
+                let mut kernel = Cursor::new(code);
+                kernel.seek(SeekFrom::End(0)).unwrap();
+                meta.write_options(&mut kernel, endian, ()).unwrap();
+                kernel.write_all(&body).unwrap();
+
+                kernel.into_inner()
+            })
+    }
+
+    fn kip(endian: Endian) -> impl Strategy<Value = Vec<u8>> {
+        (
+            "[a-z]{1,12}",
+            any::<u64>(),
+            any::<u32>(),
+            [0..0x200u32, 0..0x200u32, 0..0x200u32],
+            any::<u8>(),
+        )
+            .prop_map(move |(name, program_id, version, sizes, fill)| {
+                let mut header = Kip1Header {
+                    program_id,
+                    version,
+                    ..Default::default()
+                };
+                header.name[..name.len()].copy_from_slice(name.as_bytes());
+                for (segment, size) in header.segments.iter_mut().zip(sizes) {
+                    segment.size = size;
+                    segment.file_size = size;
+                }
+
+                let mut kip = Cursor::new(Vec::new());
+                header.write_options(&mut kip, endian, ()).unwrap();
+                kip.write_all(&vec![fill; header.file_size() - Kip1Header::SIZE])
+                    .unwrap();
+
+                kip.into_inner()
+            })
+    }
+
+    fn compression() -> impl Strategy<Value = Compression> {
+        prop_oneof![
+            Just(Compression::None),
+            Just(Compression::Gzip),
+            Just(Compression::Lz4),
+            Just(Compression::Zstd),
+        ]
+    }
+
+    /// Generates an `onyx-stub` binary with an empty header, as it
+    /// would be produced from `start.s`.
+    fn stub(endian: Endian) -> impl Strategy<Value = Vec<u8>> {
+        (vec(any::<u8>(), 0..0x100), 0..0x2000u64).prop_map(move |(body, bss)| {
+            let mut stub = Cursor::new(vec![0x6F, 0, 0, 0, 0, 0, 0, 0]);
+            stub.seek(SeekFrom::End(0)).unwrap();
+            StubHeader::default()
+                .write_options(&mut stub, endian, ())
+                .unwrap();
+            stub.write_all(&body).unwrap();
+
+            // Patch in the end of the stub including its .bss section.
+            let end = stub.position() + bss;
+            stub.seek(SeekFrom::Start(8)).unwrap();
+            StubHeader {
+                end,
+                ..Default::default()
+            }
+            .write_options(&mut stub, endian, ())
+            .unwrap();
+
+            stub.into_inner()
+        })
+    }
+
+    /// Generates an `onyx-loader` binary along with its size in
+    /// memory, which includes its .bss section.
+    fn loader() -> impl Strategy<Value = (Vec<u8>, usize)> {
+        (vec(any::<u8>(), 1..0x2000), 0..0x2000usize).prop_map(|(loader, bss)| {
+            let memory_size = loader.len() + bss;
+            (loader, memory_size)
+        })
+    }
+
+    #[allow(clippy::type_complexity)]
+    fn image() -> impl Strategy<Value = (Endian, Vec<u8>, (Vec<u8>, usize), Vec<Vec<u8>>, Vec<u8>)>
+    {
+        endian().prop_flat_map(|endian| {
+            (
+                Just(endian),
+                kernel(endian),
+                loader(),
+                vec(kip(endian), 0..4),
+                stub(endian),
+            )
+        })
+    }
+
+    proptest! {
+        #[test]
+        fn finish_then_parse_is_lossless(
+            (endian, kernel, (loader, loader_memory_size), kips, stub) in image(),
+            version in any::<(u8, u8, u8)>(),
+            compression in compression(),
+            checksum in any::<bool>(),
+            revision in 1..=KernelMeta::REVISION,
+            build_id in option::of((0..4u32, any::<u64>())),
+            dtb in option::of(vec(any::<u8>(), 0..0x100)),
+            cmdline in option::of("[ -~]{0,64}"),
+            page_size in prop_oneof![Just(PAGE_SIZE), Just(0x4000), Just(0x10000)],
+        ) {
+            // Trailers are only supported from revision 2 onwards.
+            let checksum = checksum && revision >= 2;
+            // Build IDs are only supported from revision 3 onwards.
+            let build_id = build_id.filter(|_| revision >= 3).map(|(flags, timestamp)| BuildId {
+                flags,
+                timestamp,
+                ..BuildId::default()
+            });
+            // Device trees are only supported from revision 4 onwards.
+            let dtb = dtb.filter(|_| revision >= 4).map(|body| {
+                let mut dtb = FDT_MAGIC.to_be_bytes().to_vec();
+                dtb.extend(body);
+                dtb
+            });
+            // Command lines are only supported from revision 5 onwards.
+            let cmdline = cmdline.filter(|_| revision >= 5);
+            // Larger pages can only be told apart from revision 6 onwards.
+            let page_size = if revision >= 6 { page_size } else { PAGE_SIZE };
+            let mut image = KernelImage::new()
+                .with_endian(endian)
+                .with_page_size(page_size)
+                .with_compression(compression)
+                .with_checksum(checksum)
+                .with_revision(revision)
+                .with_version(version.0, version.1, version.2)
+                .pack_kernel_bytes(kernel.clone(), find_kernel_meta(&kernel).unwrap())
+                .unwrap()
+                .pack_loader_bytes(loader.clone(), loader_memory_size)
+                .unwrap();
+            for kip in &kips {
+                image = image.pack_kip_bytes(kip.clone()).unwrap();
+            }
+            if let Some(build_id) = &build_id {
+                image = image.with_build_id(build_id.clone());
+            }
+            if let Some(dtb) = &dtb {
+                image = image.pack_dtb_bytes(dtb.clone()).unwrap();
+            }
+            if let Some(cmdline) = &cmdline {
+                image = image.with_cmdline(cmdline.clone());
+            }
+            if compression != Compression::None {
+                image = image.pack_stub_bytes(stub).unwrap();
+            }
+            let encoded = image.encode().unwrap();
+            let layout = image.kernel_meta().1.layout.clone();
+
+            let path = std::env::temp_dir().join(format!("onyx-{}.bin", std::process::id()));
+            image.finish(&path).unwrap();
+            let (data, format) = read_image(&path, endian).unwrap();
+            fs::remove_file(&path).unwrap();
+            prop_assert_eq!(&data, &encoded);
+            prop_assert_eq!(format, compression);
+
+            let parsed = KernelImage::parse(&data, endian).unwrap();
+            let (meta_offset, meta) = parsed.kernel_meta();
+
+            // Everything but the patched metadata must be preserved.
+            let meta_end = meta_offset + meta.header_size as usize;
+            prop_assert_eq!(&parsed.kernel()[..meta_offset], &kernel[..meta_offset]);
+            prop_assert_eq!(&parsed.kernel()[meta_end..], &kernel[meta_end..]);
+            prop_assert_eq!(parsed.loader(), &loader[..]);
+            if revision >= 6 {
+                prop_assert_eq!(
+                    meta.loader_end(),
+                    Some(meta.loader_base + loader_memory_size as u64)
+                );
+            }
+            prop_assert_eq!(parsed.kips(), &kips[..]);
+            prop_assert_eq!(parsed.trailer().is_some(), checksum);
+            prop_assert_eq!(parsed.build_id(), build_id.as_ref());
+            prop_assert_eq!(parsed.dtb(), dtb.as_deref());
+            prop_assert_eq!(parsed.cmdline(), cmdline.as_deref());
+            prop_assert_eq!(parsed.page_size(), page_size);
+
+            prop_assert_eq!(&meta.layout, &layout);
+            prop_assert_eq!(meta.revision, revision);
+            prop_assert_eq!(
+                meta.version,
+                u32::from_be_bytes([
+                    version.0,
+                    version.1,
+                    version.2,
+                    build_id.as_ref().map_or(0, |id| id.flags as u8),
+                ])
+            );
+
+            // Encoding the parsed image again must reproduce it exactly.
+            prop_assert_eq!(parsed.encode().unwrap(), encoded);
+        }
+    }
+}
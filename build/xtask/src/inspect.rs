@@ -0,0 +1,170 @@
+use std::path::Path;
+
+use anyhow::anyhow;
+use binrw::Endian;
+use onyx_core::kip::{Ini1, KipError};
+use serde::Serialize;
+
+use crate::image::{self, BuildId, Compression, KernelImage, KernelMeta, Kip1Segment};
+
+/// A summary of the contents of a Kernel Image.
+#[derive(Debug, Serialize)]
+struct Report {
+    /// The compression format of the image file.
+    compression: Compression,
+    /// The size of the uncompressed image in bytes.
+    size: usize,
+    /// The offset of the [`KernelMeta`] structure in the image.
+    meta_offset: usize,
+    /// The decoded kernel metadata.
+    meta: KernelMeta,
+    /// The embedded kernel command line, if any.
+    cmdline: Option<String>,
+    /// The build ID of the image, if any.
+    build_id: Option<BuildId>,
+    /// The size of the kernel binary in bytes.
+    kernel_size: usize,
+    /// The size of the Kernel Loader binary in bytes.
+    loader_size: usize,
+    /// All Kernel Initial Processes embedded in the image.
+    kips: Vec<Kip1Entry>,
+}
+
+/// A summary of a KIP1 binary embedded in a Kernel Image.
+#[derive(Debug, Serialize)]
+struct Kip1Entry {
+    name: String,
+    program_id: u64,
+    version: u32,
+    offset: usize,
+    size: usize,
+    segments: [Kip1Segment; 6],
+}
+
+/// Inspects the Kernel Image at a given path and prints its contents
+/// to stdout, either in human-readable form or as JSON.
+pub fn inspect<P: AsRef<Path>>(path: P, endian: Endian, json: bool) -> anyhow::Result<()> {
+    let path = path.as_ref();
+
+    let (data, compression) = image::read_image(path, endian)?;
+
+    // Decompose the image into its individual components.
+    let image = KernelImage::parse(&data, endian)?;
+    let (meta_offset, meta) = image.kernel_meta();
+
+    let malformed = |e: KipError| anyhow!("malformed KIP1 list: {e}");
+    let mut kips = Vec::with_capacity(image.kips().len());
+    for kip in
+        Ini1::new(&data, meta.kip1_base as usize, image::byte_order(endian)).map_err(malformed)?
+    {
+        let kip = kip.map_err(malformed)?;
+        kips.push(Kip1Entry {
+            name: kip.header.name().to_string(),
+            program_id: kip.header.program_id,
+            version: kip.header.version,
+            offset: kip.offset,
+            size: kip.data.len(),
+            segments: kip.header.segments,
+        });
+    }
+
+    let report = Report {
+        compression,
+        size: data.len(),
+        meta_offset,
+        meta: meta.clone(),
+        cmdline: image.cmdline().map(str::to_string),
+        build_id: image.build_id().cloned(),
+        kernel_size: image.kernel().len(),
+        loader_size: image.loader().len(),
+        kips,
+    };
+
+    if json {
+        println!("{}", serde_json::to_string_pretty(&report)?);
+    } else {
+        print_report(path, &report);
+    }
+
+    Ok(())
+}
+
+fn print_report(path: &Path, report: &Report) {
+    let meta = &report.meta;
+    let layout = &meta.layout;
+
+    println!("Onyx Kernel Image ({})", path.display());
+    println!("  Compression:  {:?}", report.compression);
+    println!("  Image size:   {:#x}", report.size);
+    println!(
+        "  Version:      {}.{}.{}",
+        meta.version >> 24,
+        (meta.version >> 16) & 0xFF,
+        (meta.version >> 8) & 0xFF
+    );
+    if let Some(build_id) = &report.build_id {
+        let dirty = if build_id.flags & BuildId::FLAG_DIRTY != 0 {
+            "-dirty"
+        } else {
+            ""
+        };
+        println!(
+            "  Build:        {}{dirty} ({}, timestamp {})",
+            build_id.commit(),
+            build_id.profile(),
+            build_id.timestamp
+        );
+    }
+    println!(
+        "  Meta format:  revision {}, {:#x} bytes",
+        meta.revision, meta.header_size
+    );
+    println!("  Meta offset:  {:#x}", report.meta_offset);
+    println!("  Kernel size:  {:#x}", report.kernel_size);
+    println!("  Loader base:  {:#x}", meta.loader_base);
+    println!("  Loader size:  {:#x}", report.loader_size);
+    if let Some(end) = meta.loader_end() {
+        println!("  Loader end:   {end:#x}");
+    }
+    println!("  KIP1 base:    {:#x}", meta.kip1_base);
+    if let Some((base, size)) = meta.dtb() {
+        println!("  DTB:          {size:#x} bytes at {base:#x}");
+    }
+    if let Some(cmdline) = &report.cmdline {
+        println!("  Command line: {cmdline:?}");
+    }
+    println!(
+        "  Trailer base: {:#x}",
+        meta.trailer_base().unwrap_or_default()
+    );
+
+    println!();
+    println!("Kernel layout:");
+    let sections = [
+        (".text", layout.text_start, layout.text_end),
+        (".rodata", layout.rodata_start, layout.rodata_end),
+        (".data", layout.data_start, layout.data_end),
+        (".bss", layout.bss_start, layout.bss_end),
+    ];
+    for (name, start, end) in sections {
+        println!(
+            "  {name:<8} {start:#010x} - {end:#010x} ({:#x} bytes)",
+            end.saturating_sub(start)
+        );
+    }
+    println!("  Kernel end:    {:#010x}", layout.kernel_end);
+    println!("  Dynamic start: {:#010x}", layout.dynamic_start);
+
+    println!();
+    if report.kips.is_empty() {
+        println!("KIP1 entries: none");
+    } else {
+        println!("KIP1 entries:");
+        for kip in &report.kips {
+            println!(
+                "  {:<12} program ID {:#018x}, version {}, {:#x} bytes at {:#x}",
+                kip.name, kip.program_id, kip.version, kip.size, kip.offset
+            );
+        }
+    }
+}
@@ -0,0 +1,85 @@
+use std::{fs, path::PathBuf};
+
+use anyhow::{bail, Context};
+
+use crate::{config::Config, rustc};
+
+/// Renders the linker script template for a package with the memory
+/// layout from the build configuration.
+///
+/// Templates live in `build/linker-scripts` and refer to values by
+/// `${NAME}` placeholders:
+///
+/// - `RAM_BASE` and `RAM_SIZE`: the physical memory of the board.
+/// - `PAGE_SIZE`: the page size used by the kernel.
+/// - `STACK_SIZE`: the size of the early boot stack of the package.
+///
+/// The rendered script is written to `target/linker-scripts`, where the
+/// build script in `build/link.rs` picks it up, and its path is
+/// returned. The file is only touched when its contents change, so
+/// that cargo relinks exactly when the layout changes.
+pub fn render(pkg: &str, config: &Config) -> anyhow::Result<PathBuf> {
+    // The stub for compressed images shares its memory layout with
+    // the loader.
+    let (template, stack_size) = if pkg == "onyx" {
+        (&config.kernel.linker_script, config.kernel.stack_size)
+    } else {
+        (&config.loader.linker_script, config.loader.stack_size)
+    };
+
+    let mut path = rustc::project_root();
+    path.push("build");
+    path.push("linker-scripts");
+    path.push(template);
+    let template =
+        fs::read_to_string(&path).with_context(|| format!("failed to read {}", path.display()))?;
+
+    let values = [
+        ("RAM_BASE", config.memory.base),
+        ("RAM_SIZE", config.memory.size),
+        ("PAGE_SIZE", config.memory.page_size),
+        ("STACK_SIZE", stack_size),
+    ];
+    let script = values.iter().fold(template, |script, (name, value)| {
+        script.replace(&format!("${{{name}}}"), &format!("{value:#x}"))
+    });
+    if let Some(start) = script.find("${") {
+        let placeholder = script[start..].split('}').next().unwrap_or_default();
+        bail!(
+            "unknown placeholder `{placeholder}}}` in {}",
+            path.display()
+        );
+    }
+
+    let out = scripts_dir();
+    fs::create_dir_all(&out)?;
+    let out = out.join(format!("{pkg}.x"));
+    if fs::read_to_string(&out).ok().as_deref() != Some(script.as_str()) {
+        fs::write(&out, script)?;
+    }
+
+    Ok(out)
+}
+
+/// Gets the directory which rendered linker scripts are written to.
+pub fn scripts_dir() -> PathBuf {
+    let mut path = rustc::project_root();
+    path.push("target");
+    path.push("linker-scripts");
+    path
+}
+
+/// Gets the directory which the linker writes its maps to.
+pub fn maps_dir() -> PathBuf {
+    let mut path = rustc::project_root();
+    path.push("target");
+    path.push("linker-maps");
+    path
+}
+
+/// Gets the path of the linker map for a package built in the given
+/// profile.
+pub fn map_path(pkg: &str, release: bool) -> PathBuf {
+    let profile = if release { "release" } else { "debug" };
+    maps_dir().join(format!("{pkg}-{profile}.map"))
+}
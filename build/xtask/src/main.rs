@@ -1,29 +1,89 @@
-use std::path::{Path, PathBuf};
+use std::{
+    collections::HashMap,
+    env, fs,
+    path::{Path, PathBuf},
+    process::ExitCode,
+    time::Duration,
+};
+
+use anyhow::bail;
 
 use clap::{Parser, Subcommand};
 use xshell::Shell;
 
+/// Prints a line of progress output like `println!`.
+///
+/// With `--message-format json`, stdout is reserved for cargo's JSON
+/// messages, so the line goes to stderr instead.
+macro_rules! status {
+    ($($arg:tt)*) => {
+        if crate::cargo::message_format() == crate::cargo::MessageFormat::Json {
+            eprintln!($($arg)*);
+        } else {
+            println!($($arg)*);
+        }
+    };
+}
+
 mod build;
 
+mod build_id;
+
 mod cargo;
+use cargo::{CargoError, MessageFormat};
 
 mod check;
 
 mod config;
 use config::Config;
 
+mod dist;
+
+mod elf;
+
+mod fdt;
+
+mod format;
+
+mod gdb;
+
 mod image;
 use image::KernelImage;
 
+mod inspect;
+
+mod linker;
+
 mod run;
 
 mod rustc;
 
+mod size;
+use size::SizeReport;
+
+mod test;
+
+mod verify;
+
 #[derive(Parser)]
 #[clap(long_about = None)]
 struct Cli {
     #[clap(subcommand)]
     action: Action,
+    /// Overrides a value of the build configuration.
+    ///
+    /// Takes a dotted key and a TOML value, which is treated as a
+    /// string if it fails to parse, e.g. `image.compression=lz4` or
+    /// `qemu.extra-args=["-s", "-S"]`. May be given multiple times.
+    #[clap(long = "set", value_name = "KEY=VALUE", global = true)]
+    overrides: Vec<String>,
+    /// The format of the messages from cargo.
+    ///
+    /// With `json`, cargo's JSON messages are passed through to stdout
+    /// for editors and CI to consume, while all other progress output
+    /// goes to stderr.
+    #[clap(long, value_enum, default_value_t, global = true)]
+    message_format: MessageFormat,
 }
 
 #[derive(Subcommand)]
@@ -31,7 +91,8 @@ enum Action {
     /// Builds a distribution of Onyx in the form of a full Kernel Image.
     ///
     /// This is the only supported format to execute Onyx from, and will
-    /// be shipped in all release builds.
+    /// be shipped in all release builds. The image is additionally
+    /// wrapped in all output formats selected by the config.
     Dist {
         /// Path to the build configuration file.
         #[clap(short, long)]
@@ -42,6 +103,10 @@ enum Action {
         /// Request verbosity from all invoked tools.
         #[clap(short, long)]
         verbose: bool,
+        /// Builds the image a second time from scratch and fails
+        /// unless both builds are bit-for-bit identical.
+        #[clap(long)]
+        check_reproducible: bool,
     },
 
     /// Attempts to `cargo build` a given package in the source tree.
@@ -79,64 +144,376 @@ enum Action {
         /// Invokes cargo in release mode.
         #[clap(short, long)]
         release: bool,
+        /// Halts QEMU at startup until GDB attaches on port 1234.
+        ///
+        /// Also generates the script for `xtask gdb` to attach with.
+        #[clap(long)]
+        gdb: bool,
     },
+
+    /// Attaches GDB to a QEMU instance started by `xtask run --gdb`.
+    ///
+    /// Symbols of the kernel and the Kernel Loader are loaded at the
+    /// addresses they execute from.
+    Gdb {
+        /// The GDB executable to launch.
+        ///
+        /// Defaults to `rust-gdb` backed by `gdb-multiarch`, falling
+        /// back to whichever of the two or plain `gdb` is installed.
+        #[clap(long)]
+        debugger: Option<String>,
+    },
+
+    /// Boots the Kernel Image headless in QEMU as an automated test.
+    ///
+    /// The kernel is booted with the `test` command line parameter and
+    /// reports `TEST PASS` or `TEST FAIL` on its serial console before
+    /// shutting down. Exits with a non-zero status unless the boot
+    /// markers were seen and the kernel passed.
+    ///
+    /// Given a package, its `#[test_case]` functions are run in QEMU
+    /// instead and the results are summarized. Packages of portable
+    /// kernel code, like `onyx-core`, are tested on the host.
+    Test {
+        /// The package whose tests to run.
+        ///
+        /// Either `onyx` or `onyx-loader` for in-kernel tests, or
+        /// `onyx-core` for host tests.
+        #[clap(conflicts_with = "all")]
+        package: Option<String>,
+        /// Runs the host tests, all in-kernel tests and the boot test.
+        #[clap(short, long)]
+        all: bool,
+        /// Path to the build configuration file.
+        ///
+        /// Required for all tests that run in QEMU.
+        #[clap(short, long)]
+        config: Option<PathBuf>,
+        /// Invokes cargo in release mode.
+        #[clap(short, long)]
+        release: bool,
+        /// Seconds to wait for the kernel to report a result.
+        #[clap(short, long, default_value_t = 60)]
+        timeout: u64,
+        /// Additional output the kernel must print before passing.
+        ///
+        /// May be given multiple times; markers are matched in order
+        /// after the boot banner. With `--all`, they only apply to the
+        /// boot test.
+        #[clap(short, long, value_name = "MARKER")]
+        expect: Vec<String>,
+    },
+
+    /// Dumps the contents of a built Kernel Image.
+    Inspect {
+        /// Path to the Kernel Image to inspect.
+        ///
+        /// Defaults to the image produced by `dist`.
+        image: Option<PathBuf>,
+        /// Path to the build configuration file.
+        ///
+        /// Used to determine the endianness of the image; little
+        /// endian is assumed when omitted.
+        #[clap(short, long)]
+        config: Option<PathBuf>,
+        /// Prints the image contents in JSON format.
+        #[clap(long)]
+        json: bool,
+    },
+
+    /// Verifies the integrity and layout invariants of a Kernel Image.
+    ///
+    /// All violated invariants will be reported at once.
+    Verify {
+        /// Path to the Kernel Image to verify.
+        ///
+        /// Defaults to the image produced by `dist`.
+        image: Option<PathBuf>,
+        /// Path to the build configuration file.
+        ///
+        /// Used to determine the endianness of the image and the key
+        /// to check its signature against; little endian and no
+        /// signature checks are assumed when omitted.
+        #[clap(short, long)]
+        config: Option<PathBuf>,
+    },
+
+    /// Reports the sizes of a Kernel Image, its components and the
+    /// sections of the kernel and the Kernel Loader.
+    ///
+    /// Reports can be saved as JSON and compared against later builds
+    /// to catch size regressions.
+    Size {
+        /// Path to the Kernel Image to measure.
+        ///
+        /// Defaults to the image produced by `dist`. Loader sections are
+        /// only reported when its ELF was shipped next to the image.
+        image: Option<PathBuf>,
+        /// Path to the build configuration file.
+        ///
+        /// Used to determine the endianness of the image; little
+        /// endian is assumed when omitted.
+        #[clap(short, long)]
+        config: Option<PathBuf>,
+        /// A report saved with `--save` to compare against.
+        #[clap(short, long)]
+        baseline: Option<PathBuf>,
+        /// Saves the report as JSON to the given path.
+        #[clap(short, long)]
+        save: Option<PathBuf>,
+        /// Fails when the raw image grew by more than this many bytes
+        /// over the baseline.
+        #[clap(long, value_name = "BYTES", requires = "baseline")]
+        max_growth: Option<u64>,
+    },
+
+    /// Inspects build configurations.
+    Config {
+        #[clap(subcommand)]
+        action: ConfigAction,
+    },
+}
+
+#[derive(Subcommand)]
+enum ConfigAction {
+    /// Prints the fully resolved build configuration in TOML format.
+    ///
+    /// This includes all values inherited through `extends`, all
+    /// overrides and the defaults of omitted values.
+    Show {
+        /// Path to the build configuration file.
+        #[clap(short, long)]
+        config: PathBuf,
+    },
+}
+
+fn read_config<P: AsRef<Path>>(path: P, overrides: &[String]) -> anyhow::Result<Config> {
+    // Resolve the path to the supplied config file and deserialize it.
+    Config::read(Config::path(path), overrides)
+}
+
+fn read_endian(config: Option<PathBuf>, overrides: &[String]) -> anyhow::Result<binrw::Endian> {
+    match config {
+        Some(config) => read_config(config, overrides).map(|c| c.endian),
+        None => Ok(binrw::Endian::Little),
+    }
+}
+
+fn dist_image_path() -> PathBuf {
+    let mut path = rustc::project_root();
+    path.push("target");
+    path.push("dist");
+    path.push("onyx.bin");
+    path
+}
+
+fn reproducible_target_dir() -> PathBuf {
+    let mut path = rustc::project_root();
+    path.push("target");
+    path.push("reproducible");
+    path
+}
+
+fn gdb_script_path() -> PathBuf {
+    dist_image_path().with_file_name("onyx.gdbinit")
 }
 
-fn read_config<P: AsRef<Path>>(path: P) -> anyhow::Result<Config> {
-    // Resolve the path to the supplied config file.
-    let mut config_path = rustc::project_root();
-    config_path.push("build");
-    config_path.push("config");
-    config_path.push(path);
-    config_path.set_extension("toml");
+fn test_image_path() -> PathBuf {
+    dist_image_path().with_file_name("onyx-test.bin")
+}
 
-    // Deserialize the config file.
-    Config::read(config_path)
+/// The files produced by [`build_kernel_image`].
+struct Artifacts {
+    /// The finished Kernel Image.
+    image: PathBuf,
+    /// The ELF file of the kernel, with symbols.
+    kernel_elf: PathBuf,
+    /// The ELF file of the Kernel Loader, with symbols.
+    loader_elf: PathBuf,
 }
 
+/// Builds the Kernel Image to `image_path`.
+///
+/// When `tests` names the kernel or the loader, its in-kernel test
+/// harness is packed in place of the regular build.
 fn build_kernel_image(
     sh: &Shell,
     config: &Config,
+    image_path: PathBuf,
+    tests: Option<&str>,
     release: bool,
     verbose: bool,
-) -> anyhow::Result<PathBuf> {
-    // Build the kernel and convert it to a raw binary.
-    let kernel = build::build("onyx", config, release, verbose)?;
-    let kernel = build::make_raw_binary(sh, kernel)?;
+) -> anyhow::Result<Artifacts> {
+    // Build all components of the image in a single cargo invocation,
+    // except for a test harness, which needs `cargo test`.
+    let stub_feature = config
+        .image
+        .compression
+        .stub_feature()
+        .map(|feature| format!("onyx-stub/{feature}"));
+    let mut packages = vec!["onyx", "onyx-loader"];
+    if stub_feature.is_some() {
+        packages.push("onyx-stub");
+    }
+    packages.retain(|pkg| Some(*pkg) != tests);
 
-    // Build the kernel loader and convert it to a raw binary.
-    let kernel_loader = build::build("onyx-loader", config, release, verbose)?;
-    let kernel_loader = build::make_raw_binary(sh, kernel_loader)?;
+    let features: Vec<_> = stub_feature.iter().map(String::as_str).collect();
+    let elfs = build::build_all(&packages, config, &features, release, verbose)?;
+    let mut elfs: HashMap<_, _> = packages.into_iter().zip(elfs).collect();
+    if let Some(pkg) = tests {
+        elfs.insert(pkg, build::build_tests(pkg, config, release, verbose)?);
+    }
 
-    // Build the output path for the kernel image.
-    let image_path = {
-        let mut path = rustc::project_root();
-        path.push("target");
-        path.push("dist");
+    // Convert the kernel and the kernel loader to raw binaries.
+    let kernel_elf = elfs.remove("onyx").unwrap();
+    let kernel = build::make_raw_binary(sh, kernel_elf.clone())?;
+    let loader_elf = elfs.remove("onyx-loader").unwrap();
+    let kernel_loader = build::make_raw_binary(sh, loader_elf.clone())?;
 
-        path = sh.create_dir(path)?;
-        path.push("onyx.bin");
+    // Create the output directory for the kernel image.
+    sh.create_dir(image_path.parent().unwrap())?;
 
-        path
-    };
+    // The image carries the version of the kernel, not of this tool.
+    let version = cargo::package_version("onyx")?;
 
-    KernelImage::new()
+    let mut image = KernelImage::new()
         .with_endian(config.endian)
-        .with_compression(config.image.compress)
+        .with_page_size(config.memory.page_size)
+        .with_compression(config.image.compression)
+        .with_checksum(config.image.checksum)
+        .with_revision(config.image.revision)
         .with_version(
-            env!("CARGO_PKG_VERSION_MAJOR").parse()?,
-            env!("CARGO_PKG_VERSION_MINOR").parse()?,
-            env!("CARGO_PKG_VERSION_PATCH").parse()?,
+            version.major.try_into()?,
+            version.minor.try_into()?,
+            version.patch.try_into()?,
         )
-        .pack_kernel(kernel)?
-        .pack_loader(kernel_loader)?
-        .finish(&image_path)?;
+        .pack_kernel(&kernel_elf, kernel)?
+        .pack_loader(&loader_elf, kernel_loader)?;
+    if config.image.revision >= 3 {
+        image = image.with_build_id(build_id::collect(sh, release, config.image.timestamps)?);
+    }
+    for kip in &config.image.kips {
+        image = image.pack_kip(rustc::project_root().join(kip))?;
+    }
+    if let Some(cmdline) = &config.kernel.cmdline {
+        image = image.with_cmdline(cmdline.clone());
+    }
+    if let Some(dtb) = &config.image.dtb {
+        let dtb = fdt::read_dtb(sh, &rustc::project_root().join(dtb))?;
+        image = image.pack_dtb_bytes(dtb)?;
+    }
+    if let Some(key) = &config.image.signing_key {
+        image = image.with_signing_key(image::read_signing_key(rustc::project_root().join(key))?);
+    }
+    if let Some(stub) = elfs.remove("onyx-stub") {
+        // Pack the decompression stub which prefixes the image.
+        image = image.pack_stub(build::make_raw_binary(sh, stub)?)?;
+    }
+    image.finish(&image_path)?;
+
+    // Wrap the image for other bootloaders, if requested.
+    for format in &config.image.formats {
+        format.write(&image_path, config)?;
+    }
+
+    Ok(Artifacts {
+        image: image_path,
+        kernel_elf,
+        loader_elf,
+    })
+}
+
+/// Runs the tests of `package` on the host or in QEMU, or the boot test
+/// of the whole image without a package.
+fn run_test_suite(
+    sh: &Shell,
+    config: Option<&Config>,
+    package: Option<&str>,
+    release: bool,
+    timeout: Duration,
+    expect: &[String],
+) -> anyhow::Result<()> {
+    if let Some(pkg) = package.filter(|pkg| test::HOST_PACKAGES.contains(pkg)) {
+        return test::test_on_host(pkg, release);
+    }
 
-    Ok(image_path)
+    let Some(config) = config else {
+        bail!("running tests in QEMU requires a build configuration");
+    };
+    let mut config = config.clone();
+    match package {
+        Some(pkg) => {
+            if !test::PACKAGES.contains(&pkg) {
+                bail!("package `{pkg}` has no tests");
+            }
+
+            // Selecting the embedded device tree is tested with a null
+            // firmware pointer, which needs a blob in the image.
+            if pkg == test::EMBEDDED_DTB_PACKAGE
+                && config.image.dtb.is_none()
+                && config.image.revision >= 4
+            {
+                let dtb = test_image_path().with_extension("dtb");
+                sh.create_dir(dtb.parent().unwrap())?;
+                sh.write_file(&dtb, test::embedded_dtb())?;
+                config.image.dtb = Some(dtb);
+            }
+        }
+
+        None => {
+            if config.image.revision < 5 {
+                bail!(
+                    "`xtask test` needs a command line, which requires image revision 5 or later"
+                );
+            }
+
+            // Tell the kernel to report its result and shut down.
+            let cmdline = config.kernel.cmdline.get_or_insert_with(String::new);
+            if !cmdline.is_empty() {
+                cmdline.push(' ');
+            }
+            cmdline.push_str("test");
+        }
+    }
+
+    let artifacts = build_kernel_image(sh, &config, test_image_path(), package, release, false)?;
+
+    // A component clobbered at boot, such as the command line that
+    // enables the test mode, would otherwise only show as a timeout.
+    verify::verify(&artifacts.image, Some(&config))?;
+
+    // Every boot starts with the banner, which shows the build ID only
+    // if it is still intact.
+    let mut markers = expect.to_vec();
+    if package.is_none() {
+        let (data, _) = image::read_image(&artifacts.image, config.endian)?;
+        markers.insert(0, test::banner(&KernelImage::parse(&data, config.endian)?));
+    }
+    test::test_in_qemu(artifacts.image, &config, &markers, timeout)
 }
 
-fn main() -> anyhow::Result<()> {
+fn main() -> ExitCode {
     let cli = Cli::parse();
+    cargo::set_message_format(cli.message_format);
+
+    match run(cli) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("Error: {e:?}");
+
+            // Exit like cargo did when it failed, so that callers can
+            // tell compilation errors apart from other failures.
+            let code = e
+                .downcast_ref::<CargoError>()
+                .and_then(CargoError::code)
+                .and_then(|code| u8::try_from(code).ok())
+                .unwrap_or(1);
+            ExitCode::from(code)
+        }
+    }
+}
+
+fn run(cli: Cli) -> anyhow::Result<()> {
     let shell = Shell::new()?;
 
     match cli.action {
@@ -144,9 +521,42 @@ fn main() -> anyhow::Result<()> {
             config,
             release,
             verbose,
+            check_reproducible,
         } => {
-            let config = read_config(config)?;
-            build_kernel_image(&shell, &config, release, verbose).map(|_| ())
+            let config = read_config(config, &cli.overrides)?;
+            if check_reproducible
+                && config.image.timestamps
+                && env::var_os("SOURCE_DATE_EPOCH").is_none()
+            {
+                bail!("timestamps are only reproducible with SOURCE_DATE_EPOCH set");
+            }
+
+            let artifacts =
+                build_kernel_image(&shell, &config, dist_image_path(), None, release, verbose)?;
+            dist::ship(
+                &shell,
+                &artifacts.image,
+                &artifacts.kernel_elf,
+                &artifacts.loader_elf,
+                &config,
+                release,
+            )?;
+
+            if check_reproducible {
+                // Rebuild everything in a fresh target directory, which
+                // all following cargo invocations inherit.
+                let target_dir = reproducible_target_dir();
+                if target_dir.exists() {
+                    fs::remove_dir_all(&target_dir)?;
+                }
+                env::set_var("CARGO_TARGET_DIR", &target_dir);
+
+                let image = target_dir.join("dist").join("onyx.bin");
+                let rebuilt = build_kernel_image(&shell, &config, image, None, release, verbose)?;
+                dist::check_reproducible(&artifacts.image, &rebuilt.image, &config)?;
+            }
+
+            Ok(())
         }
 
         Action::Build {
@@ -155,8 +565,8 @@ fn main() -> anyhow::Result<()> {
             release,
             verbose,
         } => {
-            let config = read_config(config)?;
-            build::build(&package, &config, release, verbose).map(|_| ())
+            let config = read_config(config, &cli.overrides)?;
+            build::build(&package, &config, &[], release, verbose).map(|_| ())
         }
 
         Action::Check {
@@ -164,14 +574,129 @@ fn main() -> anyhow::Result<()> {
             config,
             verbose,
         } => {
-            let config = read_config(config)?;
+            let config = read_config(config, &cli.overrides)?;
             check::check(&package, &config, verbose)
         }
 
-        Action::Run { config, release } => {
-            let config = read_config(config)?;
-            let image = build_kernel_image(&shell, &config, release, false)?;
-            run::run_in_qemu(&shell, image, &config)
+        Action::Run {
+            config,
+            release,
+            gdb,
+        } => {
+            let config = read_config(config, &cli.overrides)?;
+            let artifacts =
+                build_kernel_image(&shell, &config, dist_image_path(), None, release, false)?;
+            if gdb {
+                gdb::write_script(
+                    &gdb_script_path(),
+                    &artifacts.image,
+                    &artifacts.kernel_elf,
+                    &artifacts.loader_elf,
+                    &config,
+                )?;
+            }
+            run::run_in_qemu(&shell, artifacts.image, &config, gdb)
+        }
+
+        Action::Gdb { debugger } => gdb::attach(&shell, &gdb_script_path(), debugger.as_deref()),
+
+        Action::Test {
+            package,
+            all,
+            config,
+            release,
+            timeout,
+            expect,
+        } => {
+            let config = config.map(|c| read_config(c, &cli.overrides)).transpose()?;
+            let timeout = Duration::from_secs(timeout);
+            if !all {
+                return run_test_suite(
+                    &shell,
+                    config.as_ref(),
+                    package.as_deref(),
+                    release,
+                    timeout,
+                    &expect,
+                );
+            }
+
+            // Run every suite, even when an earlier one failed.
+            let suites = test::HOST_PACKAGES
+                .iter()
+                .chain(test::PACKAGES)
+                .map(|&pkg| Some(pkg))
+                .chain([None]);
+            let mut failed = Vec::new();
+            let mut total = 0;
+            for suite in suites {
+                let markers = if suite.is_some() { &[][..] } else { &expect };
+                let name = suite.unwrap_or("boot");
+                status!("Running {name} tests");
+
+                total += 1;
+                if let Err(e) =
+                    run_test_suite(&shell, config.as_ref(), suite, release, timeout, markers)
+                {
+                    eprintln!("Error: {e:?}");
+                    failed.push(name);
+                }
+            }
+
+            if !failed.is_empty() {
+                bail!(
+                    "{} of {total} test suites failed: {}",
+                    failed.len(),
+                    failed.join(", ")
+                );
+            }
+            Ok(())
+        }
+
+        Action::Inspect {
+            image,
+            config,
+            json,
+        } => {
+            let endian = read_endian(config, &cli.overrides)?;
+            let image = image.unwrap_or_else(dist_image_path);
+            inspect::inspect(image, endian, json)
+        }
+
+        Action::Verify { image, config } => {
+            let config = config.map(|c| read_config(c, &cli.overrides)).transpose()?;
+            let image = image.unwrap_or_else(dist_image_path);
+            verify::verify(image, config.as_ref())
+        }
+
+        Action::Size {
+            image,
+            config,
+            baseline,
+            save,
+            max_growth,
+        } => {
+            let endian = read_endian(config, &cli.overrides)?;
+            let image = image.unwrap_or_else(dist_image_path);
+            let report = SizeReport::read(&image, endian)?;
+            let baseline = baseline.as_deref().map(SizeReport::load).transpose()?;
+
+            report.print(baseline.as_ref());
+            if let Some(path) = save {
+                report.save(&path)?;
+            }
+            match (baseline, max_growth) {
+                (Some(baseline), Some(max)) => report.check_growth(&baseline, max),
+                _ => Ok(()),
+            }
+        }
+
+        Action::Config {
+            action: ConfigAction::Show { config },
+        } => {
+            let config = read_config(config, &cli.overrides)?;
+            print!("{}", toml::to_string_pretty(&config)?);
+            Ok(())
         }
     }
 }
@@ -7,16 +7,25 @@ use crate::{config::Config, rustc};
 /// Launches a QEMU instance emulating the kernel image at a given path.
 ///
 /// Uses the build [`Config`] to retrieve additional arguments to pass
-/// to QEMU.
-pub fn run_in_qemu(sh: &Shell, image: PathBuf, config: &Config) -> anyhow::Result<()> {
+/// to QEMU. With `gdb`, QEMU waits for a debugger to attach on port
+/// 1234 before executing anything.
+pub fn run_in_qemu(sh: &Shell, image: PathBuf, config: &Config, gdb: bool) -> anyhow::Result<()> {
     let _cwd = sh.push_dir(rustc::project_root());
 
+    let gdb_args = if gdb { &["-s", "-S"][..] } else { &[] };
+    if gdb {
+        status!("Waiting for GDB on localhost:1234, attach with `cargo xtask gdb`");
+    }
+
     let (system, extra_args) = (&config.qemu.name, &config.qemu.extra_args);
     let load_address = config.qemu.address.to_string();
+    let memory = format!("{}M", config.memory.size >> 20);
     cmd!(
         sh,
         "qemu-system-{system}
+            -m {memory}
             {extra_args...}
+            {gdb_args...}
             -device loader,file={image},addr={load_address}"
     )
     .run()?;
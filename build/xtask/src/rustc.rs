@@ -1,4 +1,4 @@
-use std::path::PathBuf;
+use std::{path::PathBuf, process::Command};
 
 use anyhow::Result;
 use xshell::{cmd, Shell};
@@ -20,6 +20,20 @@ pub fn sysroot(sh: &Shell) -> Result<PathBuf> {
     Ok(PathBuf::from(sysroot.trim()))
 }
 
+/// Gets the names of all targets built into the currently used rustc,
+/// or [`None`] if they cannot be queried.
+pub fn target_list() -> Option<Vec<String>> {
+    let rustc = std::env::var_os("RUSTC").unwrap_or_else(|| "rustc".into());
+    let output = Command::new(rustc)
+        .args(["--print", "target-list"])
+        .output()
+        .ok()
+        .filter(|o| o.status.success())?;
+
+    let targets = String::from_utf8(output.stdout).ok()?;
+    Some(targets.lines().map(str::to_string).collect())
+}
+
 /// Gets the path to an LLVM binutil given its name.
 pub fn llvm_binutil(sh: &Shell, name: &str) -> Result<PathBuf> {
     let mut path = sysroot(sh)?;
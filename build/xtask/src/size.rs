@@ -0,0 +1,420 @@
+use std::{cmp::Ordering, fs, path::Path};
+
+use anyhow::{bail, Context};
+use binrw::Endian;
+use object::SectionKind;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    dist,
+    elf::KernelElf,
+    image::{self, KernelImage},
+};
+
+/// The sizes of a Kernel Image and its components, used to track
+/// size regressions between builds.
+#[derive(Debug, Deserialize, Serialize)]
+pub struct SizeReport {
+    /// The size of the image file, after compression.
+    file_size: u64,
+    /// The size of the uncompressed image.
+    raw_size: u64,
+    /// The bytes spent on aligning components to the page size, and
+    /// on the trailing page of the image.
+    padding: u64,
+    /// The bytes which are left free between components for the .bss
+    /// sections of the binaries in front of them.
+    #[serde(default)]
+    footprint: u64,
+    /// The sizes of all components, in order of their offsets.
+    components: Vec<Component>,
+    /// The sections of the kernel, spanning the page-aligned ranges of
+    /// its [`KernelLayout`](crate::image::KernelLayout).
+    kernel: Sections,
+    /// The exact sections of the Kernel Loader, only available when its
+    /// ELF was shipped next to the image.
+    loader: Option<Sections>,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+struct Component {
+    name: String,
+    size: u64,
+}
+
+/// The sizes of the sections of a binary.
+#[derive(Debug, Deserialize, Serialize)]
+struct Sections {
+    text: u64,
+    rodata: u64,
+    data: u64,
+    bss: u64,
+}
+
+impl Sections {
+    /// Sums up the sections of an ELF by their kinds.
+    fn read(path: &Path) -> anyhow::Result<Self> {
+        let elf = KernelElf::read(path)?;
+        Ok(Self {
+            text: elf.section_size(&[SectionKind::Text])?,
+            rodata: elf.section_size(&[SectionKind::ReadOnlyData, SectionKind::ReadOnlyString])?,
+            data: elf.section_size(&[SectionKind::Data])?,
+            bss: elf.section_size(&[SectionKind::UninitializedData])?,
+        })
+    }
+}
+
+impl SizeReport {
+    /// Gathers the sizes of the Kernel Image at a given path.
+    ///
+    /// The kernel sections are taken from its [`KernelLayout`], while
+    /// the Kernel Loader sections are read from the ELF `dist` ships
+    /// next to the image, if present.
+    ///
+    /// [`KernelLayout`]: crate::image::KernelLayout
+    pub fn read(path: &Path, endian: Endian) -> anyhow::Result<Self> {
+        let file_size = fs::metadata(path)
+            .with_context(|| format!("failed to read {}", path.display()))?
+            .len();
+        let (data, _) = image::read_image(path, endian)?;
+        let image = KernelImage::parse(&data, endian)?;
+
+        let mut ranges = image.components();
+        ranges.sort_by_key(|(_, range)| range.start);
+
+        // Every component is padded up to the page size, and any space
+        // beyond that up to the next one is left for a memory footprint.
+        let mut padding = 0;
+        let mut footprint = 0;
+        for (i, (_, range)) in ranges.iter().enumerate() {
+            let next = ranges
+                .get(i + 1)
+                .map_or(data.len() as u64, |(_, r)| r.start);
+            let gap = next.saturating_sub(range.end);
+            let alignment = gap.min(align_up(range.end, image.page_size() as u64) - range.end);
+
+            padding += alignment;
+            if i + 1 < ranges.len() {
+                footprint += gap - alignment;
+            } else {
+                // The image ends with a page of padding.
+                padding += gap - alignment;
+            }
+        }
+
+        let components = ranges
+            .into_iter()
+            .map(|(name, range)| Component {
+                name: name.to_string(),
+                size: range.end - range.start,
+            })
+            .collect();
+
+        let layout = &image.kernel_meta().1.layout;
+        let kernel = Sections {
+            text: layout.text_end.saturating_sub(layout.text_start).into(),
+            rodata: layout.rodata_end.saturating_sub(layout.rodata_start).into(),
+            data: layout.data_end.saturating_sub(layout.data_start).into(),
+            bss: layout.bss_end.saturating_sub(layout.bss_start).into(),
+        };
+
+        let loader_elf = path.with_file_name(dist::debug_elf_name("onyx-loader"));
+        let loader = if loader_elf.is_file() {
+            Some(Sections::read(&loader_elf)?)
+        } else {
+            None
+        };
+
+        Ok(Self {
+            file_size,
+            raw_size: data.len() as u64,
+            padding,
+            footprint,
+            components,
+            kernel,
+            loader,
+        })
+    }
+
+    /// Reads a report previously saved as JSON.
+    pub fn load(path: &Path) -> anyhow::Result<Self> {
+        let json = fs::read_to_string(path)
+            .with_context(|| format!("failed to read {}", path.display()))?;
+        serde_json::from_str(&json)
+            .with_context(|| format!("{} is not a valid size report", path.display()))
+    }
+
+    /// Saves the report as JSON for later comparison.
+    pub fn save(&self, path: &Path) -> anyhow::Result<()> {
+        fs::write(path, serde_json::to_string_pretty(self)? + "\n")?;
+        Ok(())
+    }
+
+    /// Flattens the report into rows in the order they are printed,
+    /// each with a unique key, an indented label and a size.
+    fn rows(&self) -> Vec<(String, String, u64)> {
+        let mut rows = vec![
+            (
+                "image file".to_string(),
+                "image file".to_string(),
+                self.file_size,
+            ),
+            (
+                "raw image".to_string(),
+                "raw image".to_string(),
+                self.raw_size,
+            ),
+            ("padding".to_string(), "  padding".to_string(), self.padding),
+            (
+                "footprint".to_string(),
+                "  footprint".to_string(),
+                self.footprint,
+            ),
+        ];
+        for component in &self.components {
+            let name = &component.name;
+            rows.push((name.clone(), format!("  {name}"), component.size));
+
+            let sections = match name.as_str() {
+                "kernel" => Some(&self.kernel),
+                "Kernel Loader" => self.loader.as_ref(),
+                _ => None,
+            };
+            if let Some(sections) = sections {
+                rows.extend(
+                    [
+                        (".text", sections.text),
+                        (".rodata", sections.rodata),
+                        (".data", sections.data),
+                        (".bss", sections.bss),
+                    ]
+                    .map(|(section, size)| {
+                        (format!("{name} {section}"), format!("    {section}"), size)
+                    }),
+                );
+            }
+        }
+
+        rows
+    }
+
+    /// Pairs every row of the report with its size in the `baseline`,
+    /// if the baseline has that row.
+    fn compare(&self, baseline: &Self) -> Vec<(String, u64, Option<u64>)> {
+        let old = baseline.rows();
+        self.rows()
+            .into_iter()
+            .map(|(key, label, size)| {
+                let before = old.iter().find(|(k, _, _)| *k == key).map(|r| r.2);
+                (label, size, before)
+            })
+            .collect()
+    }
+
+    /// Prints the report, compared against a `baseline` if given.
+    pub fn print(&self, baseline: Option<&Self>) {
+        let Some(baseline) = baseline else {
+            for (_, label, size) in self.rows() {
+                println!("{label:<20} {size:>10}");
+            }
+            return;
+        };
+
+        println!(
+            "{:<20} {:>10} {:>10} {:>10}",
+            "", "size", "baseline", "delta"
+        );
+        for (label, size, before) in self.compare(baseline) {
+            match before {
+                Some(before) => println!(
+                    "{label:<20} {size:>10} {before:>10} {:>10}{}",
+                    delta(size, before),
+                    percent(size, before)
+                ),
+                None => println!("{label:<20} {size:>10} {:>10} {:>10}", "-", "new"),
+            }
+        }
+    }
+
+    /// Fails when the raw image grew by more than `max` bytes over
+    /// the `baseline`.
+    pub fn check_growth(&self, baseline: &Self, max: u64) -> anyhow::Result<()> {
+        let growth = self.raw_size.saturating_sub(baseline.raw_size);
+        if growth > max {
+            bail!(
+                "raw image grew by {growth} bytes ({} -> {}), exceeding the limit of {max} bytes",
+                baseline.raw_size,
+                self.raw_size
+            );
+        }
+
+        Ok(())
+    }
+}
+
+fn align_up(value: u64, align: u64) -> u64 {
+    (value + align - 1) & !(align - 1)
+}
+
+fn delta(size: u64, before: u64) -> String {
+    match size.cmp(&before) {
+        Ordering::Equal => "0".to_string(),
+        Ordering::Greater => format!("+{}", size - before),
+        Ordering::Less => format!("-{}", before - size),
+    }
+}
+
+fn percent(size: u64, before: u64) -> String {
+    if size == before || before == 0 {
+        return String::new();
+    }
+
+    let change = (size as f64 - before as f64) / before as f64 * 100.0;
+    format!(" ({change:+.1}%)")
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{env, io::Cursor, path::PathBuf};
+
+    use binrw::BinWrite;
+
+    use super::*;
+    use crate::image::{KernelLayout, KernelMeta};
+
+    /// Writes an image with a kernel and a loader which both have a
+    /// .bss section beyond their binaries, and returns its path.
+    fn finish_image(name: &str) -> PathBuf {
+        let meta = KernelMeta {
+            layout: KernelLayout {
+                text_end: 0x1000,
+                rodata_start: 0x1000,
+                rodata_end: 0x2000,
+                data_start: 0x2000,
+                data_end: 0x2000,
+                bss_start: 0x2000,
+                bss_end: 0x4000,
+                kernel_end: 0x4000,
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+
+        let mut kernel = Cursor::new(vec![0x6F, 0, 0, 0, 0, 0, 0, 0]);
+        kernel.set_position(8);
+        meta.write_options(&mut kernel, Endian::Little, ()).unwrap();
+        let mut kernel = kernel.into_inner();
+        kernel.resize(0x1800, 0);
+
+        let path = temp_path(name);
+        KernelImage::new()
+            .pack_kernel_bytes(kernel, 8)
+            .unwrap()
+            .pack_loader_bytes(vec![0x13; 0x100], 0x3000)
+            .unwrap()
+            .with_cmdline("loglevel=debug".to_string())
+            .finish(&path)
+            .unwrap();
+
+        path
+    }
+
+    fn temp_path(name: &str) -> PathBuf {
+        let dir = env::temp_dir().join(format!("xtask-size-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        dir.join(name)
+    }
+
+    fn report(raw_size: u64, kernel_text: u64) -> SizeReport {
+        SizeReport {
+            file_size: raw_size,
+            raw_size,
+            padding: 0x800,
+            footprint: 0,
+            components: vec![Component {
+                name: "kernel".to_string(),
+                size: kernel_text,
+            }],
+            kernel: Sections {
+                text: kernel_text,
+                rodata: 0,
+                data: 0,
+                bss: 0,
+            },
+            loader: None,
+        }
+    }
+
+    #[test]
+    fn separates_alignment_padding_from_footprints() {
+        let report = SizeReport::read(&finish_image("onyx.bin"), Endian::Little).unwrap();
+
+        assert_eq!(report.raw_size, 0x9000);
+        let sizes: Vec<_> = report.components.iter().map(|c| c.size).collect();
+        assert_eq!(sizes, [0x1800, 0x100, 0xF]);
+
+        // The kernel and the loader are padded to the next page, and
+        // the command line additionally by the trailing page.
+        assert_eq!(report.padding, 0x800 + 0xF00 + 0xFF1 + 0x1000);
+        assert_eq!(report.footprint, 0x2000 + 0x2000);
+        assert_eq!(
+            report.padding + report.footprint + sizes.iter().sum::<u64>(),
+            report.raw_size
+        );
+
+        assert_eq!(
+            (report.kernel.text, report.kernel.rodata, report.kernel.bss),
+            (0x1000, 0x1000, 0x2000)
+        );
+        assert!(report.loader.is_none());
+    }
+
+    #[test]
+    fn compares_rows_with_baseline() {
+        let mut baseline = report(0x9000, 0x1000);
+        baseline.components.clear();
+        let rows = report(0x9800, 0x1800).compare(&baseline);
+
+        let row = |label: &str| rows.iter().find(|r| r.0 == label).unwrap().clone();
+        assert_eq!(
+            row("raw image"),
+            ("raw image".to_string(), 0x9800, Some(0x9000))
+        );
+        assert_eq!(
+            row("  padding"),
+            ("  padding".to_string(), 0x800, Some(0x800))
+        );
+        assert_eq!(row("  kernel"), ("  kernel".to_string(), 0x1800, None));
+        assert_eq!(row("    .text"), ("    .text".to_string(), 0x1800, None));
+
+        assert_eq!(delta(0x1800, 0x1000), "+2048");
+        assert_eq!(delta(0x1000, 0x1800), "-2048");
+        assert_eq!(delta(0x1000, 0x1000), "0");
+        assert_eq!(percent(150, 100), " (+50.0%)");
+        assert_eq!(percent(100, 100), "");
+        assert_eq!(percent(100, 0), "");
+    }
+
+    #[test]
+    fn limits_raw_image_growth() {
+        let baseline = report(0x9000, 0x1000);
+
+        assert!(report(0x9800, 0x1000)
+            .check_growth(&baseline, 0x800)
+            .is_ok());
+        assert!(report(0x9801, 0x1000)
+            .check_growth(&baseline, 0x800)
+            .is_err());
+        assert!(report(0x8000, 0x1000).check_growth(&baseline, 0).is_ok());
+    }
+
+    #[test]
+    fn round_trips_baselines_through_json() {
+        let path = temp_path("baseline.json");
+        let saved = report(0x9000, 0x1000);
+        saved.save(&path).unwrap();
+
+        let loaded = SizeReport::load(&path).unwrap();
+        assert_eq!(loaded.rows(), saved.rows());
+    }
+}
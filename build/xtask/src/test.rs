@@ -0,0 +1,285 @@
+use std::{
+    io::{BufRead, BufReader},
+    path::PathBuf,
+    process::{Child, Command, ExitStatus, Stdio},
+    sync::mpsc::{self, Receiver, RecvTimeoutError},
+    thread,
+    time::{Duration, Instant},
+};
+
+use anyhow::{anyhow, bail, Context};
+
+use crate::{config::Config, fdt::FdtWriter, image::KernelImage, rustc};
+
+/// The packages with an in-kernel test harness.
+pub const PACKAGES: &[&str] = &["onyx", "onyx-loader"];
+
+/// The packages of portable kernel code which are tested on the host.
+pub const HOST_PACKAGES: &[&str] = &["onyx-core"];
+
+/// The line the kernel prints when a test run succeeded.
+const PASS_MARKER: &str = "TEST PASS";
+
+/// The line the kernel prints when a test run failed.
+const FAIL_MARKER: &str = "TEST FAIL";
+
+/// The package whose tests boot with a device tree embedded into the
+/// image instead of the one passed by firmware.
+pub const EMBEDDED_DTB_PACKAGE: &str = "onyx-loader";
+
+/// How long QEMU gets to exit on its own once the kernel reported.
+const SHUTDOWN_GRACE: Duration = Duration::from_secs(5);
+
+/// Boots the kernel image at a given path headless in QEMU and checks
+/// its serial output against the test protocol.
+///
+/// The run passes when all `markers` appear on the serial console in
+/// order, followed by a `TEST PASS` line, and QEMU then exits with a
+/// successful status. A `TEST FAIL` line, a non-zero exit status, an
+/// early exit or exceeding `timeout` fail the run.
+///
+/// Results of individual test cases reported by an in-kernel test
+/// harness are summarized at the end.
+pub fn test_in_qemu(
+    image: PathBuf,
+    config: &Config,
+    markers: &[String],
+    timeout: Duration,
+) -> anyhow::Result<()> {
+    let mut qemu = spawn_qemu(image, config)?;
+
+    // Forward the serial output line by line so it can be matched
+    // against while still honoring the timeout.
+    let stdout = qemu.stdout.take().unwrap();
+    let (tx, rx) = mpsc::channel();
+    thread::spawn(move || {
+        for line in BufReader::new(stdout).split(b'\n').map_while(Result::ok) {
+            let line = String::from_utf8_lossy(&line).into_owned();
+            if tx.send(line).is_err() {
+                break;
+            }
+        }
+    });
+
+    let mut summary = Summary::default();
+    let result = supervise(&mut qemu, &rx, &mut summary, markers, timeout);
+    summary.print();
+
+    result
+}
+
+fn supervise(
+    qemu: &mut Child,
+    rx: &Receiver<String>,
+    summary: &mut Summary,
+    markers: &[String],
+    timeout: Duration,
+) -> anyhow::Result<()> {
+    let deadline = Instant::now() + timeout;
+    let mut pending = markers.iter().peekable();
+    let verdict = loop {
+        let remaining = deadline.saturating_duration_since(Instant::now());
+        let line = match rx.recv_timeout(remaining) {
+            Ok(line) => line,
+            Err(RecvTimeoutError::Timeout) => {
+                qemu.kill()?;
+                qemu.wait()?;
+                bail!(
+                    "timed out after {}s waiting for the kernel{}",
+                    timeout.as_secs(),
+                    summary.context()
+                );
+            }
+            Err(RecvTimeoutError::Disconnected) => {
+                let status = qemu.wait()?;
+                bail!(
+                    "QEMU exited before the kernel reported a result ({status}){}",
+                    summary.context()
+                );
+            }
+        };
+        status!("{line}");
+
+        let line = line.trim_end();
+        if pending.next_if(|m| line.contains(m.as_str())).is_some() {
+            continue;
+        }
+        if line == PASS_MARKER {
+            break Ok(());
+        }
+        if line == FAIL_MARKER {
+            break Err(anyhow!("the kernel reported a test failure"));
+        }
+        summary.record(line);
+    };
+
+    // The kernel shuts down after reporting. Boards with a test device
+    // also report failures through the exit status, which catches a
+    // kernel that fails after printing `TEST PASS`.
+    let status = wait_timeout(qemu, SHUTDOWN_GRACE)?;
+    verdict?;
+    if let Some(marker) = pending.next() {
+        bail!("the kernel passed without printing `{marker}`");
+    }
+    match status {
+        Some(status) if status.success() => Ok(()),
+        Some(status) => bail!("QEMU exited with {status} after the kernel passed"),
+        None => bail!("QEMU did not shut down after the kernel passed"),
+    }
+}
+
+/// The results of an in-kernel test harness, collected from its
+/// `TEST RUN`, `TEST CASE`, `TEST OK` and `TEST FAILED` lines.
+#[derive(Default)]
+struct Summary {
+    total: Option<usize>,
+    running: Option<String>,
+    passed: usize,
+    failed: Vec<String>,
+}
+
+impl Summary {
+    fn record(&mut self, line: &str) {
+        let Some((event, arg)) = line.strip_prefix("TEST ").and_then(|l| l.split_once(' ')) else {
+            return;
+        };
+
+        match event {
+            "RUN" => self.total = arg.parse().ok(),
+            "CASE" => self.running = Some(arg.to_string()),
+            "OK" => {
+                self.running = None;
+                self.passed += 1;
+            }
+            "FAILED" => {
+                self.running = None;
+                self.failed.push(arg.to_string());
+            }
+            _ => {}
+        }
+    }
+
+    /// Describes the test that was running when the run was cut short.
+    fn context(&self) -> String {
+        match &self.running {
+            Some(test) => format!(" while running `{test}`"),
+            None => String::new(),
+        }
+    }
+
+    fn print(&self) {
+        let Some(total) = self.total else {
+            return;
+        };
+
+        if !self.failed.is_empty() {
+            status!("\nfailures:");
+            for test in &self.failed {
+                status!("    {test}");
+            }
+        }
+
+        let status = if self.failed.is_empty() && self.passed == total {
+            "ok"
+        } else {
+            "FAILED"
+        };
+        status!(
+            "\ntest result: {status}. {} passed; {} failed; {} not run",
+            self.passed,
+            self.failed.len(),
+            total.saturating_sub(self.passed + self.failed.len())
+        );
+    }
+}
+
+/// Gets the start of the banner the kernel in `image` prints at boot,
+/// which includes the commit from its build ID, if any.
+pub fn banner(image: &KernelImage) -> String {
+    let version = image.kernel_meta().1.version;
+    let mut banner = format!(
+        "Onyx {}.{}.{}",
+        version >> 24,
+        (version >> 16) & 0xFF,
+        (version >> 8) & 0xFF
+    );
+    if let Some(id) = image.build_id() {
+        banner.push_str(" (");
+        banner.push_str(id.commit().get(..12).unwrap_or("unknown"));
+    }
+
+    banner
+}
+
+/// Builds a minimal device tree blob to embed into test images.
+pub fn embedded_dtb() -> Vec<u8> {
+    let mut fdt = FdtWriter::new();
+
+    fdt.begin_node("");
+    fdt.property_u32("#address-cells", 2);
+    fdt.property_u32("#size-cells", 2);
+    fdt.property_string("compatible", "onyx,test");
+    fdt.begin_node("chosen");
+    fdt.end_node();
+    fdt.end_node();
+
+    fdt.finish()
+}
+
+/// Runs the unit tests of a package on the host through `cargo test`.
+pub fn test_on_host(pkg: &str, release: bool) -> anyhow::Result<()> {
+    let release_arg = if release { &["--release"][..] } else { &[] };
+    let mut cargo = Command::new("cargo")
+        .current_dir(rustc::project_root())
+        .args(["test", "-p", pkg])
+        .args(release_arg)
+        .stdout(Stdio::piped())
+        .spawn()?;
+
+    // Forward the test report like our own progress output.
+    let stdout = cargo.stdout.take().unwrap();
+    for line in BufReader::new(stdout).lines() {
+        status!("{}", line?);
+    }
+
+    let status = cargo.wait()?;
+    if !status.success() {
+        bail!("`cargo test -p {pkg}` failed with {status}");
+    }
+    Ok(())
+}
+
+fn spawn_qemu(image: PathBuf, config: &Config) -> anyhow::Result<Child> {
+    let system = format!("qemu-system-{}", config.qemu.name);
+    Command::new(&system)
+        .current_dir(rustc::project_root())
+        .arg("-m")
+        .arg(format!("{}M", config.memory.size >> 20))
+        .args(&config.qemu.extra_args)
+        // A reset ends the run instead of rebooting the kernel.
+        .arg("-no-reboot")
+        .arg("-device")
+        .arg(format!(
+            "loader,file={},addr={}",
+            image.display(),
+            config.qemu.address
+        ))
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .spawn()
+        .with_context(|| format!("failed to launch {system}"))
+}
+
+fn wait_timeout(child: &mut Child, timeout: Duration) -> anyhow::Result<Option<ExitStatus>> {
+    let deadline = Instant::now() + timeout;
+    while Instant::now() < deadline {
+        if let Some(status) = child.try_wait()? {
+            return Ok(Some(status));
+        }
+        thread::sleep(Duration::from_millis(50));
+    }
+
+    child.kill()?;
+    child.wait()?;
+    Ok(None)
+}
@@ -0,0 +1,417 @@
+use std::{fmt, ops::Range, path::Path};
+
+use binrw::Endian;
+use ed25519_dalek::{Signature, VerifyingKey};
+use onyx_image::RevisionError;
+use sha2::{Digest, Sha256};
+
+use crate::{
+    config::Config,
+    fdt::FDT_MAGIC,
+    image::{self, ImageTrailer, KernelImage, KernelMeta, PAGE_SIZE},
+    rustc,
+};
+
+/// The maximum offset of the [`KernelMeta`] in the kernel binary.
+///
+/// It is fair to assume it's a logic bug when metadata are *too* far in.
+const MAX_META_OFFSET: usize = 0x10;
+
+/// A violated invariant of a kernel binary or a Kernel Image.
+#[derive(Debug, thiserror::Error)]
+pub enum Violation {
+    /// The [`KernelMeta`] was found at a suspicious offset.
+    #[error("metadata found at suspicious offset {0:#x}; expected in 0x1..={MAX_META_OFFSET:#x}")]
+    MetaOffset(usize),
+
+    /// The [`KernelMeta`] is of a revision that cannot be understood.
+    #[error("{0}")]
+    Revision(RevisionError),
+
+    /// A section ends before it starts.
+    #[error("section {name} ends at {end:#x} before it starts at {start:#x}")]
+    InvertedSection {
+        name: &'static str,
+        start: u64,
+        end: u64,
+    },
+
+    /// Two consecutive sections are out of order or overlap.
+    #[error(
+        "section {first} (ends at {end:#x}) runs into section {second} (starts at {start:#x})"
+    )]
+    SectionOrder {
+        first: &'static str,
+        second: &'static str,
+        end: u64,
+        start: u64,
+    },
+
+    /// A section boundary or image offset is not aligned to the page size.
+    #[error("{name} at {offset:#x} is not aligned to {align:#x}")]
+    Misaligned {
+        name: &'static str,
+        offset: u64,
+        align: u64,
+    },
+
+    /// The .bss section does not fit into the kernel memory layout.
+    #[error("section .bss ends at {bss_end:#x} beyond the kernel end at {kernel_end:#x}")]
+    BssOverflow { bss_end: u64, kernel_end: u64 },
+
+    /// The kernel binary is larger than its memory layout describes.
+    #[error("kernel binary of size {size:#x} exceeds the kernel end at {kernel_end:#x}")]
+    KernelOverflow { size: u64, kernel_end: u64 },
+
+    /// Two components of the image overlap each other in memory.
+    #[error("{first} ({:#x}..{:#x}) overlaps {second} ({:#x}..{:#x})",
+        .first_range.start, .first_range.end, .second_range.start, .second_range.end)]
+    Overlap {
+        first: &'static str,
+        first_range: Range<u64>,
+        second: &'static str,
+        second_range: Range<u64>,
+    },
+
+    /// The embedded device tree blob is malformed.
+    #[error("embedded device tree is malformed: {0}")]
+    InvalidDtb(&'static str),
+
+    /// The kernel version was not encoded into the image.
+    #[error("kernel version was not encoded (found {0:#010x})")]
+    MissingVersion(u32),
+
+    /// The image digest in the trailer does not match the contents.
+    #[error("image digest mismatch; image is corrupted")]
+    DigestMismatch,
+
+    /// The image was expected to be signed, but is not.
+    #[error("image is not signed")]
+    MissingSignature,
+
+    /// The image signature does not match the expected key.
+    #[error("image signature is invalid for the configured key")]
+    InvalidSignature,
+}
+
+/// Checks the invariants of a kernel binary's memory layout.
+///
+/// `meta_offset` and `size` describe the location of the metadata
+/// and the size of the raw kernel binary, respectively.
+pub fn check_kernel(meta_offset: usize, size: usize, meta: &KernelMeta) -> Vec<Violation> {
+    let mut violations = Vec::new();
+    let layout = &meta.layout;
+
+    // The image needs to start with executable code.
+    if meta_offset == 0 || meta_offset > MAX_META_OFFSET {
+        violations.push(Violation::MetaOffset(meta_offset));
+    }
+    if let Err(e) = meta.compatibility() {
+        violations.push(Violation::Revision(e));
+    }
+
+    let sections = [
+        (".text", layout.text_start, layout.text_end),
+        (".rodata", layout.rodata_start, layout.rodata_end),
+        (".data", layout.data_start, layout.data_end),
+        (".bss", layout.bss_start, layout.bss_end),
+    ];
+    for (name, start, end) in sections {
+        if start > end {
+            violations.push(Violation::InvertedSection {
+                name,
+                start: start as u64,
+                end: end as u64,
+            });
+        }
+
+        check_aligned(&mut violations, name, start as u64, PAGE_SIZE);
+        check_aligned(&mut violations, name, end as u64, PAGE_SIZE);
+    }
+    for pair in sections.windows(2) {
+        let ((first, _, end), (second, start, _)) = (pair[0], pair[1]);
+        if end > start {
+            violations.push(Violation::SectionOrder {
+                first,
+                second,
+                end: end as u64,
+                start: start as u64,
+            });
+        }
+    }
+
+    if layout.bss_end > layout.kernel_end {
+        violations.push(Violation::BssOverflow {
+            bss_end: layout.bss_end as u64,
+            kernel_end: layout.kernel_end as u64,
+        });
+    }
+    if size > layout.kernel_end as usize {
+        violations.push(Violation::KernelOverflow {
+            size: size as u64,
+            kernel_end: layout.kernel_end as u64,
+        });
+    }
+
+    violations
+}
+
+/// Checks all invariants of a finished Kernel Image.
+///
+/// `data` is the encoded image that was parsed into `image`. When a
+/// `key` is given, the image must carry a valid signature for it.
+pub fn check_image(data: &[u8], image: &KernelImage, key: Option<&VerifyingKey>) -> Vec<Violation> {
+    let (meta_offset, meta) = image.kernel_meta();
+    let mut violations = check_kernel(meta_offset, image.kernel().len(), meta);
+
+    // Everything behind the kernel starts on a page boundary.
+    let components = image.components();
+    for (name, range) in &components[1..] {
+        check_aligned(&mut violations, name, range.start, image.page_size());
+    }
+
+    if let Some(dtb) = image.dtb() {
+        check_dtb(&mut violations, dtb);
+    }
+
+    if let Some(trailer) = image.trailer() {
+        let base = meta.trailer_base as usize;
+        check_trailer(&mut violations, &data[..base], trailer, key);
+    } else if key.is_some() {
+        violations.push(Violation::MissingSignature);
+    }
+
+    // The kernel and the Kernel Loader occupy more memory than their
+    // binaries once they clear their .bss sections, and nothing behind
+    // them may overlap that.
+    let mut footprints = components;
+    footprints[0].1.end = footprints[0].1.end.max(meta.layout.kernel_end as u64);
+    if let Some(end) = meta.loader_end() {
+        footprints[1].1.end = footprints[1].1.end.max(end);
+    }
+
+    for (i, (first, first_range)) in footprints.iter().enumerate() {
+        for (second, second_range) in &footprints[i + 1..] {
+            if first_range.start < second_range.end && second_range.start < first_range.end {
+                violations.push(Violation::Overlap {
+                    first,
+                    first_range: first_range.clone(),
+                    second,
+                    second_range: second_range.clone(),
+                });
+            }
+        }
+    }
+
+    // The placeholder from `start.s` must have been replaced.
+    if meta.version == u32::MAX {
+        violations.push(Violation::MissingVersion(meta.version));
+    }
+
+    violations
+}
+
+/// Turns a list of violations into an error, if there are any.
+pub fn ensure_valid(what: &str, violations: Vec<Violation>) -> anyhow::Result<()> {
+    if violations.is_empty() {
+        return Ok(());
+    }
+
+    Err(anyhow::anyhow!(Report(violations)).context(format!("{what} verification failed")))
+}
+
+/// Verifies the Kernel Image at the given path and reports all
+/// violated invariants.
+///
+/// The build [`Config`] determines the image endianness and the key
+/// to verify signatures with, if any.
+pub fn verify<P: AsRef<Path>>(path: P, config: Option<&Config>) -> anyhow::Result<()> {
+    let path = path.as_ref();
+
+    let endian = config.map_or(Endian::Little, |c| c.endian);
+    let key = match config.and_then(|c| c.image.signing_key.as_ref()) {
+        Some(key) => {
+            Some(image::read_signing_key(rustc::project_root().join(key))?.verifying_key())
+        }
+        None => None,
+    };
+
+    let (data, _) = image::read_image(path, endian)?;
+    let image = KernelImage::parse(&data, endian)?;
+    ensure_valid("Kernel Image", check_image(&data, &image, key.as_ref()))?;
+
+    status!("{}: all invariants hold", path.display());
+    Ok(())
+}
+
+fn check_trailer(
+    violations: &mut Vec<Violation>,
+    image: &[u8],
+    trailer: &ImageTrailer,
+    key: Option<&VerifyingKey>,
+) {
+    if Sha256::digest(image)[..] != trailer.digest {
+        violations.push(Violation::DigestMismatch);
+    }
+
+    if let Some(key) = key {
+        if trailer.flags & ImageTrailer::FLAG_SIGNED == 0 {
+            violations.push(Violation::MissingSignature);
+        } else {
+            let signature = Signature::from_bytes(&trailer.signature);
+            if key.verify_strict(&trailer.digest, &signature).is_err() {
+                violations.push(Violation::InvalidSignature);
+            }
+        }
+    }
+}
+
+fn check_dtb(violations: &mut Vec<Violation>, dtb: &[u8]) {
+    let header = |i: usize| {
+        dtb.get(i * 4..i * 4 + 4)
+            .map(|b| u32::from_be_bytes(b.try_into().unwrap()))
+    };
+
+    match (header(0), header(1)) {
+        (Some(FDT_MAGIC), Some(size)) if size as usize <= dtb.len() => {}
+        (Some(FDT_MAGIC), _) => violations.push(Violation::InvalidDtb("blob is truncated")),
+        _ => violations.push(Violation::InvalidDtb("bad magic")),
+    }
+}
+
+fn check_aligned(violations: &mut Vec<Violation>, name: &'static str, offset: u64, align: usize) {
+    let align = align as u64;
+    if offset % align != 0 {
+        violations.push(Violation::Misaligned {
+            name,
+            offset,
+            align,
+        });
+    }
+}
+
+/// A list of [`Violation`]s, displayed one per line.
+#[derive(Debug)]
+struct Report(Vec<Violation>);
+
+impl fmt::Display for Report {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "{} violation(s) found:", self.0.len())?;
+        for violation in &self.0 {
+            writeln!(f, "  - {violation}")?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for Report {}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use binrw::{BinRead, BinWrite};
+
+    use super::*;
+    use crate::image::{find_kernel_meta, KernelLayout};
+
+    /// Encodes an image whose kernel has a .bss section beyond the end
+    /// of its binary, and whose loader has one beyond its binary, too.
+    fn image() -> Vec<u8> {
+        let meta = KernelMeta {
+            version: u32::MAX,
+            layout: KernelLayout {
+                text_end: 0x1000,
+                rodata_start: 0x1000,
+                rodata_end: 0x1000,
+                data_start: 0x1000,
+                data_end: 0x1000,
+                bss_start: 0x1000,
+                bss_end: 0x3000,
+                kernel_end: 0x3000,
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+
+        let mut kernel = Cursor::new(vec![0x6F, 0, 0, 0, 0, 0, 0, 0]);
+        kernel.set_position(8);
+        meta.write_options(&mut kernel, Endian::Little, ()).unwrap();
+        let mut kernel = kernel.into_inner();
+        kernel.resize(0x1000, 0);
+
+        KernelImage::new()
+            .pack_kernel_bytes(kernel, 8)
+            .unwrap()
+            .pack_loader_bytes(vec![0x13; 0x100], 0x2000)
+            .unwrap()
+            .with_cmdline("loglevel=debug".to_string())
+            .encode()
+            .unwrap()
+    }
+
+    fn patch_meta(data: &mut [u8], patch: impl FnOnce(&mut KernelMeta)) {
+        let offset = find_kernel_meta(data).unwrap();
+        let mut cursor = Cursor::new(data);
+        cursor.set_position(offset as u64);
+        let mut meta = KernelMeta::read_options(&mut cursor, Endian::Little, ()).unwrap();
+
+        patch(&mut meta);
+        cursor.set_position(offset as u64);
+        meta.write_options(&mut cursor, Endian::Little, ()).unwrap();
+    }
+
+    fn check(data: &[u8]) -> Vec<Violation> {
+        check_image(
+            data,
+            &KernelImage::parse(data, Endian::Little).unwrap(),
+            None,
+        )
+    }
+
+    fn overlaps(violations: &[Violation], a: &str, b: &str) -> bool {
+        violations.iter().any(|v| {
+            matches!(v, Violation::Overlap { first, second, .. } if *first == a && *second == b)
+        })
+    }
+
+    #[test]
+    fn accepts_encoded_images() {
+        // Version 0.0.0 is a legitimate version, not a placeholder.
+        let violations = check(&image());
+        assert!(violations.is_empty(), "{violations:?}");
+    }
+
+    #[test]
+    fn detects_loader_in_kernel_bss() {
+        let mut data = image();
+        patch_meta(&mut data, |meta| {
+            meta.loader_end -= meta.loader_base - 0x1000;
+            meta.loader_base = 0x1000;
+        });
+
+        assert!(overlaps(&check(&data), "kernel", "Kernel Loader"));
+    }
+
+    #[test]
+    fn detects_blobs_in_loader_bss() {
+        let mut data = image();
+        patch_meta(&mut data, |meta| {
+            meta.cmdline_base = meta.loader_base + 0x1000
+        });
+
+        assert!(overlaps(&check(&data), "Kernel Loader", "command line"));
+    }
+
+    #[test]
+    fn detects_version_placeholder() {
+        let mut data = image();
+        patch_meta(&mut data, |meta| meta.version = u32::MAX);
+
+        let violations = check(&data);
+        assert!(matches!(
+            violations[..],
+            [Violation::MissingVersion(u32::MAX)]
+        ));
+    }
+}
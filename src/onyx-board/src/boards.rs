@@ -0,0 +1,17 @@
+#[cfg(feature = "generic")]
+#[path = "boards/generic.rs"]
+mod imp;
+
+#[cfg(feature = "qemu-virt")]
+#[path = "boards/qemu_virt.rs"]
+mod imp;
+
+#[cfg(feature = "qemu-sifive-u")]
+#[path = "boards/qemu_sifive_u.rs"]
+mod imp;
+
+#[cfg(not(any(feature = "generic", feature = "qemu-virt", feature = "qemu-sifive-u")))]
+compile_error!("no board selected; enable exactly one board feature");
+
+/// The board this build targets.
+pub use imp::Board as CurrentBoard;
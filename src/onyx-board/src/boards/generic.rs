@@ -0,0 +1,58 @@
+//! A generic board which only relies on SBI firmware.
+//!
+//! Everything beyond the services of the firmware must be discovered
+//! through the device tree, so this is the starting point for boards
+//! without dedicated support.
+
+use crate::{
+    drivers::sbi::{self, SbiConsole, SbiTimer},
+    InterruptController, MemoryRegion,
+};
+
+/// The timer frequency assumed until the device tree is consulted.
+const TIMEBASE_FREQUENCY: u64 = 10_000_000;
+
+pub struct Board;
+
+impl crate::Board for Board {
+    const NAME: &'static str = "generic";
+
+    const MEMORY_MAP: &'static [MemoryRegion] = &[];
+
+    type Console = SbiConsole;
+    type Timer = SbiTimer;
+    type InterruptController = NoInterruptController;
+
+    fn console() -> Self::Console {
+        SbiConsole
+    }
+
+    fn timer() -> Self::Timer {
+        SbiTimer::new(TIMEBASE_FREQUENCY)
+    }
+
+    fn interrupt_controller(_hart: usize) -> Self::InterruptController {
+        NoInterruptController
+    }
+
+    fn reset() -> ! {
+        sbi::reset()
+    }
+}
+
+/// Stands in for the interrupt controller until one is discovered.
+///
+/// No external interrupt is ever delivered through it.
+pub struct NoInterruptController;
+
+impl InterruptController for NoInterruptController {
+    fn enable(&mut self, _irq: u32) {}
+
+    fn disable(&mut self, _irq: u32) {}
+
+    fn claim(&mut self) -> Option<u32> {
+        None
+    }
+
+    fn complete(&mut self, _irq: u32) {}
+}
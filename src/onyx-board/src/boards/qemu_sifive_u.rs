@@ -0,0 +1,72 @@
+//! QEMU's `sifive_u` machine, modelling the SiFive HiFive Unleashed.
+
+use crate::{
+    drivers::{
+        plic::Plic,
+        sbi::{self, SbiTimer},
+        sifive_uart::SifiveUart,
+    },
+    MemoryKind, MemoryRegion,
+};
+
+const CLINT_BASE: usize = 0x0200_0000;
+const PLIC_BASE: usize = 0x0C00_0000;
+const UART0_BASE: usize = 0x1001_0000;
+
+/// The frequency of the `time` CSR.
+const TIMEBASE_FREQUENCY: u64 = 1_000_000;
+
+pub struct Board;
+
+impl crate::Board for Board {
+    const NAME: &'static str = "qemu-sifive-u";
+
+    const MEMORY_MAP: &'static [MemoryRegion] = &[
+        MemoryRegion {
+            base: CLINT_BASE,
+            size: 0x1_0000,
+            kind: MemoryKind::Mmio,
+        },
+        MemoryRegion {
+            base: PLIC_BASE,
+            size: 0x400_0000,
+            kind: MemoryKind::Mmio,
+        },
+        MemoryRegion {
+            base: UART0_BASE,
+            size: 0x1000,
+            kind: MemoryKind::Mmio,
+        },
+        // QEMU is started with the RAM of the build config.
+        MemoryRegion {
+            base: crate::MEMORY_BASE,
+            size: crate::MEMORY_SIZE,
+            kind: MemoryKind::Ram,
+        },
+    ];
+
+    type Console = SifiveUart;
+    type Timer = SbiTimer;
+    type InterruptController = Plic;
+
+    fn console() -> Self::Console {
+        // SAFETY: The UART is always present on this machine.
+        unsafe { SifiveUart::new(UART0_BASE) }
+    }
+
+    fn timer() -> Self::Timer {
+        SbiTimer::new(TIMEBASE_FREQUENCY)
+    }
+
+    fn interrupt_controller(hart: usize) -> Self::InterruptController {
+        // Hart 0 is the E51 monitor core with only a machine mode
+        // context. Every other hart has a machine and a supervisor
+        // mode context.
+        // SAFETY: The PLIC is always present on this machine.
+        unsafe { Plic::new(PLIC_BASE, hart * 2) }
+    }
+
+    fn reset() -> ! {
+        sbi::reset()
+    }
+}
@@ -0,0 +1,78 @@
+//! QEMU's `virt` machine.
+
+use crate::{
+    drivers::{ns16550::Ns16550, plic::Plic, sbi::SbiTimer, sifive_test},
+    MemoryKind, MemoryRegion,
+};
+
+const TEST_BASE: usize = 0x0010_0000;
+const CLINT_BASE: usize = 0x0200_0000;
+const PLIC_BASE: usize = 0x0C00_0000;
+const UART0_BASE: usize = 0x1000_0000;
+
+/// The frequency of the `time` CSR.
+const TIMEBASE_FREQUENCY: u64 = 10_000_000;
+
+pub struct Board;
+
+impl crate::Board for Board {
+    const NAME: &'static str = "qemu-virt";
+
+    const MEMORY_MAP: &'static [MemoryRegion] = &[
+        MemoryRegion {
+            base: TEST_BASE,
+            size: 0x1000,
+            kind: MemoryKind::Mmio,
+        },
+        MemoryRegion {
+            base: CLINT_BASE,
+            size: 0x1_0000,
+            kind: MemoryKind::Mmio,
+        },
+        MemoryRegion {
+            base: PLIC_BASE,
+            size: 0x60_0000,
+            kind: MemoryKind::Mmio,
+        },
+        MemoryRegion {
+            base: UART0_BASE,
+            size: 0x100,
+            kind: MemoryKind::Mmio,
+        },
+        // QEMU is started with the RAM of the build config.
+        MemoryRegion {
+            base: crate::MEMORY_BASE,
+            size: crate::MEMORY_SIZE,
+            kind: MemoryKind::Ram,
+        },
+    ];
+
+    type Console = Ns16550;
+    type Timer = SbiTimer;
+    type InterruptController = Plic;
+
+    fn console() -> Self::Console {
+        // SAFETY: The UART is always present on this machine.
+        unsafe { Ns16550::new(UART0_BASE) }
+    }
+
+    fn timer() -> Self::Timer {
+        SbiTimer::new(TIMEBASE_FREQUENCY)
+    }
+
+    fn interrupt_controller(hart: usize) -> Self::InterruptController {
+        // Every hart has a machine and a supervisor mode context.
+        // SAFETY: The PLIC is always present on this machine.
+        unsafe { Plic::new(PLIC_BASE, hart * 2 + 1) }
+    }
+
+    fn reset() -> ! {
+        // SAFETY: The test device is always present on this machine.
+        unsafe { sifive_test::reset(TEST_BASE) }
+    }
+
+    fn shutdown(failure: bool) -> ! {
+        // SAFETY: The test device is always present on this machine.
+        unsafe { sifive_test::exit(TEST_BASE, failure) }
+    }
+}
@@ -0,0 +1,11 @@
+//! Drivers for the peripherals found on supported boards.
+
+pub mod ns16550;
+
+pub mod plic;
+
+pub mod sbi;
+
+pub mod sifive_test;
+
+pub mod sifive_uart;
@@ -0,0 +1,41 @@
+//! The NS16550-compatible UART.
+
+use core::ptr;
+
+use crate::Console;
+
+/// Offset of the transmitter holding register.
+const THR: usize = 0;
+/// Offset of the line status register.
+const LSR: usize = 5;
+/// The transmitter holding register is empty.
+const LSR_THRE: u8 = 1 << 5;
+
+/// An NS16550 UART, as previously initialized by firmware.
+pub struct Ns16550 {
+    base: *mut u8,
+}
+
+impl Ns16550 {
+    /// Creates a driver for the UART at `base`.
+    ///
+    /// # Safety
+    ///
+    /// `base` must be the address of the UART registers, which must
+    /// not be accessed through any other means.
+    pub const unsafe fn new(base: usize) -> Self {
+        Self {
+            base: base as *mut u8,
+        }
+    }
+}
+
+impl Console for Ns16550 {
+    fn write_byte(&mut self, byte: u8) {
+        // SAFETY: The registers are valid as per the contract of `new`.
+        unsafe {
+            while ptr::read_volatile(self.base.add(LSR)) & LSR_THRE == 0 {}
+            ptr::write_volatile(self.base.add(THR), byte);
+        }
+    }
+}
@@ -0,0 +1,83 @@
+//! The RISC-V Platform-Level Interrupt Controller.
+
+use core::ptr;
+
+use crate::InterruptController;
+
+/// Offset of the interrupt source priorities.
+const PRIORITY: usize = 0x0000;
+/// Offset of the enable bits of the first context.
+const ENABLE: usize = 0x2000;
+/// Stride between the enable bits of consecutive contexts.
+const ENABLE_STRIDE: usize = 0x80;
+/// Offset of the threshold register of the first context.
+const THRESHOLD: usize = 0x20_0000;
+/// Offset of the claim/complete register of the first context.
+const CLAIM: usize = 0x20_0004;
+/// Stride between the registers of consecutive contexts.
+const CONTEXT_STRIDE: usize = 0x1000;
+
+/// A PLIC as seen from a single hart context.
+///
+/// Which context belongs to the supervisor mode of which hart is
+/// specific to each board.
+pub struct Plic {
+    base: *mut u8,
+    context: usize,
+}
+
+impl Plic {
+    /// Creates a driver for `context` of the PLIC at `base`.
+    ///
+    /// # Safety
+    ///
+    /// `base` must be the address of the PLIC registers and `context`
+    /// must be owned by the caller.
+    pub const unsafe fn new(base: usize, context: usize) -> Self {
+        Self {
+            base: base as *mut u8,
+            context,
+        }
+    }
+
+    fn reg(&self, offset: usize) -> *mut u32 {
+        // SAFETY: All offsets computed by us stay within the PLIC.
+        unsafe { self.base.add(offset).cast() }
+    }
+
+    fn enable_reg(&self, irq: u32) -> (*mut u32, u32) {
+        let offset = ENABLE + self.context * ENABLE_STRIDE + (irq as usize / 32) * 4;
+        (self.reg(offset), 1 << (irq % 32))
+    }
+}
+
+impl InterruptController for Plic {
+    fn enable(&mut self, irq: u32) {
+        let (reg, bit) = self.enable_reg(irq);
+
+        // SAFETY: The registers are valid as per the contract of `new`.
+        unsafe {
+            ptr::write_volatile(self.reg(PRIORITY + irq as usize * 4), 1);
+            ptr::write_volatile(self.reg(THRESHOLD + self.context * CONTEXT_STRIDE), 0);
+            ptr::write_volatile(reg, ptr::read_volatile(reg) | bit);
+        }
+    }
+
+    fn disable(&mut self, irq: u32) {
+        let (reg, bit) = self.enable_reg(irq);
+
+        // SAFETY: The registers are valid as per the contract of `new`.
+        unsafe { ptr::write_volatile(reg, ptr::read_volatile(reg) & !bit) };
+    }
+
+    fn claim(&mut self) -> Option<u32> {
+        // SAFETY: The registers are valid as per the contract of `new`.
+        let irq = unsafe { ptr::read_volatile(self.reg(CLAIM + self.context * CONTEXT_STRIDE)) };
+        (irq != 0).then_some(irq)
+    }
+
+    fn complete(&mut self, irq: u32) {
+        // SAFETY: The registers are valid as per the contract of `new`.
+        unsafe { ptr::write_volatile(self.reg(CLAIM + self.context * CONTEXT_STRIDE), irq) };
+    }
+}
@@ -0,0 +1,109 @@
+//! Services of the RISC-V Supervisor Binary Interface firmware.
+
+use core::arch::asm;
+
+use crate::{Console, Timer};
+
+/// The legacy console putchar extension.
+const EID_CONSOLE_PUTCHAR: usize = 0x01;
+/// The timer extension.
+const EID_TIME: usize = 0x5449_4D45;
+/// The system reset extension.
+const EID_SRST: usize = 0x5352_5354;
+
+/// A shutdown of the system.
+const RESET_TYPE_SHUTDOWN: usize = 0;
+/// A cold reboot of the system.
+const RESET_TYPE_COLD_REBOOT: usize = 1;
+/// No particular reason for a system reset.
+const RESET_REASON_NONE: usize = 0;
+/// The system reset due to a failure.
+const RESET_REASON_SYSTEM_FAILURE: usize = 1;
+
+/// Performs an SBI call and returns the error and value pair.
+///
+/// # Safety
+///
+/// The call must not violate any memory safety guarantees.
+unsafe fn call(eid: usize, fid: usize, arg0: usize, arg1: usize) -> (isize, usize) {
+    let error: isize;
+    let value: usize;
+    asm!(
+        "ecall",
+        inlateout("a0") arg0 => error,
+        inlateout("a1") arg1 => value,
+        in("a6") fid,
+        in("a7") eid,
+        options(nostack),
+    );
+    (error, value)
+}
+
+/// Resets the system through the SBI system reset extension.
+pub fn reset() -> ! {
+    system_reset(RESET_TYPE_COLD_REBOOT, RESET_REASON_NONE)
+}
+
+/// Shuts the system down through the SBI system reset extension.
+///
+/// With `failure`, the firmware is told that the system failed. It may
+/// ignore the reason; OpenSBI does so under QEMU, which then exits
+/// successfully either way.
+pub fn shutdown(failure: bool) -> ! {
+    let reason = if failure {
+        RESET_REASON_SYSTEM_FAILURE
+    } else {
+        RESET_REASON_NONE
+    };
+    system_reset(RESET_TYPE_SHUTDOWN, reason)
+}
+
+fn system_reset(kind: usize, reason: usize) -> ! {
+    // SAFETY: Resetting the system cannot violate memory safety.
+    unsafe { call(EID_SRST, 0, kind, reason) };
+
+    // The firmware lacks the extension, so there is nothing left to do.
+    loop {
+        core::hint::spin_loop();
+    }
+}
+
+/// A console backed by the firmware.
+pub struct SbiConsole;
+
+impl Console for SbiConsole {
+    fn write_byte(&mut self, byte: u8) {
+        // SAFETY: Printing a character cannot violate memory safety.
+        unsafe { call(EID_CONSOLE_PUTCHAR, 0, byte as usize, 0) };
+    }
+}
+
+/// The `time` CSR with timer interrupts programmed through the firmware.
+pub struct SbiTimer {
+    frequency: u64,
+}
+
+impl SbiTimer {
+    /// Creates a timer which ticks at `frequency` Hz.
+    pub const fn new(frequency: u64) -> Self {
+        Self { frequency }
+    }
+}
+
+impl Timer for SbiTimer {
+    fn frequency(&self) -> u64 {
+        self.frequency
+    }
+
+    fn now(&self) -> u64 {
+        let time: u64;
+        // SAFETY: Reading the time CSR has no side effects.
+        unsafe { asm!("rdtime {}", out(reg) time, options(nomem, nostack)) };
+        time
+    }
+
+    fn set_deadline(&mut self, deadline: u64) {
+        // SAFETY: Programming the timer cannot violate memory safety.
+        unsafe { call(EID_TIME, 0, deadline as usize, 0) };
+    }
+}
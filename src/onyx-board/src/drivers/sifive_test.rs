@@ -0,0 +1,42 @@
+//! The SiFive test device, which QEMU uses to end the emulation.
+
+use core::ptr;
+
+/// Ends the emulation successfully.
+const FINISHER_PASS: u32 = 0x5555;
+/// Ends the emulation with the exit code in the upper 16 bits.
+const FINISHER_FAIL: u32 = 0x3333;
+/// Requests a system reset.
+const FINISHER_RESET: u32 = 0x7777;
+
+/// Resets the system through the test device at `base`.
+///
+/// # Safety
+///
+/// `base` must be the address of a SiFive test device.
+pub unsafe fn reset(base: usize) -> ! {
+    ptr::write_volatile(base as *mut u32, FINISHER_RESET);
+
+    loop {
+        core::hint::spin_loop();
+    }
+}
+
+/// Ends the emulation through the test device at `base`, with an exit
+/// status of 1 on `failure` and 0 otherwise.
+///
+/// # Safety
+///
+/// `base` must be the address of a SiFive test device.
+pub unsafe fn exit(base: usize, failure: bool) -> ! {
+    let code = if failure {
+        1 << 16 | FINISHER_FAIL
+    } else {
+        FINISHER_PASS
+    };
+    ptr::write_volatile(base as *mut u32, code);
+
+    loop {
+        core::hint::spin_loop();
+    }
+}
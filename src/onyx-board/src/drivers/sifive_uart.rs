@@ -0,0 +1,40 @@
+//! The UART of SiFive SoCs.
+
+use core::ptr;
+
+use crate::Console;
+
+/// Offset of the transmit data register.
+const TXDATA: usize = 0x00;
+/// The transmit FIFO is full.
+const TXDATA_FULL: u32 = 1 << 31;
+
+/// A SiFive UART, as previously initialized by firmware.
+pub struct SifiveUart {
+    base: *mut u8,
+}
+
+impl SifiveUart {
+    /// Creates a driver for the UART at `base`.
+    ///
+    /// # Safety
+    ///
+    /// `base` must be the address of the UART registers, which must
+    /// not be accessed through any other means.
+    pub const unsafe fn new(base: usize) -> Self {
+        Self {
+            base: base as *mut u8,
+        }
+    }
+}
+
+impl Console for SifiveUart {
+    fn write_byte(&mut self, byte: u8) {
+        // SAFETY: The registers are valid as per the contract of `new`.
+        unsafe {
+            let txdata = self.base.add(TXDATA).cast::<u32>();
+            while ptr::read_volatile(txdata) & TXDATA_FULL != 0 {}
+            ptr::write_volatile(txdata, byte as u32);
+        }
+    }
+}
@@ -0,0 +1,149 @@
+//! Board support for the platforms Onyx runs on.
+//!
+//! A board bundles the peripherals needed before the kernel is able
+//! to discover the rest of the system: an early console, the timer,
+//! the interrupt controller, the physical memory map and a way to
+//! reset the machine. Every board implements [`Board`] on top of the
+//! drivers in [`drivers`].
+//!
+//! The board for a build is selected through exactly one cargo feature
+//! and is available as [`CurrentBoard`]. Adding a board amounts to a
+//! new module in `boards/`, a feature to select it and a build config.
+
+#![no_std]
+
+pub mod drivers;
+
+mod boards;
+pub use boards::CurrentBoard;
+
+/// The physical start address of RAM, as given by the `memory.base`
+/// key of the build configuration.
+///
+/// `cargo xtask` passes it in `ONYX_MEMORY_BASE`.
+pub const MEMORY_BASE: usize = match option_env!("ONYX_MEMORY_BASE") {
+    Some(base) => parse_decimal(base.as_bytes()),
+    None => 0,
+};
+
+/// The size of RAM in bytes, as given by the `memory.size` key of the
+/// build configuration.
+///
+/// `cargo xtask` passes it in `ONYX_MEMORY_SIZE`. Builds without it
+/// describe no statically known RAM and rely on the device tree.
+pub const MEMORY_SIZE: usize = match option_env!("ONYX_MEMORY_SIZE") {
+    Some(size) => parse_decimal(size.as_bytes()),
+    None => 0,
+};
+
+/// A platform Onyx can boot on.
+pub trait Board {
+    /// The name of the board, matching its cargo feature.
+    const NAME: &'static str;
+
+    /// The statically known regions of the physical address space.
+    ///
+    /// Boards that rely on firmware to describe them leave this empty,
+    /// in which case the device tree is the only source of truth.
+    const MEMORY_MAP: &'static [MemoryRegion];
+
+    /// The console for early boot output.
+    type Console: Console;
+    /// The timer of each hart.
+    type Timer: Timer;
+    /// The external interrupt controller.
+    type InterruptController: InterruptController;
+
+    /// Gets the early boot console.
+    fn console() -> Self::Console;
+
+    /// Gets the timer of the calling hart.
+    fn timer() -> Self::Timer;
+
+    /// Gets the interrupt controller as seen from supervisor mode on
+    /// the given hart.
+    fn interrupt_controller(hart: usize) -> Self::InterruptController;
+
+    /// Resets the whole system.
+    fn reset() -> !;
+
+    /// Shuts the whole system down.
+    ///
+    /// With `failure`, boards which can tell an emulator about it make
+    /// it exit with a non-zero status.
+    fn shutdown(failure: bool) -> ! {
+        drivers::sbi::shutdown(failure)
+    }
+}
+
+/// A byte-oriented output device.
+pub trait Console {
+    /// Writes a single byte, blocking until the device accepts it.
+    fn write_byte(&mut self, byte: u8);
+
+    /// Writes all bytes of a string.
+    fn write_str(&mut self, s: &str) {
+        s.bytes().for_each(|b| self.write_byte(b));
+    }
+}
+
+/// A monotonic per-hart timer.
+pub trait Timer {
+    /// Gets the frequency of the timer in Hz.
+    fn frequency(&self) -> u64;
+
+    /// Gets the current value of the timer in ticks.
+    fn now(&self) -> u64;
+
+    /// Arms a timer interrupt for when [`Timer::now`] reaches `deadline`.
+    fn set_deadline(&mut self, deadline: u64);
+}
+
+/// An external interrupt controller for a single hart context.
+pub trait InterruptController {
+    /// Unmasks an interrupt source.
+    fn enable(&mut self, irq: u32);
+
+    /// Masks an interrupt source.
+    fn disable(&mut self, irq: u32);
+
+    /// Claims the highest-priority pending interrupt, if any.
+    fn claim(&mut self) -> Option<u32>;
+
+    /// Signals that handling of a claimed interrupt has completed.
+    fn complete(&mut self, irq: u32);
+}
+
+/// A region of the physical address space.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct MemoryRegion {
+    /// The physical base address of the region.
+    pub base: usize,
+    /// The size of the region in bytes.
+    pub size: usize,
+    /// What the region is backed by.
+    pub kind: MemoryKind,
+}
+
+/// The kind of a [`MemoryRegion`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MemoryKind {
+    /// Main memory.
+    Ram,
+    /// Memory-mapped device registers.
+    Mmio,
+}
+
+const fn parse_decimal(digits: &[u8]) -> usize {
+    assert!(!digits.is_empty(), "ONYX_MEMORY_* must not be empty");
+
+    let mut value = 0;
+    let mut i = 0;
+    while i < digits.len() {
+        assert!(digits[i].is_ascii_digit(), "ONYX_MEMORY_* must be a decimal number");
+        value = value * 10 + (digits[i] - b'0') as usize;
+        i += 1;
+    }
+
+    value
+}
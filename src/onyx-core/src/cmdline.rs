@@ -0,0 +1,193 @@
+//! Parsing of the kernel command line and its typed early parameters.
+//!
+//! The command line is a whitespace-separated list of `key=value`
+//! parameters and bare `key` flags. When a key is given more than
+//! once, the last occurrence wins.
+
+/// A parsed view of a kernel command line.
+#[derive(Clone, Copy, Debug)]
+pub struct CommandLine<'a> {
+    raw: &'a str,
+}
+
+impl<'a> CommandLine<'a> {
+    /// Wraps a raw command line string.
+    pub const fn new(raw: &'a str) -> Self {
+        Self { raw }
+    }
+
+    /// Gets the raw command line string.
+    pub fn as_str(&self) -> &'a str {
+        self.raw
+    }
+
+    /// Iterates over all parameters in order, as pairs of the key and
+    /// the value, if any.
+    pub fn params(&self) -> impl Iterator<Item = (&'a str, Option<&'a str>)> {
+        self.raw
+            .split_ascii_whitespace()
+            .map(|param| match param.split_once('=') {
+                Some((key, value)) => (key, Some(value)),
+                None => (param, None),
+            })
+    }
+
+    /// Looks up a parameter by its key and parses its value.
+    ///
+    /// Returns [`None`] when the parameter is absent and an error when
+    /// its value is malformed for `T`.
+    pub fn get<T: FromParam<'a>>(&self, key: &str) -> Option<Result<T, ParamError<'a>>> {
+        let value = self.params().filter(|&(k, _)| k == key).last()?.1;
+        Some(T::from_param(value).ok_or(ParamError { value }))
+    }
+}
+
+/// A parameter value that could not be parsed into its type.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ParamError<'a> {
+    /// The offending value.
+    pub value: Option<&'a str>,
+}
+
+/// Types that can be parsed from the value of a command line parameter.
+pub trait FromParam<'a>: Sized {
+    /// Parses the value of a parameter, which is [`None`] for bare flags.
+    fn from_param(value: Option<&'a str>) -> Option<Self>;
+}
+
+impl<'a> FromParam<'a> for &'a str {
+    fn from_param(value: Option<&'a str>) -> Option<Self> {
+        value
+    }
+}
+
+impl<'a> FromParam<'a> for bool {
+    fn from_param(value: Option<&'a str>) -> Option<Self> {
+        match value {
+            // A bare flag enables the option.
+            None | Some("on" | "yes" | "true" | "1") => Some(true),
+            Some("off" | "no" | "false" | "0") => Some(false),
+            Some(_) => None,
+        }
+    }
+}
+
+impl<'a> FromParam<'a> for usize {
+    fn from_param(value: Option<&'a str>) -> Option<Self> {
+        value?.parse().ok()
+    }
+}
+
+/// The verbosity of kernel log output.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum LogLevel {
+    Error,
+    Warn,
+    Info,
+    Debug,
+    Trace,
+}
+
+impl<'a> FromParam<'a> for LogLevel {
+    fn from_param(value: Option<&'a str>) -> Option<Self> {
+        match value? {
+            "error" => Some(Self::Error),
+            "warn" => Some(Self::Warn),
+            "info" => Some(Self::Info),
+            "debug" => Some(Self::Debug),
+            "trace" => Some(Self::Trace),
+            _ => None,
+        }
+    }
+}
+
+/// The parameters which configure early kernel initialization.
+///
+/// Malformed values fall back to their defaults, so that a typo on the
+/// command line never prevents the kernel from booting.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct EarlyParams {
+    /// The verbosity of log output, from `loglevel=`.
+    ///
+    /// Defaults to [`LogLevel::Info`].
+    pub loglevel: LogLevel,
+    /// The maximum number of harts to bring up, from `smp=`.
+    ///
+    /// Defaults to all available harts.
+    pub smp: Option<usize>,
+    /// Whether to randomize the kernel base address, from `kaslr=`.
+    ///
+    /// Defaults to `true`.
+    pub kaslr: bool,
+    /// Whether the kernel runs under `xtask test`, from `test=`.
+    ///
+    /// Defaults to `false`.
+    pub test: bool,
+}
+
+impl EarlyParams {
+    /// Parses the early parameters from a command line.
+    pub fn parse(cmdline: &CommandLine<'_>) -> Self {
+        Self {
+            loglevel: cmdline
+                .get("loglevel")
+                .and_then(Result::ok)
+                .unwrap_or(LogLevel::Info),
+            smp: cmdline.get("smp").and_then(Result::ok),
+            kaslr: cmdline.get("kaslr").and_then(Result::ok).unwrap_or(true),
+            test: cmdline.get("test").and_then(Result::ok).unwrap_or(false),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn splits_keys_and_values() {
+        let mut params = CommandLine::new("  quiet loglevel=debug  root=/dev/vda=1 ").params();
+        assert_eq!(params.next(), Some(("quiet", None)));
+        assert_eq!(params.next(), Some(("loglevel", Some("debug"))));
+        assert_eq!(params.next(), Some(("root", Some("/dev/vda=1"))));
+        assert_eq!(params.next(), None);
+    }
+
+    #[test]
+    fn last_occurrence_wins() {
+        let cmdline = CommandLine::new("smp=2 smp=4");
+        assert_eq!(cmdline.get::<usize>("smp"), Some(Ok(4)));
+    }
+
+    #[test]
+    fn bare_flags_are_true() {
+        let cmdline = CommandLine::new("kaslr nokaslr=off");
+        assert_eq!(cmdline.get::<bool>("kaslr"), Some(Ok(true)));
+        assert_eq!(cmdline.get::<bool>("nokaslr"), Some(Ok(false)));
+        assert_eq!(cmdline.get::<bool>("missing"), None);
+    }
+
+    #[test]
+    fn reports_malformed_values() {
+        let cmdline = CommandLine::new("smp=many loglevel");
+        assert_eq!(
+            cmdline.get::<usize>("smp"),
+            Some(Err(ParamError {
+                value: Some("many")
+            }))
+        );
+        assert_eq!(
+            cmdline.get::<LogLevel>("loglevel"),
+            Some(Err(ParamError { value: None }))
+        );
+    }
+
+    #[test]
+    fn early_params_fall_back_to_defaults() {
+        let params = EarlyParams::parse(&CommandLine::new("loglevel=loud smp=2 kaslr=maybe"));
+        assert_eq!(params.loglevel, LogLevel::Info);
+        assert_eq!(params.smp, Some(2));
+        assert!(params.kaslr);
+        assert!(!params.test);
+    }
+}
@@ -0,0 +1,217 @@
+//! A minimal reader for the Flattened Device Tree passed at boot.
+//!
+//! This only supports the lookups needed during early boot and does
+//! not allocate.
+
+use core::{ffi::CStr, slice};
+
+use onyx_image::FDT_MAGIC;
+
+const FDT_BEGIN_NODE: u32 = 0x1;
+const FDT_END_NODE: u32 = 0x2;
+const FDT_PROP: u32 = 0x3;
+const FDT_NOP: u32 = 0x4;
+
+/// A validated device tree blob in memory.
+pub struct Fdt<'a> {
+    structure: &'a [u8],
+    strings: &'a [u8],
+}
+
+impl Fdt<'static> {
+    /// Creates a reader for the device tree blob at `dtb`.
+    ///
+    /// Returns [`None`] when `dtb` is null or not a valid blob.
+    ///
+    /// # Safety
+    ///
+    /// `dtb` must either be null or point to a device tree blob which
+    /// stays valid and unmodified for the remaining runtime.
+    pub unsafe fn from_ptr(dtb: *const u8) -> Option<Self> {
+        if dtb.is_null() || read_u32(slice::from_raw_parts(dtb, 8), 0)? != FDT_MAGIC {
+            return None;
+        }
+
+        let total_size = read_u32(slice::from_raw_parts(dtb, 8), 4)? as usize;
+        Self::new(slice::from_raw_parts(dtb, total_size))
+    }
+}
+
+impl<'a> Fdt<'a> {
+    /// Creates a reader for the device tree blob in `blob`.
+    pub fn new(blob: &'a [u8]) -> Option<Self> {
+        if read_u32(blob, 0)? != FDT_MAGIC {
+            return None;
+        }
+
+        let off_dt_struct = read_u32(blob, 8)? as usize;
+        let off_dt_strings = read_u32(blob, 12)? as usize;
+        let size_dt_strings = read_u32(blob, 32)? as usize;
+        let size_dt_struct = read_u32(blob, 36)? as usize;
+
+        Some(Self {
+            structure: blob.get(off_dt_struct..)?.get(..size_dt_struct)?,
+            strings: blob.get(off_dt_strings..)?.get(..size_dt_strings)?,
+        })
+    }
+
+    /// Looks up the value of a property in a direct child node of
+    /// the root, such as `bootargs` in `/chosen`.
+    pub fn property(&self, node: &str, name: &str) -> Option<&'a [u8]> {
+        let mut offset = 0;
+        let mut depth = 0usize;
+        let mut in_node = false;
+
+        loop {
+            let token = read_u32(self.structure, offset)?;
+            offset += 4;
+
+            match token {
+                FDT_BEGIN_NODE => {
+                    let node_name = read_str(self.structure, offset)?;
+                    offset = align(offset + node_name.len() + 1);
+
+                    depth += 1;
+                    // Node names may carry a unit address after an `@`.
+                    let base_name = node_name.split('@').next().unwrap_or_default();
+                    in_node = depth == 2 && base_name == node;
+                }
+                FDT_END_NODE => {
+                    depth = depth.checked_sub(1)?;
+                    in_node = false;
+                }
+                FDT_PROP => {
+                    let len = read_u32(self.structure, offset)? as usize;
+                    let name_offset = read_u32(self.structure, offset + 4)? as usize;
+                    let value = self.structure.get(offset + 8..)?.get(..len)?;
+                    offset = align(offset + 8 + len);
+
+                    if in_node && read_str(self.strings, name_offset)? == name {
+                        return Some(value);
+                    }
+                }
+                FDT_NOP => {}
+                // The end of the tree or a malformed token.
+                _ => return None,
+            }
+        }
+    }
+
+    /// Looks up the value of a string property like
+    /// [`Fdt::property`], without its NUL terminator.
+    pub fn property_str(&self, node: &str, name: &str) -> Option<&'a str> {
+        let value = self.property(node, name)?;
+        CStr::from_bytes_until_nul(value).ok()?.to_str().ok()
+    }
+}
+
+fn read_str(data: &[u8], offset: usize) -> Option<&str> {
+    CStr::from_bytes_until_nul(data.get(offset..)?)
+        .ok()?
+        .to_str()
+        .ok()
+}
+
+fn read_u32(data: &[u8], offset: usize) -> Option<u32> {
+    let bytes = data.get(offset..offset + 4)?;
+    Some(u32::from_be_bytes(bytes.try_into().ok()?))
+}
+
+fn align(offset: usize) -> usize {
+    (offset + 3) & !3
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FDT_END: u32 = 0x9;
+
+    /// Assembles a blob with a `/chosen` node holding `bootargs` and
+    /// an empty `/memory@80000000` node.
+    fn blob<'a>(buf: &'a mut [u8; 256], bootargs: &[u8]) -> &'a [u8] {
+        let mut len = 40;
+        let mut push = |len: &mut usize, bytes: &[u8]| {
+            buf[*len..*len + bytes.len()].copy_from_slice(bytes);
+            *len = align(*len + bytes.len());
+        };
+
+        push(&mut len, &FDT_BEGIN_NODE.to_be_bytes());
+        push(&mut len, b"\0");
+        push(&mut len, &FDT_BEGIN_NODE.to_be_bytes());
+        push(&mut len, b"memory@80000000\0");
+        push(&mut len, &FDT_END_NODE.to_be_bytes());
+        push(&mut len, &FDT_BEGIN_NODE.to_be_bytes());
+        push(&mut len, b"chosen\0");
+        push(&mut len, &FDT_NOP.to_be_bytes());
+        push(&mut len, &FDT_PROP.to_be_bytes());
+        push(&mut len, &(bootargs.len() as u32).to_be_bytes());
+        push(&mut len, &0u32.to_be_bytes());
+        push(&mut len, bootargs);
+        push(&mut len, &FDT_END_NODE.to_be_bytes());
+        push(&mut len, &FDT_END_NODE.to_be_bytes());
+        push(&mut len, &FDT_END.to_be_bytes());
+        let struct_end = len;
+        push(&mut len, b"bootargs\0");
+        let strings_end = len;
+
+        let header = [
+            FDT_MAGIC,
+            strings_end as u32,
+            40,
+            struct_end as u32,
+            0,
+            17,
+            16,
+            0,
+            (strings_end - struct_end) as u32,
+            (struct_end - 40) as u32,
+        ];
+        for (i, field) in header.iter().enumerate() {
+            buf[i * 4..i * 4 + 4].copy_from_slice(&field.to_be_bytes());
+        }
+
+        &buf[..strings_end]
+    }
+
+    #[test]
+    fn finds_chosen_bootargs() {
+        let mut buf = [0; 256];
+        let fdt = Fdt::new(blob(&mut buf, b"console=ttyS0\0")).unwrap();
+        assert_eq!(
+            fdt.property("chosen", "bootargs"),
+            Some(&b"console=ttyS0\0"[..])
+        );
+        assert_eq!(
+            fdt.property_str("chosen", "bootargs"),
+            Some("console=ttyS0")
+        );
+    }
+
+    #[test]
+    fn ignores_other_nodes_and_properties() {
+        let mut buf = [0; 256];
+        let fdt = Fdt::new(blob(&mut buf, b"\0")).unwrap();
+        assert_eq!(fdt.property("memory", "bootargs"), None);
+        assert_eq!(fdt.property("chosen", "stdout-path"), None);
+    }
+
+    #[test]
+    fn rejects_bad_blobs() {
+        let mut buf = [0; 256];
+        let blob = blob(&mut buf, b"\0");
+        assert!(Fdt::new(&blob[..20]).is_none());
+
+        let mut corrupt = [0; 256];
+        corrupt[..blob.len()].copy_from_slice(blob);
+        corrupt[0] ^= 0xFF;
+        assert!(Fdt::new(&corrupt).is_none());
+    }
+
+    #[test]
+    fn rejects_non_string_properties() {
+        let mut buf = [0; 256];
+        let fdt = Fdt::new(blob(&mut buf, &[0xFF, 0xFE, 0])).unwrap();
+        assert_eq!(fdt.property_str("chosen", "bootargs"), None);
+    }
+}
@@ -0,0 +1,314 @@
+//! Parsing of the INI1 container which bundles the Kernel Initial
+//! Processes of a Kernel Image.
+//!
+//! The container is read in place without allocating, with integers
+//! in a given byte order so that host tools can read images for any
+//! target.
+
+use core::{fmt, ptr};
+
+use onyx_image::{Ini1Header, Kip1Header, INI1_MAGIC, KIP1_MAGIC};
+
+/// The byte order of the integers in an INI1 container.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ByteOrder {
+    /// Least significant byte first.
+    Little,
+    /// Most significant byte first.
+    Big,
+}
+
+impl ByteOrder {
+    /// The byte order of the target.
+    pub const NATIVE: Self = if cfg!(target_endian = "big") {
+        Self::Big
+    } else {
+        Self::Little
+    };
+}
+
+/// A malformed INI1 container or KIP1 binary.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum KipError {
+    /// The header at the given offset does not start with its magic.
+    BadMagic(usize),
+    /// The structure at the given offset exceeds the data bounds.
+    OutOfBounds(usize),
+}
+
+impl fmt::Display for KipError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::BadMagic(offset) => write!(f, "bad header magic at {offset:#x}"),
+            Self::OutOfBounds(offset) => write!(f, "data at {offset:#x} exceeds the bounds"),
+        }
+    }
+}
+
+/// The KIP1 binaries in an INI1 container, in order.
+pub struct Ini1<'a> {
+    data: &'a [u8],
+    order: ByteOrder,
+    offset: usize,
+    remaining: u32,
+}
+
+impl<'a> Ini1<'a> {
+    /// Reads the INI1 container at offset `base` of `data`.
+    ///
+    /// A base of 0 denotes an image without any initial processes.
+    pub fn new(data: &'a [u8], base: usize, order: ByteOrder) -> Result<Self, KipError> {
+        if base == 0 {
+            return Ok(Self {
+                data,
+                order,
+                offset: 0,
+                remaining: 0,
+            });
+        }
+
+        let bytes = data
+            .get(base..)
+            .and_then(|b| b.get(..Ini1Header::SIZE))
+            .ok_or(KipError::OutOfBounds(base))?;
+        // SAFETY: `bytes` holds a whole header, which only consists of
+        // integers and byte arrays that are valid for any contents.
+        let header = unsafe { ptr::read_unaligned(bytes.as_ptr().cast::<Ini1Header>()) };
+        if header.magic != INI1_MAGIC {
+            return Err(KipError::BadMagic(base));
+        }
+
+        Ok(Self {
+            data,
+            order,
+            offset: base + Ini1Header::SIZE,
+            remaining: convert_u32(header.process_count, order),
+        })
+    }
+}
+
+impl<'a> Iterator for Ini1<'a> {
+    type Item = Result<Kip<'a>, KipError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.remaining == 0 {
+            return None;
+        }
+
+        let offset = self.offset;
+        let kip = read_kip1_at(self.data, offset, self.order).and_then(|header| {
+            let data = self
+                .data
+                .get(offset..)
+                .and_then(|d| d.get(..header.file_size()))
+                .ok_or(KipError::OutOfBounds(offset))?;
+            Ok(Kip {
+                offset,
+                header,
+                data,
+            })
+        });
+
+        // Nothing behind a malformed binary can be located anymore.
+        match &kip {
+            Ok(kip) => {
+                self.offset += kip.data.len();
+                self.remaining -= 1;
+            }
+            Err(_) => self.remaining = 0,
+        }
+        Some(kip)
+    }
+}
+
+/// A KIP1 binary in an [`Ini1`] container.
+pub struct Kip<'a> {
+    /// The offset of the binary in the data of the container.
+    pub offset: usize,
+    /// The decoded header of the binary.
+    pub header: Kip1Header,
+    /// The whole binary, including its header.
+    pub data: &'a [u8],
+}
+
+/// Reads the [`Kip1Header`] at the start of a KIP1 binary.
+pub fn read_kip1(data: &[u8], order: ByteOrder) -> Result<Kip1Header, KipError> {
+    read_kip1_at(data, 0, order)
+}
+
+fn read_kip1_at(data: &[u8], offset: usize, order: ByteOrder) -> Result<Kip1Header, KipError> {
+    let bytes = data
+        .get(offset..)
+        .and_then(|b| b.get(..Kip1Header::SIZE))
+        .ok_or(KipError::OutOfBounds(offset))?;
+    // SAFETY: `bytes` holds a whole header, which only consists of
+    // integers and byte arrays that are valid for any contents.
+    let mut header = unsafe { ptr::read_unaligned(bytes.as_ptr().cast::<Kip1Header>()) };
+    if header.magic != KIP1_MAGIC {
+        return Err(KipError::BadMagic(offset));
+    }
+
+    header.program_id = convert_u64(header.program_id, order);
+    header.version = convert_u32(header.version, order);
+    for segment in &mut header.segments {
+        segment.offset = convert_u32(segment.offset, order);
+        segment.size = convert_u32(segment.size, order);
+        segment.file_size = convert_u32(segment.file_size, order);
+        segment.attributes = convert_u32(segment.attributes, order);
+    }
+    for capability in &mut header.capabilities {
+        *capability = convert_u32(*capability, order);
+    }
+
+    Ok(header)
+}
+
+fn convert_u32(value: u32, order: ByteOrder) -> u32 {
+    if order == ByteOrder::NATIVE {
+        value
+    } else {
+        value.swap_bytes()
+    }
+}
+
+fn convert_u64(value: u64, order: ByteOrder) -> u64 {
+    if order == ByteOrder::NATIVE {
+        value
+    } else {
+        value.swap_bytes()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The offset of the container in the test images.
+    const BASE: usize = 0x20;
+
+    fn u32_bytes(value: u32, order: ByteOrder) -> [u8; 4] {
+        match order {
+            ByteOrder::Little => value.to_le_bytes(),
+            ByteOrder::Big => value.to_be_bytes(),
+        }
+    }
+
+    /// Assembles a KIP1 binary with the given .text, .rodata and .data
+    /// file sizes.
+    fn kip(name: &[u8], program_id: u64, sizes: [u32; 3], order: ByteOrder) -> Vec<u8> {
+        let mut kip = KIP1_MAGIC.to_vec();
+        kip.extend(name);
+        kip.resize(0x10, 0);
+        kip.extend(match order {
+            ByteOrder::Little => program_id.to_le_bytes(),
+            ByteOrder::Big => program_id.to_be_bytes(),
+        });
+        kip.extend(u32_bytes(1, order));
+        kip.extend([44, 3, 0, 0]);
+        for size in sizes {
+            kip.extend(u32_bytes(0, order));
+            kip.extend(u32_bytes(size, order));
+            kip.extend(u32_bytes(size, order));
+            kip.extend(u32_bytes(0, order));
+        }
+        kip.resize(Kip1Header::SIZE, 0);
+
+        let contents = sizes.iter().sum::<u32>() as usize;
+        kip.resize(Kip1Header::SIZE + contents, 0xAA);
+        kip
+    }
+
+    /// Assembles an image with an INI1 container of `kips` at [`BASE`].
+    fn image(kips: &[Vec<u8>], order: ByteOrder) -> Vec<u8> {
+        let size = Ini1Header::SIZE + kips.iter().map(Vec::len).sum::<usize>();
+
+        let mut image = vec![0; BASE];
+        image.extend(INI1_MAGIC);
+        image.extend(u32_bytes(size as u32, order));
+        image.extend(u32_bytes(kips.len() as u32, order));
+        image.extend(u32_bytes(0, order));
+        for kip in kips {
+            image.extend(kip);
+        }
+        image
+    }
+
+    #[test]
+    fn reads_kips_in_both_byte_orders() {
+        for order in [ByteOrder::Little, ByteOrder::Big] {
+            let first = kip(b"init", 0x0100_0000_0000_0001, [0x10, 0x8, 0], order);
+            let second = kip(b"loader.kip", 0x0100_0000_0000_0002, [0x20, 0, 0x4], order);
+            let image = image(&[first.clone(), second.clone()], order);
+
+            let kips: Vec<_> = Ini1::new(&image, BASE, order)
+                .unwrap()
+                .collect::<Result<_, _>>()
+                .unwrap();
+            assert_eq!(kips.len(), 2);
+
+            assert_eq!(kips[0].offset, BASE + Ini1Header::SIZE);
+            assert_eq!(kips[0].data, &first[..]);
+            assert_eq!(kips[0].header.name(), "init");
+            assert_eq!(kips[0].header.program_id, 0x0100_0000_0000_0001);
+            assert_eq!(kips[0].header.version, 1);
+            assert_eq!(kips[0].header.priority, 44);
+            assert_eq!(kips[0].header.ideal_core, 3);
+
+            assert_eq!(kips[1].offset, kips[0].offset + first.len());
+            assert_eq!(kips[1].data, &second[..]);
+            assert_eq!(kips[1].header.name(), "loader.kip");
+            assert_eq!(kips[1].header.segments[2].file_size, 0x4);
+            assert_eq!(kips[1].header.file_size(), Kip1Header::SIZE + 0x24);
+        }
+    }
+
+    #[test]
+    fn zero_base_has_no_kips() {
+        assert_eq!(Ini1::new(&[], 0, ByteOrder::NATIVE).unwrap().count(), 0);
+    }
+
+    #[test]
+    fn rejects_bad_magic() {
+        let order = ByteOrder::NATIVE;
+        let mut image = image(&[kip(b"init", 1, [0x10, 0, 0], order)], order);
+        assert!(matches!(
+            Ini1::new(&image, BASE - 4, order),
+            Err(KipError::BadMagic(offset)) if offset == BASE - 4
+        ));
+
+        let kip_offset = BASE + Ini1Header::SIZE;
+        image[kip_offset] = b'X';
+        let mut kips = Ini1::new(&image, BASE, order).unwrap();
+        assert!(matches!(
+            kips.next(),
+            Some(Err(KipError::BadMagic(offset))) if offset == kip_offset
+        ));
+        assert!(kips.next().is_none());
+        assert!(matches!(
+            read_kip1(&image[kip_offset..], order),
+            Err(KipError::BadMagic(0))
+        ));
+    }
+
+    #[test]
+    fn rejects_out_of_bounds_data() {
+        let order = ByteOrder::NATIVE;
+        let first = kip(b"init", 1, [0x10, 0, 0], order);
+        let mut image = image(&[first.clone(), kip(b"sm", 2, [0x8, 0, 0], order)], order);
+        image.pop();
+
+        assert!(matches!(
+            Ini1::new(&image, image.len() - 4, order),
+            Err(KipError::OutOfBounds(_))
+        ));
+
+        let second = BASE + Ini1Header::SIZE + first.len();
+        let mut kips = Ini1::new(&image, BASE, order).unwrap();
+        assert!(kips.next().unwrap().is_ok());
+        assert!(matches!(
+            kips.next(),
+            Some(Err(KipError::OutOfBounds(offset))) if offset == second
+        ));
+        assert!(kips.next().is_none());
+    }
+}
@@ -0,0 +1,13 @@
+//! Architecture-independent subsystems of the Onyx kernel.
+//!
+//! Everything in here is plain logic without ties to a board or an
+//! architecture, so that it builds for the host and can be tested
+//! with a regular `cargo test -p onyx-core`, without QEMU.
+
+#![cfg_attr(not(test), no_std)]
+
+pub mod cmdline;
+
+pub mod fdt;
+
+pub mod kip;
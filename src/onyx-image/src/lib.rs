@@ -0,0 +1,655 @@
+//! Definitions of the Onyx Kernel Image format.
+//!
+//! This is the single source of truth for the layout of structures
+//! shared between the kernel, the Kernel Loader and the build system.
+//! All of them are `#[repr(C)]` so they can be accessed in place from
+//! memory, and their layout is pinned down by compile-time assertions.
+
+#![cfg_attr(not(feature = "std"), no_std)]
+#![feature(offset_of)]
+
+use core::mem::{offset_of, size_of};
+
+/// The magic bytes at the start of [`KernelMeta`].
+pub const KERNEL_MAGIC: [u8; 4] = *b"ONYX";
+
+/// The big endian magic number at the start of every Flattened
+/// Device Tree blob.
+pub const FDT_MAGIC: u32 = 0xD00D_FEED;
+
+/// Encoded kernel metadata.
+///
+/// This is embedded close to the start of the kernel binary by
+/// `r0/start.s` and patched by the build system when assembling
+/// the Kernel Image.
+///
+/// The format evolves through revisions which only ever append new
+/// fields, with `header_size` telling how many bytes are valid. Fields
+/// added after the first revision must be read through accessors that
+/// account for the revision of the header.
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+#[cfg_attr(feature = "binrw", derive(binrw::BinRead, binrw::BinWrite))]
+#[cfg_attr(feature = "binrw", brw(magic = b"ONYX"))]
+#[repr(C)]
+pub struct KernelMeta {
+    /// The magic bytes; always [`KERNEL_MAGIC`].
+    #[cfg_attr(feature = "serde", serde(skip))]
+    #[cfg_attr(feature = "binrw", br(calc = KERNEL_MAGIC), bw(ignore))]
+    pub magic: [u8; 4],
+    /// The current kernel version.
+    ///
+    /// Encoded as `major << 24 | minor << 16 | patch << 8`, with the
+    /// low byte holding the `BuildId::FLAG_*` values of the build.
+    pub version: u32,
+    /// The format revision of this header.
+    pub revision: u32,
+    /// The size of this header in bytes, as of its `revision`.
+    pub header_size: u32,
+    /// The offset to the serialized KIP1 blob which holds all
+    /// Kernel Initial Processes.
+    pub kip1_base: u64,
+    /// The base address of the Kernel Loader binary.
+    pub loader_base: u64,
+    /// The size of the Kernel Loader binary.
+    pub loader_size: u64,
+    /// The size of the kernel binary, excluding padding.
+    pub kernel_size: u64,
+    /// The memory layout of the kernel binary.
+    pub layout: KernelLayout,
+
+    // Revision 2
+    /// The offset to the image trailer, or 0 if there is none.
+    ///
+    /// Use [`KernelMeta::trailer_base`] to access this.
+    #[cfg_attr(feature = "binrw", br(if(revision >= 2)), bw(if(*revision >= 2)))]
+    pub trailer_base: u64,
+
+    // Revision 3
+    /// The offset to the [`BuildId`] of the image, or 0 if there is none.
+    ///
+    /// Use [`KernelMeta::build_id_base`] to access this.
+    #[cfg_attr(feature = "binrw", br(if(revision >= 3)), bw(if(*revision >= 3)))]
+    pub build_id_base: u64,
+
+    // Revision 4
+    /// The offset to the embedded device tree blob, or 0 if there
+    /// is none.
+    ///
+    /// Use [`KernelMeta::dtb`] to access this.
+    #[cfg_attr(feature = "binrw", br(if(revision >= 4)), bw(if(*revision >= 4)))]
+    pub dtb_base: u64,
+    /// The size of the embedded device tree blob.
+    #[cfg_attr(feature = "binrw", br(if(revision >= 4)), bw(if(*revision >= 4)))]
+    pub dtb_size: u64,
+
+    // Revision 5
+    /// The offset to the embedded kernel command line, or 0 if there
+    /// is none.
+    ///
+    /// The command line is UTF-8 and followed by a NUL terminator
+    /// which is not included in `cmdline_size`. Use
+    /// [`KernelMeta::cmdline`] to access this.
+    #[cfg_attr(feature = "binrw", br(if(revision >= 5)), bw(if(*revision >= 5)))]
+    pub cmdline_base: u64,
+    /// The size of the embedded kernel command line.
+    #[cfg_attr(feature = "binrw", br(if(revision >= 5)), bw(if(*revision >= 5)))]
+    pub cmdline_size: u64,
+
+    // Revision 6
+    /// The offset to the end of the Kernel Loader in memory, including
+    /// its .bss section and stack, or 0 if it is not known.
+    ///
+    /// Everything appended to the image is placed behind this, so that
+    /// the Kernel Loader does not clobber it. Use
+    /// [`KernelMeta::loader_end`] to access this.
+    #[cfg_attr(feature = "binrw", br(if(revision >= 6)), bw(if(*revision >= 6)))]
+    pub loader_end: u64,
+}
+
+impl KernelMeta {
+    /// The encoded size of the structure in bytes, as of the latest
+    /// revision.
+    pub const SIZE: usize = size_of::<Self>();
+
+    /// The latest revision of the format.
+    pub const REVISION: u32 = 6;
+
+    /// The labels of all fields in `r0/start.s`, along with the
+    /// offset of each field from the start of the structure.
+    pub const SYMBOLS: [(&'static str, usize); 16] = [
+        ("__onyx_magic", offset_of!(Self, magic)),
+        ("__onyx_version", offset_of!(Self, version)),
+        ("__onyx_revision", offset_of!(Self, revision)),
+        ("__onyx_header_size", offset_of!(Self, header_size)),
+        ("__onyx_kip1_base", offset_of!(Self, kip1_base)),
+        ("__onyx_kernel_loader_base", offset_of!(Self, loader_base)),
+        ("__onyx_kernel_loader_size", offset_of!(Self, loader_size)),
+        ("__onyx_kernel_size", offset_of!(Self, kernel_size)),
+        ("__onyx_kernel_layout", offset_of!(Self, layout)),
+        ("__onyx_trailer_base", offset_of!(Self, trailer_base)),
+        ("__onyx_build_id_base", offset_of!(Self, build_id_base)),
+        ("__onyx_dtb_base", offset_of!(Self, dtb_base)),
+        ("__onyx_dtb_size", offset_of!(Self, dtb_size)),
+        ("__onyx_cmdline_base", offset_of!(Self, cmdline_base)),
+        ("__onyx_cmdline_size", offset_of!(Self, cmdline_size)),
+        ("__onyx_kernel_loader_end", offset_of!(Self, loader_end)),
+    ];
+
+    /// Gets the header size of a given format revision, or [`None`]
+    /// for revisions that are not known.
+    pub const fn header_size_of(revision: u32) -> Option<usize> {
+        match revision {
+            1 => Some(offset_of!(Self, trailer_base)),
+            2 => Some(offset_of!(Self, build_id_base)),
+            3 => Some(offset_of!(Self, dtb_base)),
+            4 => Some(offset_of!(Self, cmdline_base)),
+            5 => Some(offset_of!(Self, loader_end)),
+            6 => Some(Self::SIZE),
+            _ => None,
+        }
+    }
+
+    /// Checks whether this header can be understood.
+    ///
+    /// Revisions newer than [`KernelMeta::REVISION`] are compatible
+    /// as long as they carry at least all known fields; the caller
+    /// must decide whether ignoring the unknown ones is acceptable.
+    pub const fn compatibility(&self) -> Result<Compatibility, RevisionError> {
+        if self.revision == 0 {
+            return Err(RevisionError::Invalid);
+        }
+
+        let (compatibility, expected) = if self.revision > Self::REVISION {
+            (Compatibility::Newer, Self::SIZE)
+        } else {
+            match Self::header_size_of(self.revision) {
+                Some(size) => (Compatibility::Known, size),
+                None => return Err(RevisionError::Invalid),
+            }
+        };
+
+        if (self.header_size as usize) < expected {
+            return Err(RevisionError::Truncated {
+                revision: self.revision,
+                size: self.header_size,
+            });
+        }
+        Ok(compatibility)
+    }
+
+    /// Gets the offset to the image trailer, if there is one.
+    ///
+    /// Always [`None`] for revisions before 2.
+    pub const fn trailer_base(&self) -> Option<u64> {
+        match (self.revision, self.trailer_base) {
+            (0..=1, _) | (_, 0) => None,
+            (_, base) => Some(base),
+        }
+    }
+
+    /// Gets the offset to the [`BuildId`], if there is one.
+    ///
+    /// Always [`None`] for revisions before 3.
+    pub const fn build_id_base(&self) -> Option<u64> {
+        match (self.revision, self.build_id_base) {
+            (0..=2, _) | (_, 0) => None,
+            (_, base) => Some(base),
+        }
+    }
+
+    /// Gets the offset and size of the embedded device tree blob,
+    /// if there is one.
+    ///
+    /// Always [`None`] for revisions before 4.
+    pub const fn dtb(&self) -> Option<(u64, u64)> {
+        match (self.revision, self.dtb_base) {
+            (0..=3, _) | (_, 0) => None,
+            (_, base) => Some((base, self.dtb_size)),
+        }
+    }
+
+    /// Gets the offset and size of the embedded kernel command line,
+    /// if there is one.
+    ///
+    /// Always [`None`] for revisions before 5.
+    pub const fn cmdline(&self) -> Option<(u64, u64)> {
+        match (self.revision, self.cmdline_base) {
+            (0..=4, _) | (_, 0) => None,
+            (_, base) => Some((base, self.cmdline_size)),
+        }
+    }
+
+    /// Gets the offset to the end of the Kernel Loader in memory, if
+    /// it is known.
+    ///
+    /// Always [`None`] for revisions before 6.
+    pub const fn loader_end(&self) -> Option<u64> {
+        match (self.revision, self.loader_end) {
+            (0..=5, _) | (_, 0) => None,
+            (_, end) => Some(end),
+        }
+    }
+}
+
+/// How a [`KernelMeta`] relates to the known format revisions.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Compatibility {
+    /// The header is of a known revision.
+    Known,
+    /// The header is of a newer revision with unknown trailing fields.
+    Newer,
+}
+
+/// An error for a [`KernelMeta`] that cannot be understood.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RevisionError {
+    /// The revision is not a valid one.
+    Invalid,
+    /// The header is too small to hold all fields of its revision.
+    Truncated { revision: u32, size: u32 },
+}
+
+impl core::fmt::Display for RevisionError {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            Self::Invalid => f.write_str("invalid KernelMeta revision"),
+            Self::Truncated { revision, size } => write!(
+                f,
+                "KernelMeta of revision {revision} is truncated to {size:#x} bytes"
+            ),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for RevisionError {}
+
+impl Default for KernelMeta {
+    fn default() -> Self {
+        Self {
+            magic: KERNEL_MAGIC,
+            version: 0,
+            revision: Self::REVISION,
+            header_size: Self::SIZE as u32,
+            kip1_base: 0,
+            loader_base: 0,
+            loader_size: 0,
+            kernel_size: 0,
+            layout: KernelLayout::default(),
+            trailer_base: 0,
+            build_id_base: 0,
+            dtb_base: 0,
+            dtb_size: 0,
+            cmdline_base: 0,
+            cmdline_size: 0,
+            loader_end: 0,
+        }
+    }
+}
+
+/// The memory layout of the kernel binary.
+///
+/// All values are offsets from the start of the kernel.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+#[cfg_attr(feature = "binrw", derive(binrw::BinRead, binrw::BinWrite))]
+#[repr(C)]
+pub struct KernelLayout {
+    /// Start of the kernel .text section.
+    pub text_start: u32,
+    /// End of the kernel .text section.
+    pub text_end: u32,
+    /// Start of the kernel .rodata section.
+    pub rodata_start: u32,
+    /// End of the kernel .rodata section.
+    pub rodata_end: u32,
+    /// Start of the kernel .data section.
+    pub data_start: u32,
+    /// End of the kernel .data section.
+    pub data_end: u32,
+    /// Start of the kernel .bss section.
+    pub bss_start: u32,
+    /// End of the kernel .bss section.
+    pub bss_end: u32,
+    /// End of the kernel blob.
+    pub kernel_end: u32,
+    /// Start of the _DYNAMIC section.
+    pub dynamic_start: u32,
+}
+
+impl KernelLayout {
+    /// The encoded size of the structure in bytes.
+    pub const SIZE: usize = size_of::<Self>();
+}
+
+/// The magic bytes at the start of [`BuildId`].
+pub const BUILD_ID_MAGIC: [u8; 4] = *b"BLD1";
+
+/// Identifies the build a Kernel Image was produced from.
+///
+/// The build system places this into its own page-aligned section of
+/// the image, referenced by [`KernelMeta::build_id_base`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+#[cfg_attr(feature = "binrw", derive(binrw::BinRead, binrw::BinWrite))]
+#[cfg_attr(feature = "binrw", brw(magic = b"BLD1"))]
+#[repr(C)]
+pub struct BuildId {
+    /// The magic bytes; always [`BUILD_ID_MAGIC`].
+    #[cfg_attr(feature = "serde", serde(skip))]
+    #[cfg_attr(feature = "binrw", br(calc = BUILD_ID_MAGIC), bw(ignore))]
+    pub magic: [u8; 4],
+    /// A combination of `BuildId::FLAG_*` values.
+    ///
+    /// These are mirrored in the low byte of [`KernelMeta::version`].
+    pub flags: u32,
+    /// The time of the build, in seconds since the Unix epoch.
+    pub timestamp: u64,
+    /// The hex-encoded git commit hash, padded with NUL bytes.
+    #[cfg_attr(feature = "serde", serde(serialize_with = "serialize_str"))]
+    pub commit: [u8; 40],
+    /// The name of the cargo profile, padded with NUL bytes.
+    #[cfg_attr(feature = "serde", serde(serialize_with = "serialize_str"))]
+    pub profile: [u8; 16],
+}
+
+impl BuildId {
+    /// The encoded size of the structure in bytes.
+    pub const SIZE: usize = size_of::<Self>();
+
+    /// The working tree had uncommitted changes at build time.
+    pub const FLAG_DIRTY: u32 = 1 << 0;
+    /// The image was built in release mode.
+    pub const FLAG_RELEASE: u32 = 1 << 1;
+
+    /// Gets the git commit hash, or an empty string if unknown.
+    pub fn commit(&self) -> &str {
+        nul_terminated(&self.commit)
+    }
+
+    /// Gets the name of the cargo profile.
+    pub fn profile(&self) -> &str {
+        nul_terminated(&self.profile)
+    }
+}
+
+impl Default for BuildId {
+    fn default() -> Self {
+        Self {
+            magic: BUILD_ID_MAGIC,
+            flags: 0,
+            timestamp: 0,
+            commit: [0; 40],
+            profile: [0; 16],
+        }
+    }
+}
+
+/// The magic bytes at the start of [`ImageTrailer`].
+pub const TRAILER_MAGIC: [u8; 4] = *b"TRL1";
+
+/// Integrity information appended to a Kernel Image.
+///
+/// The build system places this behind everything else in the image,
+/// referenced by [`KernelMeta::trailer_base`]. The digest covers the
+/// whole image up to the trailer. When signed, the signature is
+/// computed over the digest.
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "binrw", derive(binrw::BinRead, binrw::BinWrite))]
+#[cfg_attr(feature = "binrw", brw(magic = b"TRL1"))]
+#[repr(C)]
+pub struct ImageTrailer {
+    /// The magic bytes; always [`TRAILER_MAGIC`].
+    #[cfg_attr(feature = "binrw", br(calc = TRAILER_MAGIC), bw(ignore))]
+    pub magic: [u8; 4],
+    /// A combination of `ImageTrailer::FLAG_*` values.
+    pub flags: u32,
+    /// The SHA-256 digest of the image.
+    pub digest: [u8; 32],
+    /// The Ed25519 signature of the digest, if signed.
+    pub signature: [u8; 64],
+}
+
+impl ImageTrailer {
+    /// The encoded size of the structure in bytes.
+    pub const SIZE: usize = size_of::<Self>();
+
+    /// The trailer holds a valid signature.
+    pub const FLAG_SIGNED: u32 = 1 << 0;
+}
+
+impl Default for ImageTrailer {
+    fn default() -> Self {
+        Self {
+            magic: TRAILER_MAGIC,
+            flags: 0,
+            digest: [0; 32],
+            signature: [0; 64],
+        }
+    }
+}
+
+/// The magic bytes at the start of [`StubHeader`].
+pub const STUB_MAGIC: [u8; 4] = *b"STUB";
+
+/// Describes the compressed Kernel Image appended to the `onyx-stub`
+/// binary which prefixes compressed images.
+///
+/// This is embedded directly behind the initial jump of the stub by
+/// its `r0/start.s` and patched by the build system when appending
+/// the compressed image.
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "binrw", derive(binrw::BinRead, binrw::BinWrite))]
+#[cfg_attr(feature = "binrw", brw(magic = b"STUB"))]
+#[repr(C)]
+pub struct StubHeader {
+    /// The magic bytes; always [`STUB_MAGIC`].
+    #[cfg_attr(feature = "binrw", br(calc = STUB_MAGIC), bw(ignore))]
+    pub magic: [u8; 4],
+    /// The format of the payload; one of `StubHeader::FORMAT_*`.
+    pub format: u32,
+    /// The offset of the compressed image from the start of the stub.
+    pub payload_offset: u64,
+    /// The size of the compressed image.
+    pub payload_size: u64,
+    /// The size of the decompressed image.
+    pub image_size: u64,
+    /// The offset from the start of the stub to extract the image to.
+    pub image_offset: u64,
+    /// The end of the stub in memory, including .bss and stack.
+    pub end: u64,
+}
+
+impl StubHeader {
+    /// The encoded size of the structure in bytes.
+    pub const SIZE: usize = size_of::<Self>();
+
+    /// The payload is stored uncompressed.
+    pub const FORMAT_NONE: u32 = 0;
+    /// The payload is compressed with gzip.
+    pub const FORMAT_GZIP: u32 = 1;
+    /// The payload is compressed with the LZ4 block format.
+    pub const FORMAT_LZ4: u32 = 2;
+    /// The payload is compressed with zstd.
+    pub const FORMAT_ZSTD: u32 = 3;
+}
+
+impl Default for StubHeader {
+    fn default() -> Self {
+        Self {
+            magic: STUB_MAGIC,
+            format: Self::FORMAT_NONE,
+            payload_offset: 0,
+            payload_size: 0,
+            image_size: 0,
+            image_offset: 0,
+            end: 0,
+        }
+    }
+}
+
+/// The magic bytes at the start of [`Ini1Header`].
+pub const INI1_MAGIC: [u8; 4] = *b"INI1";
+
+/// Header of the INI1 container which bundles all Kernel Initial
+/// Processes in a Kernel Image.
+///
+/// The build system places this at [`KernelMeta::kip1_base`],
+/// immediately followed by the KIP1 binaries.
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "binrw", derive(binrw::BinRead, binrw::BinWrite))]
+#[cfg_attr(feature = "binrw", brw(magic = b"INI1"))]
+#[repr(C)]
+pub struct Ini1Header {
+    /// The magic bytes; always [`INI1_MAGIC`].
+    #[cfg_attr(feature = "binrw", br(calc = INI1_MAGIC), bw(ignore))]
+    pub magic: [u8; 4],
+    /// The total size of the container, including this header.
+    pub size: u32,
+    /// The number of KIP1 binaries in the container.
+    pub process_count: u32,
+    /// Reserved; always 0.
+    pub reserved: u32,
+}
+
+impl Ini1Header {
+    /// The encoded size of the structure in bytes.
+    pub const SIZE: usize = size_of::<Self>();
+}
+
+impl Default for Ini1Header {
+    fn default() -> Self {
+        Self {
+            magic: INI1_MAGIC,
+            size: 0,
+            process_count: 0,
+            reserved: 0,
+        }
+    }
+}
+
+/// The magic bytes at the start of [`Kip1Header`].
+pub const KIP1_MAGIC: [u8; 4] = *b"KIP1";
+
+/// Header of a Kernel Initial Process binary.
+///
+/// The header is followed by the file contents of the .text, .rodata
+/// and .data segments, in that order.
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "binrw", derive(binrw::BinRead, binrw::BinWrite))]
+#[cfg_attr(feature = "binrw", brw(magic = b"KIP1"))]
+#[repr(C)]
+pub struct Kip1Header {
+    /// The magic bytes; always [`KIP1_MAGIC`].
+    #[cfg_attr(feature = "binrw", br(calc = KIP1_MAGIC), bw(ignore))]
+    pub magic: [u8; 4],
+    /// The NUL-padded name of the process.
+    pub name: [u8; 12],
+    /// The program ID of the process.
+    pub program_id: u64,
+    /// The version of the process.
+    pub version: u32,
+    /// The main thread priority.
+    pub priority: u8,
+    /// The ideal core of the main thread.
+    pub ideal_core: u8,
+    /// Reserved; always 0.
+    pub reserved: u8,
+    /// KIP1 flags, such as compression of individual segments.
+    pub flags: u8,
+    /// The .text, .rodata, .data and .bss segments, followed by
+    /// two reserved entries.
+    pub segments: [Kip1Segment; 6],
+    /// The kernel capability descriptors of the process.
+    pub capabilities: [u32; 32],
+}
+
+impl Kip1Header {
+    /// The encoded size of the structure in bytes.
+    pub const SIZE: usize = size_of::<Self>();
+
+    /// Gets the name of the process without trailing NUL bytes.
+    pub fn name(&self) -> &str {
+        nul_terminated(&self.name)
+    }
+
+    /// Gets the total size of the KIP1 binary, including this header.
+    pub fn file_size(&self) -> usize {
+        // Only .text, .rodata and .data are backed by file contents.
+        Self::SIZE
+            + self.segments[..3]
+                .iter()
+                .map(|s| s.file_size as usize)
+                .sum::<usize>()
+    }
+}
+
+impl Default for Kip1Header {
+    fn default() -> Self {
+        Self {
+            magic: KIP1_MAGIC,
+            name: [0; 12],
+            program_id: 0,
+            version: 0,
+            priority: 0,
+            ideal_core: 0,
+            reserved: 0,
+            flags: 0,
+            segments: Default::default(),
+            capabilities: [0; 32],
+        }
+    }
+}
+
+/// A segment descriptor in a [`Kip1Header`].
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+#[cfg_attr(feature = "binrw", derive(binrw::BinRead, binrw::BinWrite))]
+#[repr(C)]
+pub struct Kip1Segment {
+    /// The offset of the segment in process memory.
+    pub offset: u32,
+    /// The size of the segment in process memory.
+    pub size: u32,
+    /// The size of the segment in the KIP1 binary.
+    pub file_size: u32,
+    /// Segment attributes.
+    pub attributes: u32,
+}
+
+fn nul_terminated(bytes: &[u8]) -> &str {
+    let len = bytes.iter().position(|&b| b == 0).unwrap_or(bytes.len());
+    core::str::from_utf8(&bytes[..len]).unwrap_or_default()
+}
+
+#[cfg(feature = "serde")]
+fn serialize_str<S: serde::Serializer, const N: usize>(
+    bytes: &[u8; N],
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    serializer.serialize_str(nul_terminated(bytes))
+}
+
+// The encoded format must not change by accident. Update these
+// deliberately along with the assembly that emits the structures.
+#[allow(clippy::assertions_on_constants)]
+const _: () = {
+    assert!(KernelMeta::SIZE == 0x90);
+    assert!(KernelLayout::SIZE == 0x28);
+    assert!(BuildId::SIZE == 0x48);
+    assert!(ImageTrailer::SIZE == 0x68);
+    assert!(StubHeader::SIZE == 0x30);
+    assert!(Ini1Header::SIZE == 0x10);
+    assert!(Kip1Header::SIZE == 0x100);
+    assert!(size_of::<Kip1Segment>() == 0x10);
+
+    // The structures are accessed in place and must not contain padding.
+    assert!(offset_of!(KernelMeta, loader_end) + 8 == KernelMeta::SIZE);
+    assert!(offset_of!(BuildId, profile) + 16 == BuildId::SIZE);
+    assert!(offset_of!(ImageTrailer, signature) + 64 == ImageTrailer::SIZE);
+    assert!(offset_of!(StubHeader, end) + 8 == StubHeader::SIZE);
+    assert!(offset_of!(Ini1Header, reserved) + 4 == Ini1Header::SIZE);
+    assert!(offset_of!(Kip1Header, segments) == 0x20);
+    assert!(offset_of!(Kip1Header, capabilities) + 128 == Kip1Header::SIZE);
+};
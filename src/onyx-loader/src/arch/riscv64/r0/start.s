@@ -13,13 +13,57 @@ __onyx_loader_start:
 .endm
 
 //
-// fn __onyx_loader_entry(kernel_base: *const u8, layout: *const KernelLayout, kips: *const ())
+// fn __onyx_loader_entry(
+//     kernel_base: *const u8,
+//     meta: *const KernelMeta,
+//     kips: *const (),
+//     firmware_dtb: *const u8,
+// ) -> *const u8
 //
 .section .r0.text, "ax", %progbits
 .global __onyx_loader_entry
 .type __onyx_loader_entry, %function
 __onyx_loader_entry:
     lla t0, __onyx_loader_start
+
+    // Apply relative relocations to ourselves before entering Rust.
+    // Walk the dynamic section to find the RELA table first.
+    LOAD_LABEL_ADDR t1, t0, __onyx_loader_dynamic_start
+    li t2, 0 // DT_RELA
+    li t3, 0 // DT_RELASZ
+3:
+    ld t4, 0(t1)
+    beqz t4, 6f
+    ld t5, 8(t1)
+    li t6, 7
+    bne t4, t6, 4f
+    add t2, t0, t5
+4:
+    li t6, 8
+    bne t4, t6, 5f
+    mv t3, t5
+5:
+    addi t1, t1, 16
+    j 3b
+
+    // Now apply every R_RISCV_RELATIVE entry in the table.
+6:
+    add t3, t2, t3
+7:
+    bgeu t2, t3, 9f
+    ld t4, 8(t2)
+    li t6, 3
+    bne t4, t6, 8f
+    ld t4, 0(t2)
+    ld t5, 16(t2)
+    add t4, t0, t4
+    add t5, t0, t5
+    sd t5, 0(t4)
+8:
+    addi t2, t2, 24
+    j 7b
+
+9:
     LOAD_LABEL_ADDR t1, t0, __onyx_loader_bss_start
     LOAD_LABEL_ADDR t2, t0, __onyx_loader_bss_end
 
@@ -44,11 +88,10 @@ __onyx_loader_entry:
     // Enter Rust
     call main
 
-    li a0, 'C'
-    li a1, 0x10000000
-    sw a0, 0(a1)
-
-    2: j 2b
+    // Return to the kernel with the selected device tree in a0.
+    ld ra, 24(sp)
+    addi sp, sp, 32
+    ret
 
 
 .balign 8
@@ -0,0 +1,72 @@
+//! Selection of the device tree the kernel is booted with.
+
+use onyx_image::{KernelMeta, FDT_MAGIC};
+
+/// Selects the device tree blob to hand over to the kernel.
+///
+/// A valid blob passed by firmware always takes precedence, so that
+/// boards with a firmware DTB describe themselves accurately. Boards
+/// without one fall back to the blob embedded into the image. When
+/// neither is available, a null pointer is returned.
+///
+/// # Safety
+///
+/// `kernel_base` must point to the start of the Kernel Image that
+/// `meta` belongs to, and `firmware` must either be null or point to
+/// readable memory.
+pub unsafe fn select(kernel_base: *const u8, meta: &KernelMeta, firmware: *const u8) -> *const u8 {
+    if is_valid(firmware) {
+        return firmware;
+    }
+
+    match meta.dtb() {
+        Some((base, _)) if is_valid(kernel_base.add(base as usize)) => {
+            kernel_base.add(base as usize)
+        }
+        _ => core::ptr::null(),
+    }
+}
+
+unsafe fn is_valid(dtb: *const u8) -> bool {
+    // Blobs are required to be 8-byte aligned.
+    if dtb.is_null() || dtb as usize % 8 != 0 {
+        return false;
+    }
+
+    u32::from_be(dtb.cast::<u32>().read()) == FDT_MAGIC
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[repr(C, align(8))]
+    struct Aligned([u8; 16]);
+
+    #[test_case]
+    fn accepts_aligned_blobs() {
+        let mut blob = Aligned([0; 16]);
+        blob.0[..4].copy_from_slice(&FDT_MAGIC.to_be_bytes());
+        assert!(unsafe { is_valid(blob.0.as_ptr()) });
+    }
+
+    #[test_case]
+    fn rejects_misaligned_and_null_blobs() {
+        let mut blob = Aligned([0; 16]);
+        blob.0[4..8].copy_from_slice(&FDT_MAGIC.to_be_bytes());
+        assert!(!unsafe { is_valid(blob.0.as_ptr().add(4)) });
+        assert!(!unsafe { is_valid(blob.0.as_ptr()) });
+        assert!(!unsafe { is_valid(core::ptr::null()) });
+    }
+
+    #[test_case]
+    fn falls_back_to_embedded_blob_without_firmware_dtb() {
+        let (kernel_base, meta) = crate::booted_image();
+        let expected = match meta.dtb() {
+            Some((base, _)) => unsafe { kernel_base.add(base as usize) },
+            None => core::ptr::null(),
+        };
+
+        assert_eq!(unsafe { select(kernel_base, meta, core::ptr::null()) }, expected);
+    }
+}
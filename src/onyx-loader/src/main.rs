@@ -1,23 +1,81 @@
 #![no_std]
 #![no_main]
+#![cfg_attr(test, feature(custom_test_frameworks))]
+#![cfg_attr(test, test_runner(onyx_test::runner))]
+#![cfg_attr(test, reexport_test_harness_main = "test_main")]
 
 use core::panic::PanicInfo;
+#[cfg(test)]
+use core::sync::atomic::{AtomicPtr, Ordering};
+
+use onyx_board::{Board, Console, CurrentBoard};
+use onyx_image::{Compatibility, KernelMeta};
 
 mod arch;
 
+mod dtb;
+
+mod verify;
+
+#[cfg(test)]
+#[panic_handler]
+fn panic(info: &PanicInfo<'_>) -> ! {
+    onyx_test::panicked(info)
+}
+
+/// The Kernel Image the tests were booted from.
+#[cfg(test)]
+static KERNEL_BASE: AtomicPtr<u8> = AtomicPtr::new(core::ptr::null_mut());
+#[cfg(test)]
+static KERNEL_META: AtomicPtr<KernelMeta> = AtomicPtr::new(core::ptr::null_mut());
+
+/// Gets the base address and the [`KernelMeta`] of the Kernel Image
+/// the tests were booted from.
+#[cfg(test)]
+fn booted_image() -> (*const u8, &'static KernelMeta) {
+    let kernel_base = KERNEL_BASE.load(Ordering::Relaxed);
+    let kernel_meta = KERNEL_META.load(Ordering::Relaxed);
+
+    // SAFETY: `main` stores both before running any test.
+    (kernel_base, unsafe { &*kernel_meta })
+}
+
+#[cfg(not(test))]
 #[panic_handler]
 fn panic(_info: &PanicInfo<'_>) -> ! {
-    loop {}
+    loop {
+        core::hint::spin_loop();
+    }
 }
 
 #[no_mangle]
-extern "C" fn main(kernel_base: *const u8, kernel_layout: *const u8, kip1_base: *const u8) {
+extern "C" fn main(
+    kernel_base: *const u8,
+    kernel_meta: &KernelMeta,
+    kip1_base: *const u8,
+    firmware_dtb: *const u8,
+) -> *const u8 {
+    // Newer revisions only append fields, so the ones we know
+    // remain valid and everything beyond them can be ignored.
+    match kernel_meta.compatibility() {
+        Ok(Compatibility::Known | Compatibility::Newer) => {}
+        Err(_) => panic!("unsupported KernelMeta revision; refusing to boot"),
+    }
+
+    // The tests run in place of the loader and never return.
+    #[cfg(test)]
+    {
+        KERNEL_BASE.store(kernel_base.cast_mut(), Ordering::Relaxed);
+        KERNEL_META.store((kernel_meta as *const KernelMeta).cast_mut(), Ordering::Relaxed);
+        test_main();
+    }
+
     unsafe {
-        core::arch::asm!(
-            "li a0, 'B'",
-            "li a1, 0x10000000",
-            "sw a0, 0(a1)",
-            options(nostack)
-        );
+        // Make sure the image is intact before relocating the kernel.
+        verify::verify_image(kernel_base, kernel_meta.trailer_base().unwrap_or(0) as usize);
+
+        CurrentBoard::console().write_byte(b'B');
+
+        dtb::select(kernel_base, kernel_meta, firmware_dtb)
     }
 }
@@ -0,0 +1,175 @@
+//! Integrity checks for the Kernel Image before it is booted.
+
+use core::ptr::addr_of;
+
+use ed25519_dalek::{Signature, VerifyingKey};
+use onyx_image::{ImageTrailer, TRAILER_MAGIC};
+use sha2::{Digest, Sha256};
+
+/// The public key to verify image signatures with, if the loader was
+/// built for signed images.
+const PUBLIC_KEY: Option<[u8; 32]> = match option_env!("ONYX_PUBLIC_KEY") {
+    Some(key) => decode_key(key.as_bytes()),
+    None => None,
+};
+
+/// The size of the chunks in which the Kernel Loader hashes itself.
+const CHUNK_SIZE: usize = 0x1000;
+
+/// The type of relative relocations in our own RELA table.
+const R_RISCV_RELATIVE: usize = 3;
+
+extern "C" {
+    static __onyx_loader_start: u8;
+    static __rela_start__: Rela;
+    static __rela_end__: Rela;
+    static __bss_start__: u8;
+    static __bss_end__: u8;
+}
+
+/// An entry in the RELA table of the Kernel Loader.
+#[repr(C)]
+struct Rela {
+    offset: usize,
+    info: usize,
+    addend: usize,
+}
+
+/// Verifies the integrity of the Kernel Image in memory.
+///
+/// The digest of the image is checked against the one in its trailer.
+/// When a public key is embedded, the image must also carry a valid
+/// signature for it. Mismatches will refuse to boot the image.
+///
+/// # Safety
+///
+/// `kernel_base` must point to the start of the Kernel Image and
+/// `trailer_offset` must be the trailer offset from its metadata.
+/// Nothing but our own relocation must have written to the image
+/// before, as the digest would no longer match.
+pub unsafe fn verify_image(kernel_base: *const u8, trailer_offset: usize) {
+    // Images without a trailer carry no integrity information.
+    if trailer_offset == 0 {
+        assert!(PUBLIC_KEY.is_none(), "unsigned Kernel Image; refusing to boot");
+        return;
+    }
+
+    let trailer = &*(kernel_base.add(trailer_offset) as *const ImageTrailer);
+    assert!(trailer.magic == TRAILER_MAGIC, "malformed image trailer");
+
+    let digest = digest(kernel_base, trailer_offset);
+    assert!(
+        digest[..] == trailer.digest,
+        "image digest mismatch; refusing to boot"
+    );
+
+    if let Some(key) = PUBLIC_KEY {
+        assert!(
+            trailer.flags & ImageTrailer::FLAG_SIGNED != 0,
+            "unsigned Kernel Image; refusing to boot"
+        );
+
+        let key = VerifyingKey::from_bytes(&key).expect("invalid embedded public key");
+        let signature = Signature::from_bytes(&trailer.signature);
+        key.verify_strict(&trailer.digest, &signature)
+            .expect("image signature mismatch; refusing to boot");
+    }
+}
+
+/// Computes the digest of the first `size` bytes of the Kernel Image
+/// as they were written by the build system.
+///
+/// By now, we have relocated ourselves and cleared our .bss section,
+/// which both are part of the image. Their original contents are
+/// restored while hashing: relocated words hold their addend at link
+/// time, and .bss is all zeroes in the image.
+unsafe fn digest(kernel_base: *const u8, size: usize) -> [u8; 32] {
+    let image = core::slice::from_raw_parts(kernel_base, size);
+    let loader = addr_of!(__onyx_loader_start);
+    let relocations = core::slice::from_raw_parts(
+        addr_of!(__rela_start__),
+        addr_of!(__rela_end__).offset_from(addr_of!(__rela_start__)) as usize,
+    );
+
+    // Translate the regions we modified into offsets into the image.
+    let offset = |ptr: *const u8| (ptr.offset_from(kernel_base) as usize).min(size);
+    let loader_start = offset(loader);
+    let bss_start = offset(addr_of!(__bss_start__));
+    let bss_end = offset(addr_of!(__bss_end__));
+
+    let mut hasher = Sha256::new();
+    hasher.update(&image[..loader_start]);
+
+    let mut chunk = [0; CHUNK_SIZE];
+    for start in (loader_start..bss_start).step_by(CHUNK_SIZE) {
+        let end = (start + CHUNK_SIZE).min(bss_start);
+        let chunk = &mut chunk[..end - start];
+        chunk.copy_from_slice(&image[start..end]);
+
+        for rela in relocations.iter().filter(|r| r.info == R_RISCV_RELATIVE) {
+            let target = loader_start + rela.offset;
+            for (i, byte) in rela.addend.to_ne_bytes().into_iter().enumerate() {
+                if (start..end).contains(&(target + i)) {
+                    chunk[target + i - start] = byte;
+                }
+            }
+        }
+
+        hasher.update(chunk);
+    }
+
+    let zeroes = [0; CHUNK_SIZE];
+    for start in (bss_start..bss_end).step_by(CHUNK_SIZE) {
+        hasher.update(&zeroes[..(bss_end - start).min(CHUNK_SIZE)]);
+    }
+
+    hasher.update(&image[bss_end..]);
+    hasher.finalize().into()
+}
+
+const fn decode_key(hex: &[u8]) -> Option<[u8; 32]> {
+    // An empty key means that image signing is disabled.
+    if hex.is_empty() {
+        return None;
+    }
+    assert!(hex.len() == 64, "ONYX_PUBLIC_KEY must be 32 hex-encoded bytes");
+
+    let mut key = [0; 32];
+    let mut i = 0;
+    while i < key.len() {
+        key[i] = (decode_nibble(hex[2 * i]) << 4) | decode_nibble(hex[2 * i + 1]);
+        i += 1;
+    }
+
+    Some(key)
+}
+
+const fn decode_nibble(c: u8) -> u8 {
+    match c {
+        b'0'..=b'9' => c - b'0',
+        b'a'..=b'f' => c - b'a' + 10,
+        b'A'..=b'F' => c - b'A' + 10,
+        _ => panic!("invalid hex digit in ONYX_PUBLIC_KEY"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test_case]
+    fn decodes_hex_keys() {
+        let key = decode_key(b"00112233445566778899aabbccddeeffFFEEDDCCBBAA99887766554433221100");
+        let expected = [
+            0x00, 0x11, 0x22, 0x33, 0x44, 0x55, 0x66, 0x77, 0x88, 0x99, 0xAA, 0xBB, 0xCC, 0xDD,
+            0xEE, 0xFF, 0xFF, 0xEE, 0xDD, 0xCC, 0xBB, 0xAA, 0x99, 0x88, 0x77, 0x66, 0x55, 0x44,
+            0x33, 0x22, 0x11, 0x00,
+        ];
+        assert_eq!(key, Some(expected));
+    }
+
+    #[test_case]
+    fn empty_key_disables_signing() {
+        assert_eq!(decode_key(b""), None);
+    }
+}
@@ -0,0 +1,3 @@
+#[cfg(target_arch = "riscv64")]
+#[path = "arch/riscv64/r0.rs"]
+mod r0;
@@ -0,0 +1,15 @@
+use core::mem::offset_of;
+
+use onyx_image::StubHeader;
+
+core::arch::global_asm!(
+    include_str!("r0/start.s"),
+    FORMAT_OFFSET = const offset_of!(StubHeader, format),
+    PAYLOAD_OFFSET_OFFSET = const offset_of!(StubHeader, payload_offset),
+    PAYLOAD_SIZE_OFFSET = const offset_of!(StubHeader, payload_size),
+    IMAGE_SIZE_OFFSET = const offset_of!(StubHeader, image_size),
+    IMAGE_OFFSET_OFFSET = const offset_of!(StubHeader, image_offset),
+    END_OFFSET = const offset_of!(StubHeader, end),
+    HEADER_SIZE = const StubHeader::SIZE,
+    FORMAT_NONE = const StubHeader::FORMAT_NONE,
+);
@@ -0,0 +1,132 @@
+// Global entrypoint to a compressed Kernel Image. The stub
+// extracts the image behind itself and transfers control to it.
+.section .r0.text.start, "ax", %progbits
+.global __onyx_stub_start
+__onyx_stub_start:
+    j __onyx_stub_entry
+
+// Places a StubHeader field at the given offset from the magic.
+.macro HEADER_FIELD label, offset
+    .org __onyx_stub_header + \offset
+\label:
+.endm
+
+// The StubHeader structure, as defined by the `onyx-image` crate.
+// The build system fills in all fields but `end` when appending the
+// compressed image to the stub. Each field is placed at its offset
+// from the Rust definition, so the assembler fails when a preceding
+// field grows beyond it.
+.balign 8
+__onyx_stub_header:
+    .ascii "STUB"
+HEADER_FIELD __onyx_stub_format, {FORMAT_OFFSET}
+    .word {FORMAT_NONE}
+HEADER_FIELD __onyx_stub_payload_offset, {PAYLOAD_OFFSET_OFFSET}
+    .quad 0x0000000000000000
+HEADER_FIELD __onyx_stub_payload_size, {PAYLOAD_SIZE_OFFSET}
+    .quad 0x0000000000000000
+HEADER_FIELD __onyx_stub_image_size, {IMAGE_SIZE_OFFSET}
+    .quad 0x0000000000000000
+HEADER_FIELD __onyx_stub_image_offset, {IMAGE_OFFSET_OFFSET}
+    .quad 0x0000000000000000
+HEADER_FIELD __onyx_stub_end, {END_OFFSET}
+    .quad __end__ - __onyx_stub_start
+HEADER_FIELD __onyx_stub_header_end, {HEADER_SIZE}
+
+// Loads the absolute address of a label into a register given
+// a register containing a relative base address. Using this
+// ensures that the stub can execute from anywhere in memory.
+.macro LOAD_LABEL_ADDR reg, base, symbol
+    lla \reg, \symbol
+    ld \reg, 0(\reg)
+    add \reg, \base, \reg
+.endm
+
+//
+// fn __onyx_stub_entry(hart_id: usize, dtb: *const u8) -> !
+//
+.section .r0.text, "ax", %progbits
+.global __onyx_stub_entry
+.type __onyx_stub_entry, %function
+__onyx_stub_entry:
+    // Stash away boot arguments for the kernel.
+    mv s0, a0
+    mv s1, a1
+
+    lla t0, __onyx_stub_start
+
+    // Apply relative relocations to ourselves before entering Rust.
+    // Walk the dynamic section to find the RELA table first.
+    LOAD_LABEL_ADDR t1, t0, __onyx_stub_dynamic_start
+    li t2, 0 // DT_RELA
+    li t3, 0 // DT_RELASZ
+3:
+    ld t4, 0(t1)
+    beqz t4, 6f
+    ld t5, 8(t1)
+    li t6, 7
+    bne t4, t6, 4f
+    add t2, t0, t5
+4:
+    li t6, 8
+    bne t4, t6, 5f
+    mv t3, t5
+5:
+    addi t1, t1, 16
+    j 3b
+
+    // Now apply every R_RISCV_RELATIVE entry in the table.
+6:
+    add t3, t2, t3
+7:
+    bgeu t2, t3, 9f
+    ld t4, 8(t2)
+    li t6, 3
+    bne t4, t6, 8f
+    ld t4, 0(t2)
+    ld t5, 16(t2)
+    add t4, t0, t4
+    add t5, t0, t5
+    sd t5, 0(t4)
+8:
+    addi t2, t2, 24
+    j 7b
+
+9:
+    LOAD_LABEL_ADDR t1, t0, __onyx_stub_bss_start
+    LOAD_LABEL_ADDR t2, t0, __onyx_stub_bss_end
+
+    // Clear every double word in .bss section.
+0:
+    bgeu t1, t2, 1f
+    sd zero, 0(t1)
+    addi t1, t1, 8
+    j 0b
+
+1:
+    // Set the stack pointer to the end of the initialized .bss section.
+    LOAD_LABEL_ADDR sp, t0, __onyx_stub_stack_top
+
+    // Extract the image; its entrypoint will be returned in a0.
+    mv a0, t0
+    lla a1, __onyx_stub_header
+    call main
+
+    // Make the freshly written code visible to instruction fetch
+    // and enter the kernel with the original boot arguments.
+    fence.i
+    mv t0, a0
+    mv a0, s0
+    mv a1, s1
+    jr t0
+
+
+.balign 8
+__onyx_stub_stack_top:
+    .quad __stack_top__ - __onyx_stub_start
+__onyx_stub_bss_start:
+    .quad __bss_start__ - __onyx_stub_start
+__onyx_stub_bss_end:
+    .quad __bss_end__   - __onyx_stub_start
+__onyx_stub_dynamic_start:
+    .quad _DYNAMIC      - __onyx_stub_start
@@ -0,0 +1,94 @@
+//! Decoders for the supported compression formats.
+//!
+//! Only the decoder selected through the crate features is built
+//! into the stub to keep it as small as possible.
+
+#[cfg(any(feature = "gzip", feature = "lz4", feature = "zstd"))]
+use onyx_image::StubHeader;
+
+/// Decompresses `payload` in the given format into `image`, which
+/// must be sized to fit the decompressed data exactly.
+#[cfg_attr(
+    not(any(feature = "gzip", feature = "lz4", feature = "zstd")),
+    allow(unused_variables)
+)]
+pub fn decompress(format: u32, payload: &[u8], image: &mut [u8]) {
+    match format {
+        #[cfg(feature = "gzip")]
+        StubHeader::FORMAT_GZIP => gzip(payload, image),
+        #[cfg(feature = "lz4")]
+        StubHeader::FORMAT_LZ4 => lz4(payload, image),
+        #[cfg(feature = "zstd")]
+        StubHeader::FORMAT_ZSTD => zstd(payload, image),
+        _ => panic!("unsupported compression format {format}"),
+    }
+}
+
+#[cfg(feature = "gzip")]
+fn gzip(payload: &[u8], image: &mut [u8]) {
+    use miniz_oxide::inflate::{
+        core::{decompress, inflate_flags, DecompressorOxide},
+        TINFLStatus,
+    };
+
+    const FHCRC: u8 = 1 << 1;
+    const FEXTRA: u8 = 1 << 2;
+    const FNAME: u8 = 1 << 3;
+    const FCOMMENT: u8 = 1 << 4;
+
+    assert!(
+        payload.len() >= 10 && payload[..3] == [0x1F, 0x8B, 8],
+        "malformed gzip header"
+    );
+
+    // Skip the gzip header to get to the raw deflate stream.
+    let flags = payload[3];
+    let mut pos = 10;
+    if flags & FEXTRA != 0 {
+        pos += 2 + u16::from_le_bytes([payload[pos], payload[pos + 1]]) as usize;
+    }
+    for flag in [FNAME, FCOMMENT] {
+        if flags & flag != 0 {
+            pos += payload[pos..].iter().position(|&b| b == 0).unwrap() + 1;
+        }
+    }
+    if flags & FHCRC != 0 {
+        pos += 2;
+    }
+
+    let mut decompressor = DecompressorOxide::new();
+    let (status, _, written) = decompress(
+        &mut decompressor,
+        &payload[pos..],
+        image,
+        0,
+        inflate_flags::TINFL_FLAG_USING_NON_WRAPPING_OUTPUT_BUF,
+    );
+    assert!(
+        status == TINFLStatus::Done && written == image.len(),
+        "corrupted gzip payload"
+    );
+}
+
+#[cfg(feature = "lz4")]
+fn lz4(payload: &[u8], image: &mut [u8]) {
+    let written = lz4_flex::block::decompress_into(payload, image).expect("corrupted LZ4 payload");
+    assert!(written == image.len(), "corrupted LZ4 payload");
+}
+
+#[cfg(feature = "zstd")]
+fn zstd(payload: &[u8], image: &mut [u8]) {
+    use ruzstd::{io::Read, StreamingDecoder};
+
+    let mut decoder = StreamingDecoder::new(payload).expect("corrupted zstd payload");
+
+    let mut written = 0;
+    while written < image.len() {
+        match decoder.read(&mut image[written..]) {
+            Ok(0) => break,
+            Ok(n) => written += n,
+            Err(_) => panic!("corrupted zstd payload"),
+        }
+    }
+    assert!(written == image.len(), "corrupted zstd payload");
+}
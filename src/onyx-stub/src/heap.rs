@@ -0,0 +1,43 @@
+//! A bump allocator for decoders which depend on `alloc`.
+//!
+//! Memory is never freed; the stub is short-lived and hands over
+//! all of it to the kernel once the image is extracted.
+
+use core::{
+    alloc::{GlobalAlloc, Layout},
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+#[global_allocator]
+static HEAP: BumpAllocator = BumpAllocator {
+    next: AtomicUsize::new(0),
+};
+
+struct BumpAllocator {
+    next: AtomicUsize,
+}
+
+/// Initializes the heap to grow upwards from the given address.
+pub fn init(start: *mut u8) {
+    HEAP.next.store(start as usize, Ordering::Relaxed);
+}
+
+unsafe impl GlobalAlloc for BumpAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let mut next = self.next.load(Ordering::Relaxed);
+        loop {
+            let start = (next + layout.align() - 1) & !(layout.align() - 1);
+            match self.next.compare_exchange_weak(
+                next,
+                start + layout.size(),
+                Ordering::Relaxed,
+                Ordering::Relaxed,
+            ) {
+                Ok(_) => return start as *mut u8,
+                Err(current) => next = current,
+            }
+        }
+    }
+
+    unsafe fn dealloc(&self, _ptr: *mut u8, _layout: Layout) {}
+}
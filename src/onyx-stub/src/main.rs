@@ -0,0 +1,47 @@
+#![no_std]
+#![no_main]
+#![feature(asm_const, offset_of)]
+
+#[cfg(any(feature = "lz4", feature = "zstd"))]
+extern crate alloc;
+
+use core::{panic::PanicInfo, slice};
+
+use onyx_image::StubHeader;
+
+mod arch;
+
+mod decompress;
+
+#[cfg(any(feature = "lz4", feature = "zstd"))]
+mod heap;
+
+#[panic_handler]
+fn panic(_info: &PanicInfo<'_>) -> ! {
+    loop {
+        core::hint::spin_loop();
+    }
+}
+
+/// Extracts the Kernel Image to the page boundary behind the compressed
+/// payload chosen by the build system and returns a pointer to its
+/// entrypoint.
+#[no_mangle]
+extern "C" fn main(base: *mut u8, header: &StubHeader) -> *const u8 {
+    let payload_offset = header.payload_offset as usize;
+    let payload_size = header.payload_size as usize;
+    let image_offset = header.image_offset as usize;
+
+    // SAFETY: The build system places the payload behind the end of
+    // the stub and the image gets extracted into free memory behind it.
+    unsafe {
+        let payload = slice::from_raw_parts(base.add(payload_offset), payload_size);
+        let image = slice::from_raw_parts_mut(base.add(image_offset), header.image_size as usize);
+
+        #[cfg(any(feature = "lz4", feature = "zstd"))]
+        heap::init(image.as_mut_ptr_range().end);
+
+        decompress::decompress(header.format, payload, image);
+        image.as_ptr()
+    }
+}
@@ -0,0 +1,182 @@
+//! An in-kernel test harness built on `custom_test_frameworks`.
+//!
+//! Crates opt in with the following attributes at their root and call
+//! `test_main` once their environment is set up:
+//!
+//! ```ignore
+//! #![cfg_attr(test, feature(custom_test_frameworks))]
+//! #![cfg_attr(test, test_runner(onyx_test::runner))]
+//! #![cfg_attr(test, reexport_test_harness_main = "test_main")]
+//! ```
+//!
+//! Their panic handler must forward to [`panicked`] in test builds.
+//!
+//! # Protocol
+//!
+//! Progress is reported on the early console of the board, one line
+//! per event, for `xtask test` to parse:
+//!
+//! ```text
+//! TEST RUN <count>
+//! TEST CASE <name>
+//! TEST OK <name>
+//! TEST FAILED <name>
+//! TEST PASS
+//! TEST FAIL
+//! ```
+//!
+//! Every test is announced with `TEST CASE` and concluded with either
+//! `TEST OK` or `TEST FAILED`. Panic messages appear in between. The
+//! run ends with `TEST PASS` or `TEST FAIL`, after which the machine
+//! is shut down through the board, signalling failure where it can.
+//!
+//! # Isolation
+//!
+//! There is no unwinding, so a panicking test abandons its stack and
+//! the runner resumes with the next test on a fresh one. Any state the
+//! test left behind, such as held locks, is leaked into later tests.
+
+#![no_std]
+
+use core::{
+    any,
+    fmt::{self, Write},
+    panic::PanicInfo,
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+use onyx_board::{Board, Console, CurrentBoard};
+
+/// A single test case.
+pub trait Testable {
+    /// Gets the fully qualified name of the test.
+    fn name(&self) -> &'static str;
+
+    /// Runs the test, panicking on failure.
+    fn run(&self);
+}
+
+impl<T: Fn()> Testable for T {
+    fn name(&self) -> &'static str {
+        any::type_name::<T>()
+    }
+
+    fn run(&self) {
+        self()
+    }
+}
+
+/// Marks that no test is currently running.
+const IDLE: usize = usize::MAX;
+
+static mut TESTS: &[&dyn Testable] = &[];
+
+/// The index of the running test, or [`IDLE`].
+static CURRENT: AtomicUsize = AtomicUsize::new(IDLE);
+/// The index of the test to resume with after a panic.
+static NEXT: AtomicUsize = AtomicUsize::new(0);
+static FAILED: AtomicUsize = AtomicUsize::new(0);
+static RUNNER_SP: AtomicUsize = AtomicUsize::new(0);
+
+/// Runs all tests, reports the results and shuts the machine down.
+pub fn runner(tests: &'static [&'static dyn Testable]) -> ! {
+    // SAFETY: The harness runs on a single hart, and this is written
+    // once before any test starts.
+    unsafe { TESTS = tests };
+
+    let mut out = Writer(CurrentBoard::console());
+    let _ = writeln!(out, "TEST RUN {}", tests.len());
+
+    RUNNER_SP.store(stack_pointer(), Ordering::Relaxed);
+    run_from(0)
+}
+
+/// Reports a panic and resumes with the next test, if a test was
+/// running.
+///
+/// Panics outside of tests fail the run immediately.
+pub fn panicked(info: &PanicInfo<'_>) -> ! {
+    let mut out = Writer(CurrentBoard::console());
+    let _ = writeln!(out, "{info}");
+
+    let current = CURRENT.swap(IDLE, Ordering::Relaxed);
+    if current == IDLE {
+        exit(false);
+    }
+
+    // SAFETY: `TESTS` is no longer written to.
+    let test = unsafe { TESTS[current] };
+    let _ = writeln!(out, "TEST FAILED {}", test.name());
+    FAILED.fetch_add(1, Ordering::Relaxed);
+
+    // Abandon the stack of the failed test and continue after it.
+    // SAFETY: Nothing on the stack above the runner is used anymore.
+    unsafe { switch_stack(RUNNER_SP.load(Ordering::Relaxed), resume) }
+}
+
+extern "C" fn resume() -> ! {
+    run_from(NEXT.load(Ordering::Relaxed))
+}
+
+fn run_from(start: usize) -> ! {
+    let mut out = Writer(CurrentBoard::console());
+
+    // SAFETY: `TESTS` is no longer written to.
+    let tests = unsafe { TESTS };
+    for (i, test) in tests.iter().enumerate().skip(start) {
+        NEXT.store(i + 1, Ordering::Relaxed);
+        let _ = writeln!(out, "TEST CASE {}", test.name());
+
+        CURRENT.store(i, Ordering::Relaxed);
+        test.run();
+        CURRENT.store(IDLE, Ordering::Relaxed);
+
+        let _ = writeln!(out, "TEST OK {}", test.name());
+    }
+
+    exit(FAILED.load(Ordering::Relaxed) == 0)
+}
+
+fn exit(success: bool) -> ! {
+    let mut console = CurrentBoard::console();
+    if success {
+        console.write_str("TEST PASS\n");
+    } else {
+        console.write_str("TEST FAIL\n");
+    }
+
+    CurrentBoard::shutdown(!success)
+}
+
+#[inline(always)]
+fn stack_pointer() -> usize {
+    let sp: usize;
+    // SAFETY: Reading the stack pointer has no side effects.
+    unsafe { core::arch::asm!("mv {}, sp", out(reg) sp, options(nomem, nostack)) };
+    sp
+}
+
+/// Continues execution in `f` on the stack at `sp`.
+///
+/// # Safety
+///
+/// `sp` must be a suitably aligned stack pointer whose memory below
+/// is free for use.
+unsafe fn switch_stack(sp: usize, f: extern "C" fn() -> !) -> ! {
+    core::arch::asm!(
+        "mv sp, {sp}",
+        "jr {f}",
+        sp = in(reg) sp,
+        f = in(reg) f,
+        options(noreturn),
+    )
+}
+
+struct Writer<C>(C);
+
+impl<C: Console> fmt::Write for Writer<C> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        self.0.write_str(s);
+        Ok(())
+    }
+}
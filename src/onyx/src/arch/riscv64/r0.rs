@@ -1 +1,24 @@
-core::arch::global_asm!(include_str!("r0/start.s"));
+use core::mem::offset_of;
+
+use onyx_image::KernelMeta;
+
+core::arch::global_asm!(
+    include_str!("r0/start.s"),
+    VERSION_OFFSET = const offset_of!(KernelMeta, version),
+    REVISION_OFFSET = const offset_of!(KernelMeta, revision),
+    HEADER_SIZE_OFFSET = const offset_of!(KernelMeta, header_size),
+    KIP1_BASE_OFFSET = const offset_of!(KernelMeta, kip1_base),
+    LOADER_BASE_OFFSET = const offset_of!(KernelMeta, loader_base),
+    LOADER_SIZE_OFFSET = const offset_of!(KernelMeta, loader_size),
+    KERNEL_SIZE_OFFSET = const offset_of!(KernelMeta, kernel_size),
+    TRAILER_BASE_OFFSET = const offset_of!(KernelMeta, trailer_base),
+    BUILD_ID_BASE_OFFSET = const offset_of!(KernelMeta, build_id_base),
+    DTB_BASE_OFFSET = const offset_of!(KernelMeta, dtb_base),
+    DTB_SIZE_OFFSET = const offset_of!(KernelMeta, dtb_size),
+    CMDLINE_BASE_OFFSET = const offset_of!(KernelMeta, cmdline_base),
+    CMDLINE_SIZE_OFFSET = const offset_of!(KernelMeta, cmdline_size),
+    LOADER_END_OFFSET = const offset_of!(KernelMeta, loader_end),
+    LAYOUT_OFFSET = const offset_of!(KernelMeta, layout),
+    META_SIZE = const KernelMeta::SIZE,
+    REVISION = const KernelMeta::REVISION,
+);
@@ -3,20 +3,40 @@
 .section .r0.text.start, "ax", %progbits
 .global __onyx_start
 __onyx_start:
+    // Disable linker relaxation so that offsets into the metadata
+    // below are known at assembly time.
+    .option push
+    .option norelax
     j __onyx_bootstrap_kernel
 
-// The Onyx KernelMeta structure. See `xbuild` for details.
-// Make sure that the structure layout always matches the one
-// found in both the build script and the `onyx-loader` crate.
+// Places a KernelMeta field at the given offset from the magic.
+.macro META_FIELD label, offset
+    .org __onyx_magic + \offset
+\label:
+.endm
+
+// The Onyx KernelMeta structure, as defined by the `onyx-image`
+// crate. The build script locates every field by its label below.
+// Each field is placed at its offset from the Rust definition, so
+// the assembler fails when a preceding field grows beyond it.
+.balign 8
 __onyx_magic:
     .ascii "ONYX"
-__onyx_kip1_base:
+META_FIELD __onyx_version, {VERSION_OFFSET}
+    .word 0xFFFFFFFF
+META_FIELD __onyx_revision, {REVISION_OFFSET}
+    .word {REVISION}
+META_FIELD __onyx_header_size, {HEADER_SIZE_OFFSET}
+    .word {META_SIZE}
+META_FIELD __onyx_kip1_base, {KIP1_BASE_OFFSET}
     .quad 0x0000000000000000
-__onyx_kernel_loader_base:
+META_FIELD __onyx_kernel_loader_base, {LOADER_BASE_OFFSET}
     .quad 0x0000000000000000
-__onyx_version:
-    .word 0xFFFFFFFF
-__onyx_kernel_layout:
+META_FIELD __onyx_kernel_loader_size, {LOADER_SIZE_OFFSET}
+    .quad 0x0000000000000000
+META_FIELD __onyx_kernel_size, {KERNEL_SIZE_OFFSET}
+    .quad 0x0000000000000000
+META_FIELD __onyx_kernel_layout, {LAYOUT_OFFSET}
     .word __onyx_start     - __onyx_start  // text_start
     .word __text_end__     - __onyx_start  // text_end
     .word __rodata_start__ - __onyx_start  // rodata_start
@@ -27,6 +47,22 @@ __onyx_kernel_layout:
     .word __bss_end__      - __onyx_start  // bss_end
     .word __end__          - __onyx_start  // kernel_end
     .word _DYNAMIC         - __onyx_start  // dynamic_start
+META_FIELD __onyx_trailer_base, {TRAILER_BASE_OFFSET}
+    .quad 0x0000000000000000
+META_FIELD __onyx_build_id_base, {BUILD_ID_BASE_OFFSET}
+    .quad 0x0000000000000000
+META_FIELD __onyx_dtb_base, {DTB_BASE_OFFSET}
+    .quad 0x0000000000000000
+META_FIELD __onyx_dtb_size, {DTB_SIZE_OFFSET}
+    .quad 0x0000000000000000
+META_FIELD __onyx_cmdline_base, {CMDLINE_BASE_OFFSET}
+    .quad 0x0000000000000000
+META_FIELD __onyx_cmdline_size, {CMDLINE_SIZE_OFFSET}
+    .quad 0x0000000000000000
+META_FIELD __onyx_kernel_loader_end, {LOADER_END_OFFSET}
+    .quad 0x0000000000000000
+META_FIELD __onyx_kernel_meta_end, {META_SIZE}
+    .option pop
 
 // Loads the absolute address of a label into a register given
 // a register containing a relative base address. Using this
@@ -66,12 +102,91 @@ __onyx_bootstrap_kernel:
     // it with the following arguments:
     //
     //   - a0: The kernel base address in memory.
-    //   - a1: A pointer to the KernelLayout structure.
+    //   - a1: A pointer to the KernelMeta structure.
     //   - a2: A pointer to the embedded KIP1 list.
+    //   - a3: The device tree blob passed by firmware, if any.
     //
-    // Loader state will be returned in a0 for us to re-use.
+    // The device tree to boot with will be returned in a0.
     lla a0, __onyx_start
-    lla a1, __onyx_kernel_layout
+    lla a1, __onyx_magic
     LOAD_LABEL_ADDR a2, a0, __onyx_kip1_base
+    mv a3, s1
     LOAD_LABEL_ADDR t0, a0, __onyx_kernel_loader_base
     jalr ra, t0, 0
+    mv s1, a0
+
+    lla t0, __onyx_start
+
+    // Apply relative relocations to ourselves before entering Rust.
+    // Walk the dynamic section to find the RELA table first.
+    LOAD_LABEL_ADDR t1, t0, __onyx_kernel_dynamic_start
+    li t2, 0 // DT_RELA
+    li t3, 0 // DT_RELASZ
+3:
+    ld t4, 0(t1)
+    beqz t4, 6f
+    ld t5, 8(t1)
+    li t6, 7
+    bne t4, t6, 4f
+    add t2, t0, t5
+4:
+    li t6, 8
+    bne t4, t6, 5f
+    mv t3, t5
+5:
+    addi t1, t1, 16
+    j 3b
+
+    // Now apply every R_RISCV_RELATIVE entry in the table.
+6:
+    add t3, t2, t3
+7:
+    bgeu t2, t3, 9f
+    ld t4, 8(t2)
+    li t6, 3
+    bne t4, t6, 8f
+    ld t4, 0(t2)
+    ld t5, 16(t2)
+    add t4, t0, t4
+    add t5, t0, t5
+    sd t5, 0(t4)
+8:
+    addi t2, t2, 24
+    j 7b
+
+9:
+    // Clear every double word in the .bss section, which also holds
+    // the boot stack.
+    LOAD_LABEL_ADDR t1, t0, __onyx_kernel_bss_start
+    LOAD_LABEL_ADDR t2, t0, __onyx_kernel_bss_end
+0:
+    bgeu t1, t2, 1f
+    sd zero, 0(t1)
+    addi t1, t1, 8
+    j 0b
+
+1:
+    // Set up the boot stack and enter Rust with the kernel base
+    // address, a pointer to the KernelMeta structure and the
+    // device tree blob selected by the loader.
+    mv a0, t0
+    LOAD_LABEL_ADDR sp, a0, __onyx_kernel_stack_top
+    lla a1, __onyx_magic
+    mv a2, s1
+    call main
+
+    // main never returns, but make sure we don't run off.
+0:
+    wfi
+    j 0b
+
+
+.balign 8
+__onyx_kernel_stack_top:
+    .quad __stack_top__ - __onyx_start
+__onyx_kernel_bss_start:
+    .quad __bss_start__ - __onyx_start
+__onyx_kernel_bss_end:
+    .quad __bss_end__   - __onyx_start
+__onyx_kernel_dynamic_start:
+    .quad _DYNAMIC      - __onyx_start
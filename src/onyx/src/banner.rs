@@ -0,0 +1,129 @@
+//! The boot banner identifying the running kernel build.
+//!
+//! This is written to the early console of the board and avoids
+//! `core::fmt` to keep the early boot path small.
+
+use onyx_board::{Board, Console, CurrentBoard};
+use onyx_image::{BuildId, KernelMeta, BUILD_ID_MAGIC};
+
+use crate::cmdline::{self, EarlyParams, LogLevel};
+
+/// Prints the boot banner with the version and build ID of the kernel.
+///
+/// The build time only appears for images built with timestamps. The
+/// command line is echoed as well with `loglevel=debug` or above.
+///
+/// # Safety
+///
+/// `kernel_base` must point to the start of the Kernel Image that
+/// `meta` belongs to.
+pub unsafe fn print(kernel_base: *const u8, meta: &KernelMeta) {
+    let mut out = Writer(CurrentBoard::console());
+
+    out.write_str("Onyx ");
+    out.write_dec((meta.version >> 24) as u64);
+    out.write_str(".");
+    out.write_dec(((meta.version >> 16) & 0xFF) as u64);
+    out.write_str(".");
+    out.write_dec(((meta.version >> 8) & 0xFF) as u64);
+
+    let build_id = meta
+        .build_id_base()
+        .map(|base| &*(kernel_base.add(base as usize) as *const BuildId))
+        .filter(|id| id.magic == BUILD_ID_MAGIC);
+    if let Some(id) = build_id {
+        out.write_str(" (");
+        match id.commit().get(..12) {
+            Some(commit) => out.write_str(commit),
+            None => out.write_str("unknown"),
+        }
+        if id.flags & BuildId::FLAG_DIRTY != 0 {
+            out.write_str("-dirty");
+        }
+        out.write_str(", ");
+        out.write_str(id.profile());
+        // Reproducible builds record no build time.
+        if id.timestamp != 0 {
+            out.write_str(", built ");
+            out.write_date(id.timestamp);
+        }
+        out.write_str(")");
+    }
+    out.write_str(" on ");
+    out.write_str(CurrentBoard::NAME);
+
+    out.write_str("\n");
+
+    let cmdline = cmdline::get();
+    let params = EarlyParams::parse(&cmdline);
+    if params.loglevel >= LogLevel::Debug && !cmdline.as_str().is_empty() {
+        out.write_str("Command line: ");
+        out.write_str(cmdline.as_str());
+        out.write_str("\n");
+    }
+}
+
+struct Writer<C>(C);
+
+impl<C: Console> Writer<C> {
+    fn write_byte(&mut self, byte: u8) {
+        self.0.write_byte(byte);
+    }
+
+    fn write_str(&mut self, s: &str) {
+        self.0.write_str(s);
+    }
+
+    fn write_dec(&mut self, mut value: u64) {
+        let mut digits = [0; 20];
+        let mut len = 0;
+        loop {
+            digits[len] = b'0' + (value % 10) as u8;
+            len += 1;
+            value /= 10;
+
+            if value == 0 {
+                break;
+            }
+        }
+
+        digits[..len].iter().rev().for_each(|&d| self.write_byte(d));
+    }
+
+    fn write_padded(&mut self, value: u64) {
+        if value < 10 {
+            self.write_byte(b'0');
+        }
+        self.write_dec(value);
+    }
+
+    /// Writes a Unix timestamp as a UTC date and time.
+    fn write_date(&mut self, timestamp: u64) {
+        let (days, secs) = (timestamp / 86400, timestamp % 86400);
+
+        // Convert days since the epoch to a civil date.
+        // See http://howardhinnant.github.io/date_algorithms.html
+        let z = days + 719_468;
+        let era = z / 146_097;
+        let doe = z % 146_097;
+        let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146_096) / 365;
+        let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+        let mp = (5 * doy + 2) / 153;
+        let day = doy - (153 * mp + 2) / 5 + 1;
+        let month = if mp < 10 { mp + 3 } else { mp - 9 };
+        let year = yoe + era * 400 + (month <= 2) as u64;
+
+        self.write_dec(year);
+        self.write_str("-");
+        self.write_padded(month);
+        self.write_str("-");
+        self.write_padded(day);
+        self.write_str(" ");
+        self.write_padded(secs / 3600);
+        self.write_str(":");
+        self.write_padded(secs / 60 % 60);
+        self.write_str(":");
+        self.write_padded(secs % 60);
+        self.write_str(" UTC");
+    }
+}
@@ -0,0 +1,60 @@
+//! The command line the kernel was booted with.
+//!
+//! Parsing is implemented in [`onyx_core::cmdline`] so it can be
+//! tested on the host.
+
+use core::{
+    ffi::CStr,
+    slice, str,
+    sync::atomic::{AtomicPtr, AtomicUsize, Ordering},
+};
+
+use onyx_core::fdt::Fdt;
+use onyx_image::KernelMeta;
+
+pub use onyx_core::cmdline::{CommandLine, EarlyParams, LogLevel};
+
+static CMDLINE_PTR: AtomicPtr<u8> = AtomicPtr::new(core::ptr::null_mut());
+static CMDLINE_LEN: AtomicUsize = AtomicUsize::new(0);
+
+/// Selects the command line to boot with and makes it available
+/// through [`get`].
+///
+/// A non-empty `/chosen/bootargs` property in the device tree takes
+/// precedence over the command line embedded into the image.
+///
+/// # Safety
+///
+/// `kernel_base` must point to the start of the Kernel Image that
+/// `meta` belongs to, and `dtb` must be the device tree blob handed
+/// over by the Kernel Loader.
+pub unsafe fn init(kernel_base: *const u8, meta: &KernelMeta, dtb: *const u8) {
+    let bootargs = Fdt::from_ptr(dtb)
+        .and_then(|fdt| fdt.property_str("chosen", "bootargs"))
+        .filter(|args| !args.trim().is_empty());
+
+    // The embedded command line is ignored unless it is terminated
+    // right at its end and free of interior NULs.
+    let embedded = meta.cmdline().and_then(|(base, size)| {
+        let bytes = slice::from_raw_parts(kernel_base.add(base as usize), size as usize + 1);
+        CStr::from_bytes_with_nul(bytes).ok()?.to_str().ok()
+    });
+
+    let cmdline = bootargs.or(embedded).unwrap_or_default();
+    CMDLINE_PTR.store(cmdline.as_ptr().cast_mut(), Ordering::Relaxed);
+    CMDLINE_LEN.store(cmdline.len(), Ordering::Release);
+}
+
+/// Gets the command line the kernel was booted with.
+///
+/// This is empty before [`init`] was called.
+pub fn get() -> CommandLine<'static> {
+    let len = CMDLINE_LEN.load(Ordering::Acquire);
+    let ptr = CMDLINE_PTR.load(Ordering::Relaxed);
+    if len == 0 {
+        return CommandLine::new("");
+    }
+
+    // SAFETY: `init` stored a `&'static str` which was validated as UTF-8.
+    CommandLine::new(unsafe { str::from_utf8_unchecked(slice::from_raw_parts(ptr, len)) })
+}
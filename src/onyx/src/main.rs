@@ -1,11 +1,74 @@
 #![no_std]
 #![no_main]
+#![feature(asm_const, offset_of)]
+#![cfg_attr(test, feature(custom_test_frameworks))]
+#![cfg_attr(test, test_runner(onyx_test::runner))]
+#![cfg_attr(test, reexport_test_harness_main = "test_main")]
+
+use onyx_image::KernelMeta;
 
 mod arch;
 
+mod banner;
+
+mod cmdline;
+
+mod testing;
+
+#[cfg(test)]
+#[panic_handler]
+fn panic(info: &core::panic::PanicInfo<'_>) -> ! {
+    onyx_test::panicked(info)
+}
+
+#[cfg(not(test))]
 #[panic_handler]
 fn panic(_info: &core::panic::PanicInfo<'_>) -> ! {
-    loop {}
+    if testing::enabled() {
+        testing::exit(testing::Outcome::Fail);
+    }
+
+    loop {
+        core::hint::spin_loop();
+    }
 }
 
-fn main() {}
+#[no_mangle]
+extern "C" fn main(kernel_base: *const u8, kernel_meta: &KernelMeta, dtb: *const u8) -> ! {
+    // SAFETY: The image is still laid out in memory as it was loaded,
+    // and `dtb` is the blob that was selected by the Kernel Loader.
+    unsafe {
+        cmdline::init(kernel_base, kernel_meta, dtb);
+        banner::print(kernel_base, kernel_meta);
+    }
+
+    #[cfg(test)]
+    test_main();
+
+    if testing::enabled() {
+        testing::exit(testing::Outcome::Pass);
+    }
+
+    loop {
+        core::hint::spin_loop();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use onyx_board::{Board, CurrentBoard, Timer};
+
+    #[test_case]
+    fn timer_advances() {
+        let mut timer = CurrentBoard::timer();
+        assert!(timer.frequency() > 0);
+
+        let start = timer.now();
+        while timer.now() == start {
+            core::hint::spin_loop();
+        }
+
+        // Timer interrupts are disabled, so this only exercises SBI.
+        timer.set_deadline(u64::MAX);
+    }
+}
@@ -0,0 +1,33 @@
+//! Support for automated boot tests under `xtask test`.
+//!
+//! When booted with the `test` parameter, the kernel reports its
+//! outcome on the early console as a line of either `TEST PASS` or
+//! `TEST FAIL` and then shuts the machine down, so that QEMU exits on
+//! its own. Boards with a test device make its exit status match.
+
+use onyx_board::{Board, Console, CurrentBoard};
+
+use crate::cmdline::{self, EarlyParams};
+
+/// The outcome of a test run.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Outcome {
+    Pass,
+    Fail,
+}
+
+/// Whether the kernel was booted for a test run.
+pub fn enabled() -> bool {
+    EarlyParams::parse(&cmdline::get()).test
+}
+
+/// Reports the outcome of the test run and shuts the machine down.
+pub fn exit(outcome: Outcome) -> ! {
+    let mut console = CurrentBoard::console();
+    match outcome {
+        Outcome::Pass => console.write_str("TEST PASS\n"),
+        Outcome::Fail => console.write_str("TEST FAIL\n"),
+    }
+
+    CurrentBoard::shutdown(outcome == Outcome::Fail)
+}